@@ -2,107 +2,118 @@ use anyhow::{Context, Result};
 use std::io::Read;
 use std::process::{Command, Stdio};
 
-use crate::config::{DemonConfig, Job};
+use crate::config::{self, DemonConfig, Job, PathsConfig};
 use crate::daemon;
 use crate::gateway;
+use crate::history::{self, RunTrigger};
 use crate::logging;
 use crate::scheduler;
+use crate::store;
 use crate::task;
+use crate::template::TemplateContext;
+use crate::usage::{self, Scope, Totals, UsageEntry};
 
-pub async fn start(with_gateway: bool, foreground: bool) -> Result<()> {
-    let config = DemonConfig::load()?;
+pub async fn start(home: &PathsConfig, with_gateway: bool, foreground: bool) -> Result<()> {
+    let config = DemonConfig::load(home)?;
 
-    if daemon::is_running()? {
-        println!("Demon is already running (PID: {})", daemon::read_pid()?);
+    if daemon::is_running(&config.paths)? {
+        println!("Demon is already running (PID: {})", daemon::read_pid(&config.paths)?);
         return Ok(());
     }
 
     if foreground {
         println!("Starting demon in foreground...");
-        run_foreground(config, with_gateway).await
+        run_foreground(home, config, with_gateway).await
     } else {
         println!("Starting demon daemon...");
-        daemon::daemonize(with_gateway)?;
+        daemon::daemonize(home, config, with_gateway)?;
         println!("Demon started successfully");
         Ok(())
     }
 }
 
-async fn run_foreground(config: DemonConfig, with_gateway: bool) -> Result<()> {
-    let _guard = logging::init_foreground_logging()?;
+async fn run_foreground(home: &PathsConfig, config: DemonConfig, with_gateway: bool) -> Result<()> {
+    let _guard = logging::init_foreground_logging(&config.paths)?;
 
     tracing::info!(component = "daemon", "Demon starting in foreground mode");
 
-    let scheduler_handle = tokio::spawn({
-        let config = config.clone();
-        async move {
-            if let Err(e) = scheduler::run(config).await {
-                tracing::error!(component = "daemon", error = %e, "Scheduler error");
-            }
-        }
-    });
-
-    let gateway_handle = if with_gateway {
-        Some(tokio::spawn({
-            let config = config.clone();
-            async move {
-                if let Err(e) = gateway::run(config).await {
-                    tracing::error!(component = "daemon", error = %e, "Gateway error");
-                }
-            }
-        }))
-    } else {
-        None
-    };
-
-    tokio::signal::ctrl_c().await?;
-    tracing::info!(component = "daemon", "Shutting down...");
-
-    scheduler_handle.abort();
-    if let Some(h) = gateway_handle {
-        h.abort();
-    }
-
-    Ok(())
+    daemon::serve(home, config, with_gateway).await
 }
 
-pub async fn stop() -> Result<()> {
-    if !daemon::is_running()? {
+pub async fn stop(home: &PathsConfig) -> Result<()> {
+    let config = DemonConfig::load(home)?;
+    if !daemon::is_running(&config.paths)? {
         println!("Demon is not running");
         return Ok(());
     }
 
-    let pid = daemon::read_pid()?;
-    daemon::stop_daemon(pid)?;
+    let pid = daemon::read_pid(&config.paths)?;
+    println!("Stopping demon (PID: {pid}), letting running jobs finish...");
+    daemon::stop_daemon(&config.paths, pid, daemon::stop_wait(&config))?;
     println!("Demon stopped (was PID: {pid})");
     Ok(())
 }
 
-pub async fn status() -> Result<()> {
-    let config = DemonConfig::load()?;
+pub async fn status(home: &PathsConfig) -> Result<()> {
+    let config = DemonConfig::load(home)?;
 
-    if daemon::is_running()? {
-        let pid = daemon::read_pid()?;
+    let running = daemon::is_running(&config.paths)?;
+    if running {
+        let pid = daemon::read_pid(&config.paths)?;
         println!("Demon: running (PID: {pid})");
     } else {
         println!("Demon: stopped");
     }
 
+    // Active runs are only meaningful while the daemon is up
+    let active = if running {
+        scheduler::load_running(&config).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    if !active.is_empty() {
+        println!("\nRunning Jobs ({}):", active.len());
+        println!("{:<20} {:<8} {:<10} Name", "ID", "State", "Elapsed");
+        println!("{}", "-".repeat(60));
+        let now = chrono::Utc::now();
+        for run in &active {
+            let state = match run.state {
+                scheduler::RunState::Running => "running",
+                scheduler::RunState::Waiting => "waiting",
+            };
+            println!(
+                "{:<20} {:<8} {:<10} {}",
+                run.job_id,
+                state,
+                scheduler::format_duration((now - run.registered_at).num_seconds()),
+                run.job_name
+            );
+        }
+    }
+
     let jobs = config.load_jobs()?;
     if jobs.is_empty() {
         println!("Jobs: none configured");
     } else {
         println!("\nScheduled Jobs ({}):", jobs.len());
-        println!("{:<20} {:<12} {:<10} {}", "ID", "Schedule", "Status", "Name");
-        println!("{}", "-".repeat(70));
+        println!(
+            "{:<20} {:<12} {:<10} {:<24} {:<17} {:<8} Name",
+            "ID", "Schedule", "Status", "Next Run", "Last Run", "Result"
+        );
+        println!("{}", "-".repeat(115));
         for job in &jobs {
             let status = if job.enabled { "enabled" } else { "disabled" };
-            let schedule_display = if job.schedule_type == "once" {
-                job.once_at.as_deref().unwrap_or("pending")
+            let schedule_display = scheduler::schedule_summary(job);
+            let next_run = if job.enabled {
+                scheduler::next_run_summary(&config, job)
             } else {
-                &job.schedule
+                "-".to_string()
             };
-            println!("{:<20} {:<12} {:<10} {}", job.id, schedule_display, status, job.name);
+            let (last_run, last_status) = scheduler::last_run_summary(&config, &job.id);
+            println!(
+                "{:<20} {:<12} {:<10} {:<24} {:<17} {:<8} {}",
+                job.id, schedule_display, status, next_run, last_run, last_status, job.name
+            );
         }
     }
 
@@ -120,7 +131,69 @@ pub async fn status() -> Result<()> {
     Ok(())
 }
 
-pub async fn job_add() -> Result<()> {
+/// `status --all`: one line per profile under `root`, plus `root` itself.
+pub async fn status_all(root: &PathsConfig) -> Result<()> {
+    let mut profiles = vec![root.clone()];
+    // Directories that aren't valid profile names, listed with the error
+    let mut invalid = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(root.profiles_dir()) {
+        let mut names: Vec<String> = entries
+            .flatten()
+            .filter(|e| e.path().is_dir())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        for name in names {
+            match PathsConfig::new(Some(root.base_dir()), Some(name.clone())) {
+                Ok(home) => profiles.push(home),
+                Err(e) => invalid.push((name, e)),
+            }
+        }
+    }
+
+    println!("{:<16} {:<20} {:<6} {:<8} Directory", "Profile", "Status", "Jobs", "Gateway");
+    println!("{}", "-".repeat(80));
+    for home in &profiles {
+        let name = home.profile.as_deref().unwrap_or("(default)");
+        let config = match DemonConfig::load(home) {
+            Ok(config) => config,
+            Err(e) => {
+                let status = format!("error: {e}");
+                println!("{:<16} {:<20} {:<6} {:<8} {}", name, status, "-", "-", home.base_dir().display());
+                continue;
+            }
+        };
+        let status = match daemon::is_running(&config.paths) {
+            Ok(true) => match daemon::read_pid(&config.paths) {
+                Ok(pid) => format!("running (PID {pid})"),
+                Err(_) => "running".to_string(),
+            },
+            Ok(false) => "stopped".to_string(),
+            Err(e) => format!("error: {e}"),
+        };
+        let jobs = config
+            .load_jobs()
+            .map(|jobs| jobs.len().to_string())
+            .unwrap_or_else(|_| "?".to_string());
+        let gateway = if config.gateway.enabled { "yes" } else { "no" };
+        println!(
+            "{:<16} {:<20} {:<6} {:<8} {}",
+            name,
+            status,
+            jobs,
+            gateway,
+            config.paths.base_dir().display()
+        );
+    }
+    for (name, e) in &invalid {
+        let status = format!("error: {e}");
+        println!("{:<16} {:<20} {:<6} {:<8} {}", name, status, "-", "-", root.profiles_dir().join(name).display());
+    }
+
+    Ok(())
+}
+
+pub async fn job_add(home: &PathsConfig) -> Result<()> {
     let mut input = String::new();
     std::io::stdin()
         .read_to_string(&mut input)
@@ -128,24 +201,23 @@ pub async fn job_add() -> Result<()> {
 
     let job: Job = toml::from_str(&input).context("Invalid job TOML")?;
 
-    let config = DemonConfig::load()?;
-    let mut jobs = config.load_jobs()?;
-
-    // Check for duplicate ID
-    if jobs.iter().any(|j| j.id == job.id) {
-        anyhow::bail!("Job with ID '{}' already exists", job.id);
-    }
+    let config = DemonConfig::load(home)?;
+    config.update_jobs(|jobs| {
+        // Check for duplicate ID
+        if jobs.iter().any(|j| j.id == job.id) {
+            anyhow::bail!("Job with ID '{}' already exists", job.id);
+        }
+        jobs.push(job.clone());
+        Ok(())
+    })?;
 
     println!("Added job: {} ({})", job.name, job.id);
-    println!("  Schedule: {}", job.schedule);
+    println!("  Schedule: {}", scheduler::schedule_summary(&job));
     println!("  Prompt: {}...", &job.prompt[..job.prompt.len().min(60)]);
 
-    jobs.push(job);
-    config.save_jobs(&jobs)?;
-
     // Signal daemon to reload if running
-    if daemon::is_running()? {
-        let pid = daemon::read_pid()?;
+    if daemon::is_running(&config.paths)? {
+        let pid = daemon::read_pid(&config.paths)?;
         daemon::signal_reload(pid)?;
         println!("  Daemon notified to reload jobs");
     }
@@ -153,8 +225,8 @@ pub async fn job_add() -> Result<()> {
     Ok(())
 }
 
-pub async fn job_list() -> Result<()> {
-    let config = DemonConfig::load()?;
+pub async fn job_list(home: &PathsConfig) -> Result<()> {
+    let config = DemonConfig::load(home)?;
     let jobs = config.load_jobs()?;
 
     if jobs.is_empty() {
@@ -170,10 +242,21 @@ pub async fn job_list() -> Result<()> {
         println!("Status:   {}", status);
         println!("Type:     {}", job.schedule_type);
         println!("Schedule: {}", job.schedule);
+        if !job.depends_on.is_empty() {
+            println!("After:    {}", job.depends_on.join(", "));
+        }
         if let Some(ref once_at) = job.once_at {
             println!("Run at:   {}", once_at);
         }
+        if let Some(tz) = job.timezone.as_ref().or(config.defaults.timezone.as_ref()) {
+            println!("Timezone: {}", tz);
+        }
+        if job.enabled {
+            println!("Next run: {}", scheduler::next_run_summary(&config, job));
+        }
         println!("Model:    {}", job.model);
+        let (last_run, last_status) = scheduler::last_run_summary(&config, &job.id);
+        println!("Last run: {} ({})", last_run, last_status);
         println!("Prompt:   {}", job.prompt);
         println!(
             "Output:   {}",
@@ -184,30 +267,29 @@ pub async fn job_list() -> Result<()> {
     Ok(())
 }
 
-pub async fn job_remove(id: &str) -> Result<()> {
-    let config = DemonConfig::load()?;
-    let mut jobs = config.load_jobs()?;
-
-    let before = jobs.len();
-    jobs.retain(|j| j.id != id);
+pub async fn job_remove(home: &PathsConfig, id: &str) -> Result<()> {
+    let config = DemonConfig::load(home)?;
+    config.update_jobs(|jobs| {
+        let before = jobs.len();
+        jobs.retain(|j| j.id != id);
 
-    if jobs.len() == before {
-        anyhow::bail!("Job '{}' not found", id);
-    }
-
-    config.save_jobs(&jobs)?;
+        if jobs.len() == before {
+            anyhow::bail!("Job '{}' not found", id);
+        }
+        Ok(())
+    })?;
     println!("Removed job: {}", id);
 
-    if daemon::is_running()? {
-        let pid = daemon::read_pid()?;
+    if daemon::is_running(&config.paths)? {
+        let pid = daemon::read_pid(&config.paths)?;
         daemon::signal_reload(pid)?;
     }
 
     Ok(())
 }
 
-pub async fn job_run(id: &str) -> Result<()> {
-    let config = DemonConfig::load()?;
+pub async fn job_run(home: &PathsConfig, id: &str) -> Result<()> {
+    let config = DemonConfig::load(home)?;
     let jobs = config.load_jobs()?;
 
     let job = jobs
@@ -216,45 +298,394 @@ pub async fn job_run(id: &str) -> Result<()> {
         .context(format!("Job '{}' not found", id))?;
 
     println!("Running job: {} ({})", job.name, job.id);
-    let result = scheduler::execute_job(job, &config).await?;
-
+    let result = scheduler::run_now(job, &config, RunTrigger::Manual, None).await?;
     println!("\n--- Output ---");
     println!("{}", result);
 
     Ok(())
 }
 
-pub async fn job_toggle(id: &str, enabled: bool) -> Result<()> {
-    let config = DemonConfig::load()?;
-    let mut jobs = config.load_jobs()?;
+pub async fn job_render(home: &PathsConfig, id: &str) -> Result<()> {
+    let config = DemonConfig::load(home)?;
+    let jobs = config.load_jobs()?;
+    let job = jobs
+        .iter()
+        .find(|j| j.id == id)
+        .context(format!("Job '{}' not found", id))?;
 
+    let mut ctx = TemplateContext::for_job(job, &config);
+    if job.schedule_type == "after" {
+        // Only known when an upstream run triggers the job
+        ctx.set("upstream_result", "<result of upstream run>")
+            .set("upstream_job", job.depends_on.first().map_or("", |d| d.as_str()));
+    }
+    let rendered = ctx.render_job(job, &config)?;
+
+    println!("--- Prompt ---");
+    println!("{}", rendered.prompt);
+    if !rendered.system_prompt.is_empty() {
+        println!("\n--- System prompt ---");
+        println!("{}", rendered.system_prompt);
+    }
+    if !rendered.working_dir.is_empty() {
+        println!("\n--- Working dir ---");
+        println!("{}", rendered.working_dir);
+    }
+    if !rendered.context_commands.is_empty() {
+        println!("\n--- Context commands (output appended to the prompt at run time) ---");
+        for cmd in &rendered.context_commands {
+            println!("{}", cmd.command);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn job_show(home: &PathsConfig, id: &str) -> Result<()> {
+    let config = DemonConfig::load(home)?;
+    let jobs = config.load_jobs()?;
     let job = jobs
-        .iter_mut()
+        .iter()
         .find(|j| j.id == id)
         .context(format!("Job '{}' not found", id))?;
 
-    job.enabled = enabled;
-    let name = job.name.clone();
-    config.save_jobs(&jobs)?;
+    print!("{}", toml::to_string_pretty(job)?);
+
+    println!();
+    if !job.enabled {
+        println!("Next runs: none (disabled)");
+    } else if job.schedule_type == "after" {
+        println!("Next runs: after {} succeeds", job.depends_on.join(", "));
+    } else {
+        let runs = scheduler::upcoming_runs(job, &config, chrono::Utc::now(), 5)?;
+        if runs.is_empty() {
+            println!("Next runs: none");
+        } else {
+            println!("Next runs:");
+            for at in runs {
+                println!("  {}", scheduler::format_in_job_timezone(&config, job, at));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn job_set(home: &PathsConfig, id: &str, fields: &[String]) -> Result<()> {
+    let config = DemonConfig::load(home)?;
+    config.update_jobs(|jobs| {
+        let job = jobs
+            .iter_mut()
+            .find(|j| j.id == id)
+            .context(format!("Job '{}' not found", id))?;
+
+        let mut updated = job.clone();
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .context(format!("Expected field=value, got '{}'", field))?;
+            let key = key.trim();
+            if key == "id" {
+                anyhow::bail!("A job's ID can't be changed with `job set`; use `demon job edit`");
+            }
+            updated = updated.with_field(key, value)?;
+        }
+        scheduler::validate_job(&updated, &config)?;
+        *job = updated;
+        Ok(())
+    })?;
+    println!("Updated job: {}", id);
+
+    if daemon::is_running(&config.paths)? {
+        let pid = daemon::read_pid(&config.paths)?;
+        daemon::signal_reload(pid)?;
+        println!("  Daemon notified to reload jobs");
+    }
+
+    Ok(())
+}
+
+pub async fn job_edit(home: &PathsConfig, id: &str) -> Result<()> {
+    let config = DemonConfig::load(home)?;
+    let jobs = config.read_jobs()?;
+    let idx = jobs
+        .iter()
+        .position(|j| j.id == id)
+        .context(format!("Job '{}' not found", id))?;
+
+    let original = toml::to_string_pretty(&jobs[idx])?;
+    let path = std::env::temp_dir().join(format!("demon-job-{}-{}.toml", id, uuid::Uuid::new_v4()));
+    std::fs::write(&path, &original).context("Failed to write temporary job file")?;
+
+    let result = edit_until_valid(&path, &config, &jobs, idx);
+    let _ = std::fs::remove_file(&path);
+    let Some(edited) = result? else {
+        println!("Edit cancelled, job unchanged");
+        return Ok(());
+    };
+
+    if toml::to_string_pretty(&edited)? == original {
+        println!("No changes");
+        return Ok(());
+    }
+    // The editor holds no lock, so refuse to overwrite a concurrent change
+    config.update_jobs(|jobs| {
+        let job = jobs
+            .iter_mut()
+            .find(|j| j.id == id)
+            .context(format!("Job '{}' was removed while editing", id))?;
+        if toml::to_string_pretty(job)? != original {
+            anyhow::bail!("Job '{}' changed while editing; run `demon job edit` again", id);
+        }
+        *job = edited.clone();
+        Ok(())
+    })?;
+    println!("Updated job: {}", edited.id);
+
+    if daemon::is_running(&config.paths)? {
+        let pid = daemon::read_pid(&config.paths)?;
+        daemon::signal_reload(pid)?;
+        println!("  Daemon notified to reload jobs");
+    }
+
+    Ok(())
+}
+
+/// Open `path` in the user's editor until it holds a valid replacement
+/// for `jobs[idx]`. Returns `None` if the user gives up.
+fn edit_until_valid(
+    path: &std::path::Path,
+    config: &DemonConfig,
+    jobs: &[Job],
+    idx: usize,
+) -> Result<Option<Job>> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    loop {
+        // Through the shell so EDITOR may carry arguments, e.g. "code --wait"
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(path)
+            .status()
+            .context(format!("Failed to run editor '{}'", editor))?;
+        if !status.success() {
+            anyhow::bail!("Editor '{}' exited with {}", editor, status);
+        }
+
+        let content = std::fs::read_to_string(path).context("Failed to read edited job")?;
+        let problem = match toml::from_str::<Job>(&content) {
+            Ok(job) => {
+                let mut updated = jobs.to_vec();
+                updated[idx] = job.clone();
+                let duplicate = jobs
+                    .iter()
+                    .enumerate()
+                    .any(|(i, j)| i != idx && j.id == job.id);
+                let checked = if duplicate {
+                    Err(anyhow::anyhow!("Job with ID '{}' already exists", job.id))
+                } else {
+                    scheduler::validate_job(&job, config)
+                        .and_then(|_| config::check_new_cycles(jobs, &updated))
+                };
+                match checked {
+                    Ok(()) => return Ok(Some(job)),
+                    Err(e) => format!("{e:#}"),
+                }
+            }
+            Err(e) => format!("Invalid job TOML: {e}"),
+        };
+
+        eprintln!("{problem}");
+        print!("Edit again? [Y/n] ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if answer.trim().eq_ignore_ascii_case("n") {
+            return Ok(None);
+        }
+    }
+}
+
+pub async fn job_history(home: &PathsConfig, id: &str, limit: usize) -> Result<()> {
+    let config = DemonConfig::load(home)?;
+    let jobs = config.load_jobs()?;
+    let records = history::load(&config, id)?;
+
+    if records.is_empty() {
+        if !jobs.iter().any(|j| j.id == id) {
+            anyhow::bail!("Job '{}' not found", id);
+        }
+        println!("No runs recorded for job '{}'", id);
+        return Ok(());
+    }
+
+    println!("Run history for '{}' ({} total):", id, records.len());
+    println!(
+        "{:<20} {:<9} {:<8} {:<4} {:<10} {:<8} {:<6} {:<5} Output / Error",
+        "Started", "Trigger", "Status", "Try", "Duration", "Cost", "Turns", "Exit"
+    );
+    println!("{}", "-".repeat(105));
+
+    for r in records.iter().rev().take(limit) {
+        let cost = r
+            .cost_usd
+            .map(|c| format!("${:.3}", c))
+            .unwrap_or_else(|| "-".to_string());
+        let turns = r
+            .num_turns
+            .map(|t| t.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let exit = r
+            .exit_code
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let detail = match (&r.error, &r.output_path) {
+            (Some(err), _) => err.lines().next().unwrap_or_default().chars().take(60).collect(),
+            (None, Some(path)) => path.clone(),
+            (None, None) => String::new(),
+        };
+        println!(
+            "{:<20} {:<9} {:<8} {:<4} {:<10} {:<8} {:<6} {:<5} {}",
+            r.started_at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S"),
+            r.trigger.as_str(),
+            r.status,
+            r.attempt,
+            scheduler::format_duration((r.duration_ms / 1000) as i64),
+            cost,
+            turns,
+            exit,
+            detail
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn job_toggle(home: &PathsConfig, id: &str, enabled: bool) -> Result<()> {
+    let config = DemonConfig::load(home)?;
+    let name = config.update_jobs(|jobs| {
+        let job = jobs
+            .iter_mut()
+            .find(|j| j.id == id)
+            .context(format!("Job '{}' not found", id))?;
+        job.enabled = enabled;
+        Ok(job.name.clone())
+    })?;
 
     let action = if enabled { "Enabled" } else { "Disabled" };
     println!("{} job: {} ({})", action, name, id);
 
-    if daemon::is_running()? {
-        let pid = daemon::read_pid()?;
+    if daemon::is_running(&config.paths)? {
+        let pid = daemon::read_pid(&config.paths)?;
+        daemon::signal_reload(pid)?;
+    }
+
+    Ok(())
+}
+
+// ==================== Config Commands ====================
+
+pub async fn config_get(home: &PathsConfig, key: &str, reveal: bool) -> Result<()> {
+    let mut config = DemonConfig::load(home)?;
+    if !reveal {
+        config = config.redacted()?;
+    }
+    if let Some(value) = config.get(key)? {
+        println!("{}", config::display_value(&value).trim_end());
+    }
+    Ok(())
+}
+
+pub async fn config_set(home: &PathsConfig, key: &str, value: Option<&str>) -> Result<()> {
+    let config = DemonConfig::update(home, |config| {
+        let updated = config.with_key(key, value)?;
+        // A bad value would stop the daemon from starting, so don't save
+        // it; problems that were already there are left to `config validate`
+        let messages = |c: &DemonConfig| -> Vec<String> {
+            config::config_problems(c, home.config_file())
+                .into_iter()
+                .map(|p| p.message)
+                .collect()
+        };
+        let existing = messages(&config);
+        let added: Vec<String> = messages(&updated)
+            .into_iter()
+            .filter(|m| !existing.contains(m))
+            .collect();
+        if !added.is_empty() {
+            anyhow::bail!("Invalid {}: {}", key, added.join("; "));
+        }
+        Ok(updated)
+    })?;
+
+    match config.redacted()?.get(key)? {
+        Some(value) => println!("{} = {}", key, config::display_value(&value).trim_end()),
+        None => println!("{} unset", key),
+    }
+
+    if daemon::is_running(&config.paths)? {
+        let pid = daemon::read_pid(&config.paths)?;
         daemon::signal_reload(pid)?;
+        println!("  Daemon notified to reload config");
     }
 
     Ok(())
 }
 
-pub async fn gateway_start() -> Result<()> {
-    let config = DemonConfig::load()?;
+pub async fn config_show(home: &PathsConfig) -> Result<()> {
+    let config = DemonConfig::load(home)?.redacted()?;
+    print!("{}", toml::to_string_pretty(&config)?);
+    Ok(())
+}
+
+pub async fn config_path(home: &PathsConfig) -> Result<()> {
+    println!("{}", home.config_file().display());
+    Ok(())
+}
+
+pub async fn config_validate(home: &PathsConfig) -> Result<()> {
+    // config.toml itself may not parse, so start from where load() reads it
+    let problems = config::validate_all(home);
+    if problems.is_empty() {
+        println!("Configuration OK");
+        return Ok(());
+    }
+
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    anyhow::bail!(
+        "{} problem{} found",
+        problems.len(),
+        if problems.len() == 1 { "" } else { "s" }
+    )
+}
+
+pub async fn gateway_start(home: &PathsConfig) -> Result<()> {
+    let config = DemonConfig::load(home)?;
     if config.gateway.bot_token.is_empty() {
         anyhow::bail!("Telegram bot token not configured. Run: demon config set gateway.bot_token <TOKEN>");
     }
     println!("Starting Telegram gateway...");
-    gateway::run(config).await
+    let terminated = daemon::terminated()?;
+    let config_rx = daemon::spawn_reload_handler(home.clone(), config)?;
+    let shutdown = daemon::Shutdown::new();
+    let mut gateway = tokio::spawn(gateway::run(config_rx.clone(), shutdown.clone(), None));
+
+    tokio::select! {
+        _ = terminated => {}
+        result = &mut gateway => return result.context("Gateway task failed")?,
+    }
+    // Let messages in progress finish, as the daemon does
+    let grace = std::time::Duration::from_secs(config_rx.borrow().shutdown.grace_secs);
+    daemon::wind_down(&shutdown, grace).await;
+    gateway.await.context("Gateway task failed")?
 }
 
 pub async fn gateway_stop() -> Result<()> {
@@ -263,8 +694,8 @@ pub async fn gateway_stop() -> Result<()> {
     Ok(())
 }
 
-pub async fn gateway_status() -> Result<()> {
-    let config = DemonConfig::load()?;
+pub async fn gateway_status(home: &PathsConfig) -> Result<()> {
+    let config = DemonConfig::load(home)?;
     if config.gateway.bot_token.is_empty() {
         println!("Telegram Gateway: not configured (no bot token)");
     } else {
@@ -279,22 +710,54 @@ pub async fn gateway_status() -> Result<()> {
     Ok(())
 }
 
-pub async fn install(with_gateway: bool) -> Result<()> {
+/// Global options that select `home` from `root`, for service definitions:
+/// services don't inherit DEMON_HOME or DEMON_PROFILE.
+fn service_args(root: &PathsConfig, home: &PathsConfig) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(dir) = &root.base_dir {
+        args.extend(["--base-dir".to_string(), dir.clone()]);
+    }
+    if let Some(profile) = &home.profile {
+        args.extend(["--profile".to_string(), profile.clone()]);
+    }
+    args
+}
+
+/// Service name, with the profile appended so each profile gets its own.
+fn service_name(home: &PathsConfig) -> String {
+    match &home.profile {
+        Some(profile) => format!("cc-demon-{profile}"),
+        None => "cc-demon".to_string(),
+    }
+}
+
+pub async fn install(root: &PathsConfig, home: &PathsConfig, with_gateway: bool) -> Result<()> {
     let exe_path = std::env::current_exe()?;
     let exe_str = exe_path.to_string_lossy();
+    let args = service_args(root, home);
+    let name = service_name(home);
 
     #[cfg(target_os = "linux")]
     {
         let gateway_flag = if with_gateway { " --with-gateway" } else { "" };
+        let global_flags: String = args.iter().map(|a| format!(" {a}")).collect();
+        let description = match &home.profile {
+            Some(profile) => format!(" ({profile})"),
+            None => String::new(),
+        };
+        // Leave `demon stop` time to drain running jobs
+        let config = DemonConfig::load(home)?;
+        let stop_timeout = daemon::stop_wait(&config).as_secs() + 10;
         let service = format!(
             r#"[Unit]
-Description=CC-Demon - Claude Code Daemon Scheduler & Gateway
+Description=CC-Demon - Claude Code Daemon Scheduler & Gateway{description}
 After=network.target
 
 [Service]
 Type=forking
-ExecStart={exe_str} start{gateway_flag}
-ExecStop={exe_str} stop
+ExecStart={exe_str}{global_flags} start{gateway_flag}
+ExecStop={exe_str}{global_flags} stop
+TimeoutStopSec={stop_timeout}
 Restart=on-failure
 RestartSec=10
 
@@ -305,14 +768,14 @@ WantedBy=multi-user.target
 
         let service_path = dirs::home_dir()
             .context("No home directory")?
-            .join(".config/systemd/user/cc-demon.service");
+            .join(format!(".config/systemd/user/{name}.service"));
 
         std::fs::create_dir_all(service_path.parent().unwrap())?;
         std::fs::write(&service_path, service)?;
 
         println!("Installed systemd user service at: {}", service_path.display());
-        println!("Enable with: systemctl --user enable cc-demon");
-        println!("Start with:  systemctl --user start cc-demon");
+        println!("Enable with: systemctl --user enable {name}");
+        println!("Start with:  systemctl --user start {name}");
     }
 
     #[cfg(target_os = "macos")]
@@ -322,16 +785,21 @@ WantedBy=multi-user.target
         } else {
             ""
         };
+        let global_args: String = args
+            .iter()
+            .map(|a| format!("\n        <string>{a}</string>"))
+            .collect();
+        let label = name.replacen("cc-demon", "com.cc-demon.daemon", 1);
         let plist = format!(
             r#"<?xml version="1.0" encoding="UTF-8"?>
 <!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
 <plist version="1.0">
 <dict>
     <key>Label</key>
-    <string>com.cc-demon.daemon</string>
+    <string>{label}</string>
     <key>ProgramArguments</key>
     <array>
-        <string>{exe_str}</string>
+        <string>{exe_str}</string>{global_args}
         <string>start</string>
         <string>--foreground</string>{gateway_arg}
     </array>
@@ -340,9 +808,9 @@ WantedBy=multi-user.target
     <key>KeepAlive</key>
     <true/>
     <key>StandardOutPath</key>
-    <string>/tmp/cc-demon.out.log</string>
+    <string>/tmp/{name}.out.log</string>
     <key>StandardErrorPath</key>
-    <string>/tmp/cc-demon.err.log</string>
+    <string>/tmp/{name}.err.log</string>
 </dict>
 </plist>
 "#
@@ -350,7 +818,7 @@ WantedBy=multi-user.target
 
         let plist_path = dirs::home_dir()
             .context("No home directory")?
-            .join("Library/LaunchAgents/com.cc-demon.daemon.plist");
+            .join(format!("Library/LaunchAgents/{label}.plist"));
 
         std::fs::write(&plist_path, plist)?;
         println!("Installed launchd service at: {}", plist_path.display());
@@ -360,8 +828,13 @@ WantedBy=multi-user.target
     #[cfg(target_os = "windows")]
     {
         println!("Windows service installation:");
-        println!("  Use Task Scheduler to create a task that runs:");
-        println!("  {} start --foreground{}", exe_str, if with_gateway { " --with-gateway" } else { "" });
+        println!("  Use Task Scheduler to create a task named {name} that runs:");
+        println!(
+            "  {}{} start --foreground{}",
+            exe_str,
+            args.iter().map(|a| format!(" {a}")).collect::<String>(),
+            if with_gateway { " --with-gateway" } else { "" }
+        );
         println!("  Set trigger: At log on");
         println!("  Set action: Start a program");
     }
@@ -369,12 +842,14 @@ WantedBy=multi-user.target
     Ok(())
 }
 
-pub async fn uninstall() -> Result<()> {
+pub async fn uninstall(home: &PathsConfig) -> Result<()> {
+    let name = service_name(home);
+
     #[cfg(target_os = "linux")]
     {
         let service_path = dirs::home_dir()
             .context("No home directory")?
-            .join(".config/systemd/user/cc-demon.service");
+            .join(format!(".config/systemd/user/{name}.service"));
 
         if service_path.exists() {
             std::fs::remove_file(&service_path)?;
@@ -387,9 +862,10 @@ pub async fn uninstall() -> Result<()> {
 
     #[cfg(target_os = "macos")]
     {
+        let label = name.replacen("cc-demon", "com.cc-demon.daemon", 1);
         let plist_path = dirs::home_dir()
             .context("No home directory")?
-            .join("Library/LaunchAgents/com.cc-demon.daemon.plist");
+            .join(format!("Library/LaunchAgents/{label}.plist"));
 
         if plist_path.exists() {
             println!("Unload with: launchctl unload {}", plist_path.display());
@@ -402,7 +878,7 @@ pub async fn uninstall() -> Result<()> {
 
     #[cfg(target_os = "windows")]
     {
-        println!("Remove the cc-demon task from Task Scheduler manually");
+        println!("Remove the {name} task from Task Scheduler manually");
     }
 
     Ok(())
@@ -414,7 +890,8 @@ const DEFAULT_CONFIG: &str = r#"# CC-Demon Configuration
 # See: https://github.com/phunguyen/cc-demon for documentation
 
 [paths]
-# base_dir = "~/.demon"  # Uncomment to customize
+# Keep jobs, logs and output here instead of next to this file
+# base_dir = "~/.demon"
 
 [gateway]
 enabled = false
@@ -426,12 +903,29 @@ max_budget_usd = 5.0
 allowed_tools = []
 disallowed_tools = []
 append_system_prompt = ""
+max_attachment_bytes = 20971520  # Largest document or photo accepted from a chat
+attachment_types = ["image/jpeg", "image/png", "image/gif", "image/webp", "application/pdf", "application/json", "text/*"]
+inbox_retention_days = 30  # Received files older than this are deleted; 0 keeps them
 
 [defaults]
 model = "sonnet"
 max_turns = 10
 max_budget_usd = 5.0
 output_format = "json"
+# timezone = "Asia/Ho_Chi_Minh"  # Default IANA timezone for job schedules
+
+[scheduler]
+max_concurrent_jobs = 0  # 0 = unlimited
+
+[shutdown]
+grace_secs = 30  # Time running jobs and gateway messages get to finish on stop
+
+[budget]
+# Spend caps in USD per local calendar day/month; unset = no cap
+# daily_usd = 10.0
+# monthly_usd = 100.0
+# job_daily_usd = 2.0     # Per job or CLI task (jobs can set daily_budget_usd/monthly_budget_usd)
+# chat_monthly_usd = 20.0  # Per gateway chat
 "#;
 
 const DEFAULT_JOBS: &str = r#"# Scheduled Jobs
@@ -447,6 +941,23 @@ const DEFAULT_JOBS: &str = r#"# Scheduled Jobs
 # working_dir = "/path/to/project"
 # model = "sonnet"
 # output_destinations = ["file", "telegram:123456789"]
+# on_failure_destinations = ["telegram:123456789"]  # Failure alerts (default: output_destinations)
+# catch_up = "run-once"  # skip | run-once | run-all (replays up to catch_up_max)
+# timezone = "Asia/Ho_Chi_Minh"  # IANA name; defaults to [defaults] timezone, else UTC
+# overlap = "skip"  # allow | skip | queue | kill-previous, if still running when it fires again
+# timeout_secs = 1800  # Kill the run (and everything it started) after 30 minutes
+# retries = 2  # Retry failed runs, waiting retry_delay_secs (retry_backoff = "fixed" | "exponential")
+# prompt_file = "prompts/standup.md"  # Read the prompt from ~/.demon/prompts/standup.md
+# context_commands = [{ command = "git log --oneline -20", label = "Recent commits" }]
+# run_if = { command = "git log --since=yesterday | grep -q ." }  # Skip the run unless this succeeds
+# daily_budget_usd = 1.0  # Skip runs once the job has spent this much today
+#
+# [[jobs]]
+# id = "standup-post"
+# name = "Post Standup"
+# trigger = "after"  # Run when every job in depends_on has succeeded
+# depends_on = ["daily-standup"]
+# prompt = "Rewrite for Slack: {upstream_result}"
 "#;
 
 const DEFAULT_AGENTS: &str = r#"# Agent Definitions
@@ -460,6 +971,7 @@ const DEFAULT_AGENTS: &str = r#"# Agent Definitions
 # model = "sonnet"
 # system_prompt = "You are a code review expert. Review code for bugs, performance issues, and best practices."
 # allowed_tools = ["Read", "Grep", "Glob"]
+# timeout_secs = 1800  # Kill the task after 30 minutes
 "#;
 
 const DEFAULT_TASKS: &str = r#"# Task Definitions
@@ -474,11 +986,10 @@ const DEFAULT_TASKS: &str = r#"# Task Definitions
 # enabled = true
 "#;
 
-pub async fn init(with_gateway: bool) -> Result<()> {
+pub async fn init(home: &PathsConfig, with_gateway: bool) -> Result<()> {
     use std::io::{self, Write};
 
-    let paths = crate::config::PathsConfig::default();
-    let base_dir = paths.base_dir();
+    let base_dir = home.base_dir();
 
     println!("Initializing CC-Demon in {}...", base_dir.display());
 
@@ -510,8 +1021,14 @@ pub async fn init(with_gateway: bool) -> Result<()> {
 
     for (filename, content) in &files {
         let path = base_dir.join(filename);
-        if !path.exists() {
-            std::fs::write(&path, content)?;
+        let created = store::locked(&path, || {
+            if path.exists() {
+                return Ok(false);
+            }
+            store::write_atomic(&path, content)?;
+            Ok(true)
+        })?;
+        if created {
             println!("  Created {}", filename);
         } else {
             println!("  {} already exists (skipped)", filename);
@@ -558,22 +1075,14 @@ pub async fn init(with_gateway: bool) -> Result<()> {
                 .collect();
 
             // Load existing config, update gateway settings, and save
-            let config_path = paths.config_file();
-            let mut config = if config_path.exists() {
-                let content = std::fs::read_to_string(&config_path)?;
-                toml::from_str(&content).unwrap_or_default()
-            } else {
-                crate::config::DemonConfig::default()
-            };
-
-            config.gateway.enabled = true;
-            config.gateway.bot_token = token.to_string();
-            if !chat_ids.is_empty() {
-                config.gateway.allowed_chat_ids = chat_ids.clone();
-            }
-
-            let content = toml::to_string_pretty(&config)?;
-            std::fs::write(&config_path, content)?;
+            DemonConfig::update(home, |mut config| {
+                config.gateway.enabled = true;
+                config.gateway.bot_token = token.to_string();
+                if !chat_ids.is_empty() {
+                    config.gateway.allowed_chat_ids = chat_ids.clone();
+                }
+                Ok(config)
+            })?;
 
             println!("\nGateway configured!");
             println!("  Bot token: {}...", &token[..token.len().min(20)]);
@@ -584,7 +1093,7 @@ pub async fn init(with_gateway: bool) -> Result<()> {
         } else {
             println!("\nSkipping gateway setup. You can configure it later with:");
             println!("  demon init --with-gateway");
-            println!("  # or edit ~/.demon/config.toml directly");
+            println!("  # or edit {} directly", home.config_file().display());
         }
     } else {
         println!("\nTo configure Telegram gateway, run: demon init --with-gateway");
@@ -595,8 +1104,8 @@ pub async fn init(with_gateway: bool) -> Result<()> {
 
 // ==================== Task Commands ====================
 
-pub async fn task_run(task_name: &str, message: &str) -> Result<()> {
-    let config = DemonConfig::load()?;
+pub async fn task_run(home: &PathsConfig, task_name: &str, message: &str) -> Result<()> {
+    let config = DemonConfig::load(home)?;
 
     // Use default message if empty
     let msg = if message.is_empty() {
@@ -605,6 +1114,14 @@ pub async fn task_run(task_name: &str, message: &str) -> Result<()> {
         message
     };
 
+    let task_id = task::load_tasks(&config)?
+        .into_iter()
+        .find(|t| t.name == task_name || t.id == task_name)
+        .map_or_else(|| task_name.to_string(), |t| t.id);
+    if let Some(reason) = usage::check(&config, Scope::Task(&task_id), chrono::Utc::now())? {
+        anyhow::bail!("{reason}");
+    }
+
     println!("Running task: {}", task_name);
     let result = task::run_task_by_name(task_name, msg, &config).await?;
 
@@ -614,20 +1131,20 @@ pub async fn task_run(task_name: &str, message: &str) -> Result<()> {
     Ok(())
 }
 
-pub async fn task_list() -> Result<()> {
-    let config = DemonConfig::load()?;
+pub async fn task_list(home: &PathsConfig) -> Result<()> {
+    let config = DemonConfig::load(home)?;
     let tasks = task::load_tasks(&config)?;
 
     if tasks.is_empty() {
         println!("No tasks configured.");
-        println!("\nCreate ~/.demon/tasks.toml with task definitions.");
+        println!("\nCreate {} with task definitions.", config.paths.tasks_file().display());
         return Ok(());
     }
 
     println!("Configured Tasks ({}):", tasks.len());
     println!(
-        "{:<20} {:<20} {:<10} {}",
-        "ID", "Agent", "Status", "Description"
+        "{:<20} {:<20} {:<10} Description",
+        "ID", "Agent", "Status"
     );
     println!("{}", "-".repeat(80));
 
@@ -645,20 +1162,20 @@ pub async fn task_list() -> Result<()> {
     Ok(())
 }
 
-pub async fn agent_list() -> Result<()> {
-    let config = DemonConfig::load()?;
+pub async fn agent_list(home: &PathsConfig) -> Result<()> {
+    let config = DemonConfig::load(home)?;
     let agents = task::load_agents(&config)?;
 
     if agents.is_empty() {
         println!("No agents configured.");
-        println!("\nCreate ~/.demon/agents.toml with agent definitions.");
+        println!("\nCreate {} with agent definitions.", config.paths.agents_file().display());
         return Ok(());
     }
 
     println!("Configured Agents ({}):", agents.len());
     println!(
-        "{:<20} {:<15} {:<40} {}",
-        "ID", "Model", "Working Dir", "Name"
+        "{:<20} {:<15} {:<40} Name",
+        "ID", "Model", "Working Dir"
     );
     println!("{}", "-".repeat(90));
 
@@ -677,15 +1194,111 @@ pub async fn agent_list() -> Result<()> {
     Ok(())
 }
 
+// ==================== Usage Command ====================
+
+pub async fn usage(home: &PathsConfig, days: u32) -> Result<()> {
+    let config = DemonConfig::load(home)?;
+    let now = chrono::Utc::now();
+    let today = usage::day_start(now);
+    let month = usage::month_start(now);
+    let first_day = today - chrono::Duration::days(i64::from(days.max(1)) - 1);
+    let entries = usage::load_since(&config, month.min(first_day))?;
+
+    let this_month: Vec<UsageEntry> = entries.iter().filter(|e| e.at >= month).cloned().collect();
+    let spent_today: f64 = entries
+        .iter()
+        .filter(|e| e.at >= today)
+        .map(|e| e.usage.cost_usd)
+        .sum();
+    let spent_month = usage::total(&this_month).cost_usd;
+
+    let cap = |limit: Option<f64>| {
+        limit
+            .map(|l| format!(" of ${l:.2} cap"))
+            .unwrap_or_default()
+    };
+    println!("Today:      ${spent_today:.2}{}", cap(config.budget.daily_usd));
+    println!("This month: ${spent_month:.2}{}", cap(config.budget.monthly_usd));
+
+    let daily = usage::totals_by(&entries, |e| {
+        (e.at >= first_day).then(|| e.at.with_timezone(&chrono::Local).date_naive())
+    });
+    println!("\nLast {} day(s):", days.max(1));
+    print_totals_header("Date");
+    for (date, totals) in daily.iter().rev() {
+        print_totals(&date.format("%Y-%m-%d").to_string(), totals);
+    }
+
+    if this_month.is_empty() {
+        return Ok(());
+    }
+
+    println!("\nThis month by source:");
+    print_totals_header("Source");
+    for (source, totals) in usage::totals_by(&this_month, |e| Some(e.source)) {
+        print_totals(source.as_str(), &totals);
+    }
+
+    let by_job = usage::totals_by(&this_month, |e| e.job_id.clone());
+    if !by_job.is_empty() {
+        println!("\nThis month by job:");
+        print_totals_header("Job");
+        for (job_id, totals) in &by_job {
+            print_totals(job_id, totals);
+        }
+    }
+
+    let by_task = usage::totals_by(&this_month, |e| e.task_id.clone());
+    if !by_task.is_empty() {
+        println!("\nThis month by task:");
+        print_totals_header("Task");
+        for (task_id, totals) in &by_task {
+            print_totals(task_id, totals);
+        }
+    }
+
+    let by_chat = usage::totals_by(&this_month, |e| e.chat_id);
+    if !by_chat.is_empty() {
+        println!("\nThis month by chat:");
+        print_totals_header("Chat");
+        for (chat_id, totals) in &by_chat {
+            print_totals(&chat_id.to_string(), totals);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_totals_header(key: &str) {
+    println!(
+        "{:<24} {:>6} {:>10} {:>12} {:>12}",
+        key, "Calls", "Cost", "Input tok", "Output tok"
+    );
+    println!("{}", "-".repeat(68));
+}
+
+fn print_totals(key: &str, totals: &Totals) {
+    println!(
+        "{:<24} {:>6} {:>10} {:>12} {:>12}",
+        key,
+        totals.calls,
+        format!("${:.3}", totals.cost_usd),
+        totals.input_tokens,
+        totals.output_tokens
+    );
+}
+
 // ==================== Logs Command ====================
 
 pub async fn logs(
+    home: &PathsConfig,
     follow: bool,
     tail: Option<usize>,
     level: Option<String>,
     raw: bool,
 ) -> Result<()> {
-    let log_file = logging::log_file_path();
+    let config = DemonConfig::load(home)?;
+    let log_file = logging::log_file_path(&config.paths);
 
     if !log_file.exists() {
         println!("No log file found at: {}", log_file.display());
@@ -695,7 +1308,7 @@ pub async fn logs(
     }
 
     // Show log file info
-    let size = logging::log_size()?;
+    let size = logging::log_size(&config.paths)?;
     if !raw && !follow {
         println!(
             "Log file: {} ({})",
@@ -2,10 +2,21 @@ mod commands;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::config::PathsConfig;
 
 #[derive(Parser)]
 #[command(name = "demon", version, about = "Daemon scheduler and Telegram gateway for Claude Code")]
 pub struct Cli {
+    /// Directory holding config, jobs, logs and the PID file (default: ~/.demon)
+    #[arg(long, global = true, env = "DEMON_HOME", value_name = "DIR")]
+    pub base_dir: Option<PathBuf>,
+
+    /// Named profile: a separate daemon with its own config, jobs and logs
+    #[arg(long, global = true, env = "DEMON_PROFILE", value_name = "NAME")]
+    pub profile: Option<String>,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -24,12 +35,21 @@ pub enum Command {
     /// Stop the demon daemon
     Stop,
     /// Show daemon status and scheduled jobs
-    Status,
+    Status {
+        /// List the daemon of every profile instead
+        #[arg(long)]
+        all: bool,
+    },
     /// Manage scheduled jobs
     Job {
         #[command(subcommand)]
         action: JobAction,
     },
+    /// Read, change and check configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
     /// Manage Telegram gateway
     Gateway {
         #[command(subcommand)]
@@ -54,6 +74,12 @@ pub enum Command {
         #[command(subcommand)]
         action: TaskAction,
     },
+    /// Show spend from the usage ledger against the budget caps
+    Usage {
+        /// Number of days in the daily breakdown
+        #[arg(short, long, default_value_t = 7)]
+        days: u32,
+    },
     /// View daemon logs with hl
     Logs {
         /// Follow log output in real-time (like tail -f)
@@ -97,6 +123,68 @@ pub enum JobAction {
         /// Job ID
         id: String,
     },
+    /// Show a job's definition and its next scheduled runs
+    Show {
+        /// Job ID
+        id: String,
+    },
+    /// Set job fields, e.g. `demon job set digest retries=2 model=opus`
+    Set {
+        /// Job ID
+        id: String,
+        /// `field=value` pairs; values are TOML, or plain strings
+        #[arg(required = true)]
+        fields: Vec<String>,
+    },
+    /// Edit a job in $EDITOR
+    Edit {
+        /// Job ID
+        id: String,
+    },
+    /// Show a job's prompt, system prompt and working directory with
+    /// template variables expanded
+    Render {
+        /// Job ID
+        id: String,
+    },
+    /// Show run history for a job
+    History {
+        /// Job ID
+        id: String,
+        /// Show only the last N runs
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print a setting, e.g. `gateway.max_turns`
+    Get {
+        /// Dotted key
+        key: String,
+        /// Print secrets such as gateway.bot_token instead of masking them
+        #[arg(long)]
+        reveal: bool,
+    },
+    /// Change a setting; the value is TOML, or a plain string
+    Set {
+        /// Dotted key
+        key: String,
+        /// New value
+        value: String,
+    },
+    /// Reset a setting to its default
+    Unset {
+        /// Dotted key
+        key: String,
+    },
+    /// Print the whole configuration, defaults included and secrets masked
+    Show,
+    /// Print the path of config.toml
+    Path,
+    /// Check config.toml, jobs.toml, agents.toml and tasks.toml
+    Validate,
 }
 
 #[derive(Subcommand)]
@@ -125,37 +213,62 @@ pub enum TaskAction {
     ListAgents,
 }
 
+impl Cli {
+    /// The paths selected by `--base-dir` and `--profile`.
+    pub fn home(&self) -> Result<PathsConfig> {
+        PathsConfig::new(self.base_dir.clone(), self.profile.clone())
+    }
+}
+
 pub async fn run(cli: Cli) -> Result<()> {
+    let home = &cli.home()?;
+    // Where profiles live, for commands that span or name them
+    let root = &PathsConfig::new(cli.base_dir.clone(), None)?;
     match cli.command {
         Command::Start {
             with_gateway,
             foreground,
-        } => commands::start(with_gateway, foreground).await,
-        Command::Stop => commands::stop().await,
-        Command::Status => commands::status().await,
+        } => commands::start(home, with_gateway, foreground).await,
+        Command::Stop => commands::stop(home).await,
+        Command::Status { all: false } => commands::status(home).await,
+        Command::Status { all: true } => commands::status_all(root).await,
         Command::Job { action } => match action {
-            JobAction::Add => commands::job_add().await,
-            JobAction::List => commands::job_list().await,
-            JobAction::Remove { id } => commands::job_remove(&id).await,
-            JobAction::Run { id } => commands::job_run(&id).await,
-            JobAction::Enable { id } => commands::job_toggle(&id, true).await,
-            JobAction::Disable { id } => commands::job_toggle(&id, false).await,
+            JobAction::Add => commands::job_add(home).await,
+            JobAction::List => commands::job_list(home).await,
+            JobAction::Remove { id } => commands::job_remove(home, &id).await,
+            JobAction::Run { id } => commands::job_run(home, &id).await,
+            JobAction::Enable { id } => commands::job_toggle(home, &id, true).await,
+            JobAction::Disable { id } => commands::job_toggle(home, &id, false).await,
+            JobAction::History { id, limit } => commands::job_history(home, &id, limit).await,
+            JobAction::Render { id } => commands::job_render(home, &id).await,
+            JobAction::Show { id } => commands::job_show(home, &id).await,
+            JobAction::Set { id, fields } => commands::job_set(home, &id, &fields).await,
+            JobAction::Edit { id } => commands::job_edit(home, &id).await,
+        },
+        Command::Config { action } => match action {
+            ConfigAction::Get { key, reveal } => commands::config_get(home, &key, reveal).await,
+            ConfigAction::Set { key, value } => commands::config_set(home, &key, Some(&value)).await,
+            ConfigAction::Unset { key } => commands::config_set(home, &key, None).await,
+            ConfigAction::Show => commands::config_show(home).await,
+            ConfigAction::Path => commands::config_path(home).await,
+            ConfigAction::Validate => commands::config_validate(home).await,
         },
         Command::Gateway { action } => match action {
-            GatewayAction::Start => commands::gateway_start().await,
+            GatewayAction::Start => commands::gateway_start(home).await,
             GatewayAction::Stop => commands::gateway_stop().await,
-            GatewayAction::Status => commands::gateway_status().await,
+            GatewayAction::Status => commands::gateway_status(home).await,
         },
-        Command::Install { with_gateway } => commands::install(with_gateway).await,
-        Command::Uninstall => commands::uninstall().await,
-        Command::Init { with_gateway } => commands::init(with_gateway).await,
+        Command::Install { with_gateway } => commands::install(root, home, with_gateway).await,
+        Command::Uninstall => commands::uninstall(home).await,
+        Command::Init { with_gateway } => commands::init(home, with_gateway).await,
         Command::Task { action } => match action {
-            TaskAction::Run { name, message } => commands::task_run(&name, &message).await,
-            TaskAction::List => commands::task_list().await,
-            TaskAction::ListAgents => commands::agent_list().await,
+            TaskAction::Run { name, message } => commands::task_run(home, &name, &message).await,
+            TaskAction::List => commands::task_list(home).await,
+            TaskAction::ListAgents => commands::agent_list(home).await,
         },
+        Command::Usage { days } => commands::usage(home, days).await,
         Command::Logs { follow, tail, level, raw } => {
-            commands::logs(follow, tail, level, raw).await
+            commands::logs(home, follow, tail, level, raw).await
         }
     }
 }
@@ -0,0 +1,90 @@
+//! Reading and writing settings by dotted key, e.g. `gateway.max_turns`.
+//!
+//! Values are edited on the serialized form and deserialized back, so the
+//! structs' own serde attributes (types, enums, defaults) validate them.
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// A copy of `target` with the field at `key` set from `value` text, or
+/// reset to its default when `value` is `None`. The text is read as TOML
+/// (`3`, `true`, `["a", "b"]`, `{ path = "~/inbox" }`), falling back to a
+/// plain string so `model=opus` needs no quotes.
+pub(super) fn with_value<T: Serialize + DeserializeOwned>(
+    target: &T,
+    key: &str,
+    value: Option<&str>,
+) -> Result<T> {
+    let root = serde_json::to_value(target)?;
+    let path: Vec<&str> = key.split('.').collect();
+    let (field, parents) = path.split_last().unwrap();
+    if !table(&mut root.clone(), parents).is_some_and(|t| t.contains_key(*field)) {
+        anyhow::bail!("Unknown key '{}'", key);
+    }
+
+    let with = |v: Option<Value>| {
+        let mut root = root.clone();
+        let fields = table(&mut root, parents).unwrap();
+        match v {
+            Some(v) => fields.insert(field.to_string(), v),
+            None => fields.remove(*field),
+        };
+        serde_json::from_value::<T>(root)
+    };
+
+    let updated = match value {
+        None => with(None),
+        Some(text) => {
+            let raw = Value::String(text.to_string());
+            match parse_toml(text) {
+                Some(parsed) => with(Some(parsed)).or_else(|e| with(Some(raw)).map_err(|_| e)),
+                None => with(Some(raw)),
+            }
+        }
+    };
+    updated.map_err(|e| anyhow::anyhow!("Invalid value for '{}': {}", key, e))
+}
+
+/// The value at `key`, or `None` if it is a valid key that isn't set.
+pub(super) fn get<T: Serialize>(target: &T, key: &str) -> Result<Option<toml::Value>> {
+    let mut all = serde_json::to_value(target)?;
+    let path: Vec<&str> = key.split('.').collect();
+    let (field, parents) = path.split_last().unwrap();
+    if !table(&mut all, parents).is_some_and(|t| t.contains_key(*field)) {
+        anyhow::bail!("Unknown key '{}'", key);
+    }
+
+    // Unset options are left out of the TOML form
+    let mut value = toml::Value::try_from(target)?;
+    for part in &path {
+        match value.get(part) {
+            Some(v) => value = v.clone(),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(value))
+}
+
+/// Format a value for printing: strings bare, tables as TOML documents.
+pub fn display(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Table(t) => toml::to_string_pretty(t).unwrap_or_default(),
+        other => other.to_string(),
+    }
+}
+
+fn table<'a>(root: &'a mut Value, path: &[&str]) -> Option<&'a mut Map<String, Value>> {
+    let mut current = root.as_object_mut()?;
+    for part in path {
+        current = current.get_mut(*part)?.as_object_mut()?;
+    }
+    Some(current)
+}
+
+fn parse_toml(text: &str) -> Option<Value> {
+    let mut doc = toml::from_str::<toml::Table>(&format!("v = {text}")).ok()?;
+    serde_json::to_value(doc.remove("v")?).ok()
+}
@@ -1,10 +1,25 @@
+mod keys;
+mod validate;
+
 use anyhow::{Context, Result};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::PathBuf;
 
 use crate::formatter::MessageFormat;
+use crate::store;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+pub use keys::display as display_value;
+pub use validate::{config_problems, validate_all};
+
+/// Settings whose values are masked when printed or logged.
+pub const SECRET_KEYS: &[&str] = &["gateway.bot_token"];
+
+/// Printed in place of a secret setting's value.
+const REDACTED: &str = "********";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DemonConfig {
     #[serde(default)]
     pub paths: PathsConfig,
@@ -12,23 +27,70 @@ pub struct DemonConfig {
     pub gateway: GatewayConfig,
     #[serde(default)]
     pub defaults: JobDefaults,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub budget: BudgetConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PathsConfig {
     pub base_dir: Option<String>,
+    /// Profile these paths belong to (`--profile`); never read from or
+    /// written to config.toml.
+    #[serde(skip)]
+    pub profile: Option<String>,
 }
 
-impl Default for PathsConfig {
-    fn default() -> Self {
-        Self { base_dir: None }
+impl PathsConfig {
+    /// Paths under `base_dir`, or under `~/.demon` when it is `None`. A
+    /// relative `base_dir` is made absolute, as the daemon changes its
+    /// working directory. A named `profile` gets its own directory under
+    /// `<base_dir>/profiles/`.
+    pub fn new(base_dir: Option<PathBuf>, profile: Option<String>) -> Result<Self> {
+        let base_dir = base_dir
+            .map(|dir| std::path::absolute(&dir).context(format!("Invalid base directory {}", dir.display())))
+            .transpose()?;
+        let root = Self {
+            base_dir: base_dir.map(|dir| dir.to_string_lossy().to_string()),
+            profile: None,
+        };
+        let Some(name) = profile else {
+            return Ok(root);
+        };
+
+        let valid = !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            anyhow::bail!(
+                "Invalid profile name '{}': use letters, digits, '-' and '_'",
+                name
+            );
+        }
+        Ok(root.for_profile(name))
+    }
+
+    /// The paths of profile `name` under these.
+    fn for_profile(&self, name: String) -> Self {
+        Self {
+            base_dir: Some(self.profiles_dir().join(&name).to_string_lossy().to_string()),
+            profile: Some(name),
+        }
+    }
+
+    /// Directory holding one subdirectory per named profile.
+    pub fn profiles_dir(&self) -> PathBuf {
+        self.base_dir().join("profiles")
     }
-}
 
-impl PathsConfig {
     pub fn base_dir(&self) -> PathBuf {
         if let Some(ref base) = self.base_dir {
-            PathBuf::from(base)
+            match (base.strip_prefix("~/"), dirs::home_dir()) {
+                (Some(rest), Some(home)) => home.join(rest),
+                _ => PathBuf::from(base),
+            }
         } else {
             dirs::home_dir()
                 .expect("No home directory found")
@@ -48,10 +110,35 @@ impl PathsConfig {
         self.base_dir().join("output")
     }
 
+    pub fn history_dir(&self) -> PathBuf {
+        self.base_dir().join("history")
+    }
+
     pub fn logs_dir(&self) -> PathBuf {
         self.base_dir().join("logs")
     }
 
+    pub fn scheduler_state_file(&self) -> PathBuf {
+        self.base_dir().join("scheduler-state.json")
+    }
+
+    pub fn running_file(&self) -> PathBuf {
+        self.base_dir().join("running.json")
+    }
+
+    /// Documents and photos received by the gateway, one directory per chat.
+    pub fn inbox_dir(&self) -> PathBuf {
+        self.base_dir().join("inbox")
+    }
+
+    pub fn gates_dir(&self) -> PathBuf {
+        self.base_dir().join("gates")
+    }
+
+    pub fn usage_file(&self) -> PathBuf {
+        self.base_dir().join("usage.jsonl")
+    }
+
     pub fn pid_file(&self) -> PathBuf {
         self.base_dir().join("demon.pid")
     }
@@ -97,9 +184,10 @@ pub struct GatewayConfig {
     /// Use persistent tmux session instead of spawning claude -p per message
     #[serde(default)]
     pub use_persistent_session: bool,
-    /// Tmux session name for persistent session (default: "cc-demon-gateway")
-    #[serde(default = "default_tmux_session_name")]
-    pub tmux_session_name: String,
+    /// Tmux session name for persistent session (default:
+    /// "cc-demon-gateway", or "cc-demon-gateway-<profile>" for a profile)
+    #[serde(default)]
+    pub tmux_session_name: Option<String>,
     /// Prompt marker to detect response completion (default: "> ")
     #[serde(default = "default_prompt_marker")]
     pub prompt_marker: String,
@@ -109,6 +197,18 @@ pub struct GatewayConfig {
     /// Message format for Telegram (markdownv2, html, plain)
     #[serde(default)]
     pub message_format: MessageFormat,
+    /// Largest document or photo accepted from a chat, in bytes (default:
+    /// 20 MB, the most the Bot API lets bots download)
+    #[serde(default = "default_max_attachment_bytes")]
+    pub max_attachment_bytes: u64,
+    /// MIME types of documents accepted from a chat; `type/*` matches all
+    /// subtypes and an empty list turns attachments off. Photos are image/jpeg.
+    #[serde(default = "default_attachment_types")]
+    pub attachment_types: Vec<String>,
+    /// Days to keep received files in the inbox before they are deleted
+    /// (default: 30; 0 keeps them forever)
+    #[serde(default = "default_inbox_retention_days")]
+    pub inbox_retention_days: u64,
 }
 
 impl Default for GatewayConfig {
@@ -125,17 +225,18 @@ impl Default for GatewayConfig {
             append_system_prompt: String::new(),
             session_timeout_secs: default_session_timeout(),
             use_persistent_session: false,
-            tmux_session_name: default_tmux_session_name(),
+            tmux_session_name: None,
             prompt_marker: default_prompt_marker(),
             compact_interval_secs: default_compact_interval(),
             message_format: MessageFormat::default(),
+            max_attachment_bytes: default_max_attachment_bytes(),
+            attachment_types: default_attachment_types(),
+            inbox_retention_days: default_inbox_retention_days(),
         }
     }
 }
 
-fn default_tmux_session_name() -> String {
-    "cc-demon-gateway".to_string()
-}
+const DEFAULT_TMUX_SESSION_NAME: &str = "cc-demon-gateway";
 
 fn default_prompt_marker() -> String {
     "❯".to_string() // Claude Code uses ❯ (U+276F) as prompt
@@ -149,6 +250,20 @@ fn default_session_timeout() -> u64 {
     3600
 }
 
+fn default_max_attachment_bytes() -> u64 {
+    20 * 1024 * 1024
+}
+
+fn default_attachment_types() -> Vec<String> {
+    ["image/jpeg", "image/png", "image/gif", "image/webp", "application/pdf", "application/json", "text/*"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_inbox_retention_days() -> u64 {
+    30
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JobDefaults {
     #[serde(default = "default_model")]
@@ -161,6 +276,9 @@ pub struct JobDefaults {
     pub max_budget_usd: f64,
     #[serde(default = "default_output_format")]
     pub output_format: String,
+    /// IANA timezone for jobs without their own `timezone` (e.g. "Asia/Ho_Chi_Minh")
+    #[serde(default)]
+    pub timezone: Option<String>,
 }
 
 impl Default for JobDefaults {
@@ -171,6 +289,7 @@ impl Default for JobDefaults {
             max_turns: default_max_turns(),
             max_budget_usd: default_max_budget(),
             output_format: default_output_format(),
+            timezone: None,
         }
     }
 }
@@ -195,13 +314,32 @@ fn default_output_format() -> String {
 pub struct Job {
     pub id: String,
     pub name: String,
-    #[serde(default = "default_recurring")]
+    /// "recurring", "once" or "after" (run when `depends_on` jobs succeed)
+    #[serde(default = "default_recurring", alias = "trigger")]
     pub schedule_type: String,
     #[serde(default)]
     pub schedule: String,
     #[serde(default)]
     pub once_at: Option<String>,
+    /// Jobs whose successful runs trigger this one (`schedule_type = "after"`)
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// IANA timezone for `schedule` and naive `once_at` times
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
     pub prompt: String,
+    /// File whose contents are used as the prompt (relative to the demon
+    /// base directory); `prompt`, if also set, is appended to it
+    #[serde(default)]
+    pub prompt_file: Option<String>,
+    /// Commands whose output is appended to the prompt before each run
+    #[serde(default)]
+    pub context_commands: Vec<ContextCommand>,
+    /// Condition checked before each scheduled run; the run is skipped
+    /// when it doesn't hold
+    #[serde(default)]
+    pub run_if: Option<RunIf>,
     #[serde(default)]
     pub working_dir: String,
     #[serde(default = "default_model")]
@@ -222,12 +360,205 @@ pub struct Job {
     pub max_turns: u32,
     #[serde(default = "default_max_budget")]
     pub max_budget_usd: f64,
+    /// Cap on the job's spend per local calendar day (overrides
+    /// `[budget].job_daily_usd`)
+    #[serde(default)]
+    pub daily_budget_usd: Option<f64>,
+    /// Cap on the job's spend per local calendar month (overrides
+    /// `[budget].job_monthly_usd`)
+    #[serde(default)]
+    pub monthly_budget_usd: Option<f64>,
     #[serde(default = "default_output_format")]
     pub output_format: String,
     #[serde(default = "default_output_destinations")]
     pub output_destinations: Vec<String>,
+    /// Where failure notifications go (defaults to `output_destinations`)
+    #[serde(default)]
+    pub on_failure_destinations: Option<Vec<String>>,
+    /// Where a short notice goes when a run succeeds
+    #[serde(default)]
+    pub on_success_destinations: Vec<String>,
     #[serde(default = "default_true")]
     pub enabled: bool,
+    /// What to do about runs missed while the daemon was down or asleep
+    #[serde(default)]
+    pub catch_up: CatchUpPolicy,
+    /// Maximum number of missed runs replayed by `catch_up = "run-all"`
+    #[serde(default = "default_catch_up_max")]
+    pub catch_up_max: u32,
+    /// What to do when the job fires while a previous run is still going
+    #[serde(default)]
+    pub overlap: OverlapPolicy,
+    /// Extra attempts after a failed run
+    #[serde(default)]
+    pub retries: u32,
+    /// How the delay between attempts grows
+    #[serde(default)]
+    pub retry_backoff: RetryBackoff,
+    /// Delay before the first retry
+    #[serde(default = "default_retry_delay_secs")]
+    pub retry_delay_secs: u64,
+    /// Failures that are retried
+    #[serde(default = "default_retry_on")]
+    pub retry_on: Vec<RetryOn>,
+    /// Kill the run if it takes longer than this (no limit when unset)
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+/// A shell command whose output is appended to a job's prompt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextCommand {
+    /// Run with `sh -c` in the job's working directory
+    pub command: String,
+    /// Heading for the output in the prompt (defaults to the command)
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default = "default_context_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Output beyond this many bytes is cut off
+    #[serde(default = "default_context_max_bytes")]
+    pub max_bytes: usize,
+    #[serde(default)]
+    pub on_failure: ContextFailure,
+}
+
+/// Gate for a job run. When both are set, `command` must succeed and
+/// `path` must have changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunIf {
+    /// Shell command; the job runs only if it exits 0
+    #[serde(default)]
+    pub command: Option<String>,
+    /// File or directory that must have changed since the last run
+    #[serde(default)]
+    pub path: Option<String>,
+    /// How a change to `path` is detected
+    #[serde(default)]
+    pub check: ChangeCheck,
+    /// Timeout for `command`; a timeout skips the run
+    #[serde(default = "default_context_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// How `run_if.path` changes are detected.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeCheck {
+    /// Latest modification time (default)
+    #[default]
+    Mtime,
+    /// Hash of the contents
+    Hash,
+}
+
+/// What a failing or timed-out context command does to the run.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContextFailure {
+    /// Fail the run without starting claude (default)
+    #[default]
+    Fatal,
+    /// Leave the command's output out of the prompt and carry on
+    Ignore,
+}
+
+/// Policy for runs missed while the daemon was not running.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum CatchUpPolicy {
+    /// Drop missed runs (default)
+    #[default]
+    Skip,
+    /// Run once to make up for any number of missed runs
+    RunOnce,
+    /// Replay every missed run, up to `catch_up_max`
+    RunAll,
+}
+
+/// Policy for a job firing while its previous run is still in progress.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverlapPolicy {
+    /// Start another run alongside the previous one (default)
+    #[default]
+    Allow,
+    /// Drop the new run
+    Skip,
+    /// Start the new run once the previous one finishes
+    Queue,
+    /// Kill the previous run, then start the new one
+    KillPrevious,
+}
+
+/// Growth of the delay between retry attempts.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RetryBackoff {
+    /// Wait `retry_delay_secs` before every retry (default)
+    #[default]
+    Fixed,
+    /// Double the delay after each retry
+    Exponential,
+}
+
+/// Kinds of failure a job can be retried on.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RetryOn {
+    /// The claude CLI could not be started
+    SpawnError,
+    /// The claude CLI exited with a non-zero status
+    NonZeroExit,
+    /// The run exceeded its timeout
+    Timeout,
+    /// claude finished but reported `is_error` in its JSON result
+    IsError,
+}
+
+impl Job {
+    /// Timezone the job's schedule is evaluated in: the job's own
+    /// `timezone`, else `defaults.timezone`. `None` keeps the historic
+    /// behaviour of cron in UTC and naive `once_at` in system local time.
+    pub fn schedule_timezone(&self, defaults: &JobDefaults) -> Result<Option<Tz>> {
+        match self.timezone.as_ref().or(defaults.timezone.as_ref()) {
+            Some(name) => name
+                .parse::<Tz>()
+                .map(Some)
+                .map_err(|e| anyhow::anyhow!("Invalid timezone '{}': {}", name, e)),
+            None => Ok(None),
+        }
+    }
+
+    /// A copy of the job with one field set from `key=value` text. The
+    /// value is read as TOML, falling back to a plain string; an empty
+    /// value resets the field to its default. Unknown fields and values
+    /// of the wrong type are rejected.
+    pub fn with_field(&self, key: &str, value: &str) -> Result<Job> {
+        let key = if key == "trigger" { "schedule_type" } else { key };
+        let value = Some(value).filter(|v| !v.trim().is_empty());
+        keys::with_value(self, key, value)
+    }
+}
+
+fn default_catch_up_max() -> u32 {
+    5
+}
+
+fn default_context_timeout_secs() -> u64 {
+    30
+}
+
+fn default_context_max_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_retry_delay_secs() -> u64 {
+    30
+}
+
+fn default_retry_on() -> Vec<RetryOn> {
+    vec![RetryOn::SpawnError, RetryOn::NonZeroExit, RetryOn::Timeout]
 }
 
 fn default_recurring() -> String {
@@ -242,6 +573,144 @@ fn default_true() -> bool {
     true
 }
 
+/// Fail if the `depends_on` graph contains a cycle. Dependencies on jobs
+/// that don't exist are ignored here.
+pub fn check_dependencies(jobs: &[Job]) -> Result<()> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        Unvisited,
+        InProgress,
+        Done,
+    }
+
+    fn visit<'a>(
+        idx: usize,
+        jobs: &'a [Job],
+        marks: &mut [Mark],
+        path: &mut Vec<&'a str>,
+    ) -> Result<()> {
+        match marks[idx] {
+            Mark::Done => return Ok(()),
+            Mark::InProgress => {
+                let id = jobs[idx].id.as_str();
+                let start = path.iter().position(|p| *p == id).unwrap_or(0);
+                let mut cycle = path[start..].to_vec();
+                cycle.push(id);
+                anyhow::bail!("Job dependency cycle: {}", cycle.join(" -> "));
+            }
+            Mark::Unvisited => {}
+        }
+        marks[idx] = Mark::InProgress;
+        path.push(&jobs[idx].id);
+        for dep in &jobs[idx].depends_on {
+            if let Some(dep_idx) = jobs.iter().position(|j| &j.id == dep) {
+                visit(dep_idx, jobs, marks, path)?;
+            }
+        }
+        path.pop();
+        marks[idx] = Mark::Done;
+        Ok(())
+    }
+
+    let mut marks = vec![Mark::Unvisited; jobs.len()];
+    for idx in 0..jobs.len() {
+        visit(idx, jobs, &mut marks, &mut Vec::new())?;
+    }
+    Ok(())
+}
+
+/// IDs of the jobs that are part of a dependency cycle, i.e. that depend
+/// on themselves through `depends_on`.
+pub fn cyclic_jobs(jobs: &[Job]) -> HashSet<String> {
+    let depends_on = |id: &str| {
+        jobs.iter()
+            .find(|j| j.id == id)
+            .map_or(&[][..], |j| j.depends_on.as_slice())
+    };
+    jobs.iter()
+        .filter(|job| {
+            let mut seen = HashSet::new();
+            let mut stack: Vec<&str> = job.depends_on.iter().map(String::as_str).collect();
+            while let Some(id) = stack.pop() {
+                if id == job.id {
+                    return true;
+                }
+                if seen.insert(id) {
+                    stack.extend(depends_on(id).iter().map(String::as_str));
+                }
+            }
+            false
+        })
+        .map(|job| job.id.clone())
+        .collect()
+}
+
+/// Fail if `after` has a dependency cycle that `before` didn't, so a
+/// change can't add a cycle while a hand-edited one doesn't block every
+/// other change.
+pub fn check_new_cycles(before: &[Job], after: &[Job]) -> Result<()> {
+    let existing = cyclic_jobs(before);
+    let mut added: Vec<String> = cyclic_jobs(after).difference(&existing).cloned().collect();
+    if added.is_empty() {
+        return Ok(());
+    }
+    if existing.is_empty() {
+        // Name the cycle itself
+        check_dependencies(after)?;
+    }
+    added.sort();
+    anyhow::bail!("Job dependency cycle through {}", added.join(", "))
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SchedulerConfig {
+    /// Maximum number of jobs running at once across the daemon (0 = unlimited)
+    #[serde(default)]
+    pub max_concurrent_jobs: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ShutdownConfig {
+    /// Seconds running jobs and gateway messages get to finish when the
+    /// daemon stops, before they are cancelled
+    #[serde(default = "default_grace_secs")]
+    pub grace_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            grace_secs: default_grace_secs(),
+        }
+    }
+}
+
+fn default_grace_secs() -> u64 {
+    30
+}
+
+/// Caps on cumulative spend, in USD per local calendar day or month.
+/// Unset caps don't apply.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct BudgetConfig {
+    /// Everything: jobs, tasks and the gateway
+    #[serde(default)]
+    pub daily_usd: Option<f64>,
+    #[serde(default)]
+    pub monthly_usd: Option<f64>,
+    /// Each job, unless it sets its own `daily_budget_usd`/`monthly_budget_usd`,
+    /// and each task run with `demon task run`
+    #[serde(default)]
+    pub job_daily_usd: Option<f64>,
+    #[serde(default)]
+    pub job_monthly_usd: Option<f64>,
+    /// Each gateway chat, including the tasks it starts
+    #[serde(default)]
+    pub chat_daily_usd: Option<f64>,
+    #[serde(default)]
+    pub chat_monthly_usd: Option<f64>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct JobsFile {
     #[serde(default)]
@@ -249,9 +718,17 @@ struct JobsFile {
 }
 
 impl DemonConfig {
-    pub fn load() -> Result<Self> {
-        let default_paths = PathsConfig::default();
-        let config_file = default_paths.config_file();
+    /// Load config.toml from the directory of `paths`. The other files live
+    /// there too, unless config.toml sets its own `[paths].base_dir`; for a
+    /// profile that is the base directory its `profiles/<name>` is under,
+    /// as with `--base-dir`, so profiles never share their files.
+    pub fn load(paths: &PathsConfig) -> Result<Self> {
+        Ok(Self::read(paths)?.rooted_at(paths))
+    }
+
+    /// config.toml as written, without `rooted_at` applied.
+    fn read(paths: &PathsConfig) -> Result<Self> {
+        let config_file = paths.config_file();
 
         if config_file.exists() {
             let content = std::fs::read_to_string(&config_file)
@@ -262,16 +739,87 @@ impl DemonConfig {
         }
     }
 
-    #[allow(dead_code)]
-    pub fn save(&self) -> Result<()> {
-        let config_file = self.paths.config_file();
-        std::fs::create_dir_all(config_file.parent().unwrap())?;
-        let content = toml::to_string_pretty(self)?;
-        std::fs::write(&config_file, content)?;
-        Ok(())
+    fn rooted_at(mut self, paths: &PathsConfig) -> Self {
+        self.paths = match (self.paths.base_dir.take(), &paths.profile) {
+            (None, _) => paths.clone(),
+            (Some(base_dir), None) => PathsConfig {
+                base_dir: Some(base_dir),
+                profile: None,
+            },
+            (Some(base_dir), Some(name)) => PathsConfig {
+                base_dir: Some(base_dir),
+                profile: None,
+            }
+            .for_profile(name.clone()),
+        };
+        self
+    }
+
+    /// The gateway's tmux session: `gateway.tmux_session_name`, or a
+    /// default that differs per profile.
+    pub fn tmux_session_name(&self) -> String {
+        match (&self.gateway.tmux_session_name, &self.paths.profile) {
+            (Some(name), _) => name.clone(),
+            (None, Some(profile)) => format!("{}-{}", DEFAULT_TMUX_SESSION_NAME, profile),
+            (None, None) => DEFAULT_TMUX_SESSION_NAME.to_string(),
+        }
+    }
+
+    /// The setting at a dotted key such as `gateway.max_turns`, or `None`
+    /// if it is unset.
+    pub fn get(&self, key: &str) -> Result<Option<toml::Value>> {
+        keys::get(self, key)
     }
 
+    /// A copy for printing, with the secret settings that are set masked.
+    pub fn redacted(&self) -> Result<Self> {
+        let mut config = self.clone();
+        for key in SECRET_KEYS {
+            if config.get(key)?.is_some_and(|v| v.as_str() != Some("")) {
+                config = config.with_key(key, Some(REDACTED))?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// A copy of the config with the setting at a dotted key set from
+    /// text, or reset to its default when `value` is `None`.
+    pub fn with_key(&self, key: &str, value: Option<&str>) -> Result<Self> {
+        keys::with_value(self, key, value)
+    }
+
+    /// Load config.toml from `paths`, apply `f` and save the result,
+    /// holding the file lock throughout. Returns the saved config, loaded
+    /// as by `load`.
+    pub fn update(paths: &PathsConfig, f: impl FnOnce(Self) -> Result<Self>) -> Result<Self> {
+        let config_file = paths.config_file();
+        store::locked(&config_file, || {
+            let updated = f(Self::read(paths)?)?;
+            let content = toml::to_string_pretty(&updated)?;
+            store::write_atomic(&config_file, &content)?;
+            Ok(updated.rooted_at(paths))
+        })
+    }
+
+    /// The jobs to run: jobs.toml without the jobs in a dependency cycle,
+    /// which are skipped with a logged error.
     pub fn load_jobs(&self) -> Result<Vec<Job>> {
+        let mut jobs = self.read_jobs()?;
+        let cyclic = cyclic_jobs(&jobs);
+        if !cyclic.is_empty() {
+            let error = check_dependencies(&jobs).err().map(|e| e.to_string()).unwrap_or_default();
+            tracing::error!(
+                component = "config",
+                error = %error,
+                "Skipping jobs in a dependency cycle; fix depends_on in jobs.toml"
+            );
+            jobs.retain(|j| !cyclic.contains(&j.id));
+        }
+        Ok(jobs)
+    }
+
+    /// All jobs in jobs.toml, including any in a dependency cycle.
+    pub fn read_jobs(&self) -> Result<Vec<Job>> {
         let jobs_file = self.paths.jobs_file();
         if !jobs_file.exists() {
             return Ok(Vec::new());
@@ -282,24 +830,200 @@ impl DemonConfig {
         Ok(file.jobs)
     }
 
-    pub fn save_jobs(&self, jobs: &[Job]) -> Result<()> {
+    /// Load jobs.toml, apply `f` and save the result, holding the file
+    /// lock throughout so concurrent changes aren't lost. Nothing is saved
+    /// if `f` fails or adds a dependency cycle.
+    pub fn update_jobs<R>(&self, f: impl FnOnce(&mut Vec<Job>) -> Result<R>) -> Result<R> {
         let jobs_file = self.paths.jobs_file();
-        std::fs::create_dir_all(jobs_file.parent().unwrap())?;
-        let file = JobsFile {
-            jobs: jobs.to_vec(),
-        };
-        let content = toml::to_string_pretty(&file)?;
-        std::fs::write(&jobs_file, content)?;
-        Ok(())
+        store::locked(&jobs_file, || {
+            let mut jobs = self.read_jobs()?;
+            let before = jobs.clone();
+            let result = f(&mut jobs)?;
+            check_new_cycles(&before, &jobs)?;
+            let content = toml::to_string_pretty(&JobsFile { jobs })?;
+            store::write_atomic(&jobs_file, &content)?;
+            Ok(result)
+        })
+    }
+
+    /// `update_jobs` for async code: the file lock is waited for on a
+    /// blocking thread.
+    pub async fn update_jobs_async<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Vec<Job>) -> Result<R> + Send + 'static,
+    ) -> Result<R> {
+        let config = self.clone();
+        tokio::task::spawn_blocking(move || config.update_jobs(f))
+            .await
+            .context("Jobs file update failed")?
     }
 }
 
-impl Default for DemonConfig {
-    fn default() -> Self {
-        Self {
-            paths: PathsConfig::default(),
-            gateway: GatewayConfig::default(),
-            defaults: JobDefaults::default(),
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacted_masks_secrets() {
+        let mut config = DemonConfig::default();
+        assert_eq!(config.redacted().unwrap().gateway.bot_token, "");
+
+        config.gateway.bot_token = "123:secret".to_string();
+        config.gateway.max_turns = 3;
+        let redacted = config.redacted().unwrap();
+        assert_eq!(redacted.gateway.bot_token, REDACTED);
+        assert_eq!(redacted.gateway.max_turns, 3);
+    }
+
+    fn jobs(toml_str: &str) -> Vec<Job> {
+        toml::from_str::<JobsFile>(toml_str).unwrap().jobs
+    }
+
+    #[test]
+    fn test_with_field_parses_typed_values() {
+        let job = &jobs("[[jobs]]\nid = \"a\"\nname = \"A\"\nschedule = \"0 * * * * *\"\nprompt = \"hi\"")[0];
+
+        let job = job.with_field("retries", "3").unwrap();
+        assert_eq!(job.retries, 3);
+        let job = job.with_field("model", "opus").unwrap();
+        assert_eq!(job.model, "opus");
+        let job = job.with_field("name", "42").unwrap();
+        assert_eq!(job.name, "42");
+        let job = job.with_field("overlap", "skip").unwrap();
+        assert_eq!(job.overlap, OverlapPolicy::Skip);
+        let job = job.with_field("timeout_secs", "600").unwrap();
+        assert_eq!(job.timeout_secs, Some(600));
+        let job = job.with_field("timeout_secs", "").unwrap();
+        assert_eq!(job.timeout_secs, None);
+        let job = job.with_field("output_destinations", r#"["file", "telegram:1"]"#).unwrap();
+        assert_eq!(job.output_destinations.len(), 2);
+
+        assert!(job.with_field("retries", "many").is_err());
+        assert!(job.with_field("overlap", "sometimes").is_err());
+        assert!(job.with_field("colour", "red").is_err());
+    }
+
+    #[test]
+    fn test_check_dependencies_detects_cycle() {
+        let pipeline = jobs(
+            r#"
+            [[jobs]]
+            id = "fetch"
+            name = "Fetch"
+            schedule = "0 0 7 * * *"
+            prompt = "fetch"
+
+            [[jobs]]
+            id = "summarize"
+            name = "Summarize"
+            trigger = "after"
+            depends_on = ["fetch", "missing"]
+            prompt = "summarize"
+            "#,
+        );
+        assert_eq!(pipeline[1].schedule_type, "after");
+        assert!(check_dependencies(&pipeline).is_ok());
+
+        let cyclic = jobs(
+            r#"
+            [[jobs]]
+            id = "a"
+            name = "A"
+            schedule_type = "after"
+            depends_on = ["b"]
+            prompt = "a"
+
+            [[jobs]]
+            id = "b"
+            name = "B"
+            schedule_type = "after"
+            depends_on = ["a"]
+            prompt = "b"
+            "#,
+        );
+        let err = check_dependencies(&cyclic).unwrap_err().to_string();
+        assert_eq!(err, "Job dependency cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn test_load_jobs_skips_only_cyclic_jobs() {
+        let dir = std::env::temp_dir().join(format!("demon-config-{}", uuid::Uuid::new_v4()));
+        let config = DemonConfig {
+            paths: PathsConfig {
+                base_dir: Some(dir.to_string_lossy().to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        let job = |id: &str, deps: &str| {
+            format!("[[jobs]]\nid = \"{id}\"\nname = \"{id}\"\ntrigger = \"after\"\ndepends_on = [{deps}]\nprompt = \"p\"\n")
+        };
+        let content = [job("a", "\"b\""), job("b", "\"a\""), job("c", "\"a\""), job("d", "")].concat();
+        std::fs::write(config.paths.jobs_file(), content).unwrap();
+
+        let ids = |jobs: Vec<Job>| jobs.into_iter().map(|j| j.id).collect::<Vec<_>>();
+        assert_eq!(ids(config.load_jobs().unwrap()), vec!["c", "d"]);
+        assert_eq!(config.read_jobs().unwrap().len(), 4);
+
+        // Changes that leave the old cycle alone are saved; new cycles aren't
+        config
+            .update_jobs(|jobs| {
+                jobs.retain(|j| j.id != "c");
+                Ok(())
+            })
+            .unwrap();
+        let err = config
+            .update_jobs(|jobs| {
+                jobs[2].depends_on = vec!["d".to_string()];
+                Ok(())
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Job dependency cycle through d");
+        assert_eq!(ids(config.read_jobs().unwrap()), vec!["a", "b", "d"]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_load_uses_home_unless_config_sets_base_dir() {
+        let dir = std::env::temp_dir().join(format!("demon-config-{}", uuid::Uuid::new_v4()));
+        let home = PathsConfig::new(Some(dir.clone()), None).unwrap();
+
+        let config = DemonConfig::load(&home).unwrap();
+        assert_eq!(config.paths.pid_file(), dir.join("demon.pid"));
+
+        let config = DemonConfig::update(&home, |c| c.with_key("paths.base_dir", Some("/srv/demon"))).unwrap();
+        assert_eq!(config.paths.jobs_file(), PathBuf::from("/srv/demon/jobs.toml"));
+        assert_eq!(DemonConfig::load(&home).unwrap().paths.base_dir(), PathBuf::from("/srv/demon"));
+
+        // Profiles that set the same base_dir still keep their files apart
+        let profile_files = |name: &str| {
+            let home = PathsConfig::new(Some(dir.clone()), Some(name.to_string())).unwrap();
+            DemonConfig::update(&home, |c| c.with_key("paths.base_dir", Some("/srv/shared"))).unwrap();
+            DemonConfig::load(&home).unwrap().paths
+        };
+        let (work, personal) = (profile_files("work"), profile_files("personal"));
+        assert_eq!(work.jobs_file(), PathBuf::from("/srv/shared/profiles/work/jobs.toml"));
+        assert_eq!(work.profile.as_deref(), Some("work"));
+        assert_ne!(work.running_file(), personal.running_file());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_profile_paths_and_session_name() {
+        let home = PathsConfig::new(Some(PathBuf::from("/srv/demon")), Some("work".to_string())).unwrap();
+        assert_eq!(home.pid_file(), PathBuf::from("/srv/demon/profiles/work/demon.pid"));
+        assert!(PathsConfig::new(None, Some("../x".to_string())).is_err());
+
+        let mut config = DemonConfig {
+            paths: home,
+            ..Default::default()
+        };
+        assert_eq!(config.tmux_session_name(), "cc-demon-gateway-work");
+        config.gateway.tmux_session_name = Some("mine".to_string());
+        assert_eq!(config.tmux_session_name(), "mine");
+        assert_eq!(DemonConfig::default().tmux_session_name(), "cc-demon-gateway");
     }
 }
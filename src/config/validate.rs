@@ -0,0 +1,389 @@
+//! Checks config.toml, jobs.toml, agents.toml and tasks.toml together,
+//! reporting every problem with the file and line it was found on.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use super::{check_dependencies, DemonConfig, JobsFile, PathsConfig};
+use crate::output;
+use crate::scheduler;
+use crate::task::{AgentsFile, TasksFile};
+
+/// A problem found in a configuration file.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub path: PathBuf,
+    /// 1-based line, when the problem can be pinned to one
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// A file's text, for mapping problems back to lines.
+struct Source {
+    path: PathBuf,
+    text: String,
+}
+
+impl Source {
+    /// Read a file; `None` if it doesn't exist.
+    fn read(path: PathBuf, problems: &mut Vec<Problem>) -> Option<Self> {
+        if !path.exists() {
+            return None;
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Some(Self { path, text }),
+            Err(e) => {
+                problems.push(Problem {
+                    path,
+                    line: None,
+                    message: format!("Failed to read: {e}"),
+                });
+                None
+            }
+        }
+    }
+
+    fn parse<T: serde::de::DeserializeOwned>(&self, problems: &mut Vec<Problem>) -> Option<T> {
+        match toml::from_str(&self.text) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                let line = e.span().map(|span| self.line_at(span.start));
+                problems.push(self.problem(line, e.message().trim_end().to_string()));
+                None
+            }
+        }
+    }
+
+    fn problem(&self, line: Option<usize>, message: String) -> Problem {
+        Problem {
+            path: self.path.clone(),
+            line,
+            message,
+        }
+    }
+
+    fn line_at(&self, offset: usize) -> usize {
+        self.text[..offset.min(self.text.len())].matches('\n').count() + 1
+    }
+
+    /// Line of `key` in the `[table]` section, or of the section header
+    /// when the key isn't written out.
+    fn table_key_line(&self, table: &str, key: &str) -> Option<usize> {
+        let header = format!("[{table}]");
+        let lines: Vec<&str> = self.text.lines().collect();
+        let start = lines.iter().position(|l| l.trim() == header)?;
+        let end = lines[start + 1..]
+            .iter()
+            .position(|l| l.trim_start().starts_with('['))
+            .map_or(lines.len(), |i| start + 1 + i);
+        let found = (start + 1..end).find(|&i| is_key(lines[i], key));
+        Some(found.unwrap_or(start) + 1)
+    }
+
+    /// Line of `key` in the `[[array]]` entry whose `id` is `id`, or of
+    /// its `id` when the key isn't written out.
+    fn entry_key_line(&self, array: &str, id: &str, key: &str) -> Option<usize> {
+        let header = format!("[[{array}]]");
+        let lines: Vec<&str> = self.text.lines().collect();
+        let starts: Vec<usize> = (0..lines.len())
+            .filter(|&i| lines[i].trim() == header)
+            .collect();
+        for (n, &start) in starts.iter().enumerate() {
+            let end = starts.get(n + 1).copied().unwrap_or(lines.len());
+            // Sub-tables such as [[jobs.context_commands]] stay in the entry
+            let end = (start + 1..end)
+                .find(|&i| {
+                    let l = lines[i].trim_start();
+                    l.starts_with('[')
+                        && !l.starts_with(&format!("[{array}."))
+                        && !l.starts_with(&format!("[[{array}."))
+                })
+                .unwrap_or(end);
+            let Some(id_line) = (start + 1..end).find(|&i| is_key(lines[i], "id") && has_string(lines[i], id))
+            else {
+                continue;
+            };
+            let found = (start + 1..end).find(|&i| is_key(lines[i], key));
+            return Some(found.unwrap_or(id_line) + 1);
+        }
+        None
+    }
+}
+
+fn is_key(line: &str, key: &str) -> bool {
+    line.trim_start()
+        .strip_prefix(key)
+        .is_some_and(|rest| rest.trim_start().starts_with('='))
+}
+
+fn has_string(line: &str, value: &str) -> bool {
+    line.contains(&format!("\"{value}\"")) || line.contains(&format!("'{value}'"))
+}
+
+/// Whether a path that may contain template variables points nowhere.
+/// Templated paths are only known at run time and are not checked.
+fn missing_dir(path: &str) -> bool {
+    if path.is_empty() || path.contains('{') {
+        return false;
+    }
+    let expanded = match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().map(|h| h.join(rest)).unwrap_or_else(|| PathBuf::from(path)),
+        None => PathBuf::from(path),
+    };
+    !expanded.is_dir()
+}
+
+/// Check all configuration files under `paths`, returning every problem
+/// found. Missing files are not problems.
+pub fn validate_all(paths: &PathsConfig) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let config_source = Source::read(paths.config_file(), &mut problems);
+    let config = config_source
+        .as_ref()
+        .and_then(|src| src.parse::<DemonConfig>(&mut problems))
+        .unwrap_or_default()
+        .rooted_at(paths);
+    if let Some(src) = &config_source {
+        check_config(&config, src, &mut problems);
+    }
+
+    let base_dir = config.paths.base_dir();
+    let mut job_ids = HashSet::new();
+    if let Some(src) = Source::read(config.paths.jobs_file(), &mut problems) {
+        if let Some(file) = src.parse::<JobsFile>(&mut problems) {
+            let all_ids: HashSet<&str> = file.jobs.iter().map(|j| j.id.as_str()).collect();
+            for job in &file.jobs {
+                let line = |key: &str| src.entry_key_line("jobs", &job.id, key);
+                let mut report = |key: &str, message: String| {
+                    problems.push(src.problem(line(key), format!("job '{}': {}", job.id, message)));
+                };
+
+                if !job_ids.insert(job.id.clone()) {
+                    report("id", "duplicate job ID".to_string());
+                }
+                for (field, message) in scheduler::job_problems(job, &config) {
+                    report(field, message);
+                }
+                let destinations = [
+                    ("output_destinations", Some(&job.output_destinations)),
+                    ("on_failure_destinations", job.on_failure_destinations.as_ref()),
+                    ("on_success_destinations", Some(&job.on_success_destinations)),
+                ];
+                for (field, dests) in destinations {
+                    for dest in dests.into_iter().flatten() {
+                        if let Err(e) = output::check_destination(dest) {
+                            report(field, format!("{e:#}"));
+                        }
+                    }
+                }
+                for dep in job.depends_on.iter().filter(|d| !all_ids.contains(d.as_str())) {
+                    report("depends_on", format!("depends on unknown job '{dep}'"));
+                }
+                if missing_dir(&job.working_dir) {
+                    report("working_dir", format!("working_dir '{}' does not exist", job.working_dir));
+                }
+                if let Some(prompt_file) = &job.prompt_file {
+                    let path = base_dir.join(prompt_file);
+                    if !prompt_file.contains('{') && !prompt_file.starts_with("~/") && !path.is_file() {
+                        report("prompt_file", format!("prompt_file '{}' not found", path.display()));
+                    }
+                }
+            }
+            if let Err(e) = check_dependencies(&file.jobs) {
+                problems.push(src.problem(None, e.to_string()));
+            }
+        }
+    }
+
+    let mut agent_ids = HashSet::new();
+    // Unknown agents can't be told apart when agents.toml doesn't parse
+    let mut agents_known = true;
+    if let Some(src) = Source::read(config.paths.agents_file(), &mut problems) {
+        let parsed = src.parse::<AgentsFile>(&mut problems);
+        agents_known = parsed.is_some();
+        if let Some(file) = parsed {
+            for agent in &file.agents {
+                let line = |key: &str| src.entry_key_line("agents", &agent.id, key);
+                if !agent_ids.insert(agent.id.clone()) {
+                    problems.push(src.problem(line("id"), format!("agent '{}': duplicate agent ID", agent.id)));
+                }
+                if missing_dir(&agent.working_dir) {
+                    problems.push(src.problem(
+                        line("working_dir"),
+                        format!("agent '{}': working_dir '{}' does not exist", agent.id, agent.working_dir),
+                    ));
+                }
+            }
+        }
+    }
+
+    if let Some(src) = Source::read(config.paths.tasks_file(), &mut problems) {
+        if let Some(file) = src.parse::<TasksFile>(&mut problems) {
+            let mut task_ids = HashSet::new();
+            for t in &file.tasks {
+                let line = |key: &str| src.entry_key_line("tasks", &t.id, key);
+                if !task_ids.insert(t.id.clone()) {
+                    problems.push(src.problem(line("id"), format!("task '{}': duplicate task ID", t.id)));
+                }
+                if agents_known && !agent_ids.contains(&t.agent_id) {
+                    problems.push(src.problem(
+                        line("agent_id"),
+                        format!("task '{}': unknown agent '{}'", t.id, t.agent_id),
+                    ));
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+/// Problems with the settings in `config`, as they would be saved to
+/// `path`; lines refer to that serialization.
+pub fn config_problems(config: &DemonConfig, path: PathBuf) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let src = Source {
+        path,
+        text: toml::to_string_pretty(config).unwrap_or_default(),
+    };
+    check_config(config, &src, &mut problems);
+    problems
+}
+
+fn check_config(config: &DemonConfig, src: &Source, problems: &mut Vec<Problem>) {
+    if config.gateway.enabled && config.gateway.bot_token.is_empty() {
+        problems.push(src.problem(
+            src.table_key_line("gateway", "bot_token"),
+            "gateway is enabled but bot_token is empty".to_string(),
+        ));
+    }
+    for mime in &config.gateway.attachment_types {
+        let valid = mime
+            .split_once('/')
+            .is_some_and(|(kind, sub)| !kind.is_empty() && !sub.is_empty() && !sub.contains('/'));
+        if !valid {
+            problems.push(src.problem(
+                src.table_key_line("gateway", "attachment_types"),
+                format!("invalid MIME type '{mime}' in attachment_types (expected e.g. \"image/png\" or \"text/*\")"),
+            ));
+        }
+    }
+    if let Some(tz) = &config.defaults.timezone {
+        if tz.parse::<chrono_tz::Tz>().is_err() {
+            problems.push(src.problem(
+                src.table_key_line("defaults", "timezone"),
+                format!("invalid timezone '{tz}'"),
+            ));
+        }
+    }
+    let budget = &config.budget;
+    let caps = [
+        ("daily_usd", budget.daily_usd),
+        ("monthly_usd", budget.monthly_usd),
+        ("job_daily_usd", budget.job_daily_usd),
+        ("job_monthly_usd", budget.job_monthly_usd),
+        ("chat_daily_usd", budget.chat_daily_usd),
+        ("chat_monthly_usd", budget.chat_monthly_usd),
+    ];
+    for (key, cap) in caps {
+        if cap.is_some_and(|c| c < 0.0) {
+            problems.push(src.problem(
+                src.table_key_line("budget", key),
+                format!("budget.{key} must not be negative"),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_paths(files: &[(&str, &str)]) -> PathsConfig {
+        let dir = std::env::temp_dir().join(format!("demon-validate-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base_dir = dir.to_string_lossy().to_string();
+        for (name, content) in files {
+            let content = content.replace("BASE_DIR", &base_dir);
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        PathsConfig {
+            base_dir: Some(base_dir),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_config_problems() {
+        let config = DemonConfig::default();
+        assert!(config_problems(&config, PathBuf::from("config.toml")).is_empty());
+
+        let config = config.with_key("defaults.timezone", Some("Mars/Olympus")).unwrap();
+        let problems = config_problems(&config, PathBuf::from("config.toml"));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].to_string().ends_with("invalid timezone 'Mars/Olympus'"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn test_reports_problems_with_lines() {
+        let paths = temp_paths(&[
+            ("config.toml", "[paths]\nbase_dir = \"BASE_DIR\"\n"),
+            (
+                "jobs.toml",
+                r#"[[jobs]]
+id = "a"
+name = "A"
+schedule = "not cron"
+prompt = "hi"
+
+[[jobs]]
+id = "b"
+name = "B"
+schedule = "0 0 9 * * *"
+prompt = "hi"
+output_destinations = ["file", "slack"]
+"#,
+            ),
+            ("agents.toml", "[[agents]]\nid = \"dev\"\nname = \"Dev\"\nworking_dir = \"/nonexistent/dir\"\n"),
+            ("tasks.toml", "[[tasks]]\nid = \"t\"\nname = \"T\"\ndescription = \"d\"\nagent_id = \"ghost\"\n"),
+        ]);
+
+        let problems: Vec<String> = validate_all(&paths)
+            .iter()
+            .map(|p| {
+                let file = p.path.file_name().unwrap().to_string_lossy().to_string();
+                format!("{}:{}", file, p.line.unwrap_or(0))
+            })
+            .collect();
+        assert_eq!(
+            problems,
+            ["jobs.toml:4", "jobs.toml:12", "agents.toml:4", "tasks.toml:5"]
+        );
+
+        let _ = std::fs::remove_dir_all(paths.base_dir());
+    }
+
+    #[test]
+    fn test_parse_error_has_line() {
+        let paths = temp_paths(&[
+            ("config.toml", "[paths]\nbase_dir = \"BASE_DIR\"\n"),
+            ("jobs.toml", "[[jobs]]\nid = \"a\"\nname = \"A\"\nretries = \"x\"\nprompt = \"hi\"\n"),
+        ]);
+
+        let problems = validate_all(&paths);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line, Some(4));
+
+        let _ = std::fs::remove_dir_all(paths.base_dir());
+    }
+}
@@ -1,21 +1,27 @@
+mod reload;
+mod shutdown;
+
 use anyhow::{Context, Result};
 use std::fs;
-use std::path::PathBuf;
+use tokio::time::Duration;
 
 use crate::config::{DemonConfig, PathsConfig};
 use crate::logging;
 
-fn pid_file() -> PathBuf {
-    PathsConfig::default().pid_file()
-}
+pub use reload::spawn_reload_handler;
+pub use shutdown::Shutdown;
+
+/// How long cancelled work and the gateway get to stop once the grace
+/// period is over.
+const ABORT_WAIT_SECS: u64 = 10;
 
-pub fn is_running() -> Result<bool> {
-    let pid_path = pid_file();
+pub fn is_running(paths: &PathsConfig) -> Result<bool> {
+    let pid_path = paths.pid_file();
     if !pid_path.exists() {
         return Ok(false);
     }
 
-    let pid = read_pid()?;
+    let pid = read_pid(paths)?;
 
     // Check if process is actually running
     #[cfg(unix)]
@@ -40,23 +46,24 @@ pub fn is_running() -> Result<bool> {
     }
 }
 
-pub fn read_pid() -> Result<i32> {
-    let content = fs::read_to_string(pid_file()).context("Failed to read PID file")?;
+pub fn read_pid(paths: &PathsConfig) -> Result<i32> {
+    let content = fs::read_to_string(paths.pid_file()).context("Failed to read PID file")?;
     content
         .trim()
         .parse()
         .context("Invalid PID in PID file")
 }
 
-pub fn remove_pid() -> Result<()> {
-    let pid_path = pid_file();
+pub fn remove_pid(paths: &PathsConfig) -> Result<()> {
+    let pid_path = paths.pid_file();
     if pid_path.exists() {
         fs::remove_file(&pid_path)?;
     }
     Ok(())
 }
 
-pub fn stop_daemon(pid: i32) -> Result<()> {
+/// Send SIGTERM and wait up to `wait` for the daemon to exit.
+pub fn stop_daemon(paths: &PathsConfig, pid: i32, wait: Duration) -> Result<()> {
     #[cfg(unix)]
     {
         use nix::sys::signal::{self, Signal};
@@ -71,15 +78,22 @@ pub fn stop_daemon(pid: i32) -> Result<()> {
     }
 
     // Wait for process to exit
-    for _ in 0..30 {
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        if !is_running().unwrap_or(false) {
-            remove_pid()?;
+    let started = std::time::Instant::now();
+    while started.elapsed() < wait {
+        std::thread::sleep(Duration::from_millis(100));
+        if !is_running(paths).unwrap_or(false) {
+            remove_pid(paths)?;
             return Ok(());
         }
     }
 
-    anyhow::bail!("Daemon did not stop within 3 seconds")
+    anyhow::bail!("Daemon did not stop within {} seconds", wait.as_secs())
+}
+
+/// How long `stop_daemon` should wait: the shutdown grace period plus
+/// time to cancel what is left.
+pub fn stop_wait(config: &DemonConfig) -> Duration {
+    Duration::from_secs(config.shutdown.grace_secs + 2 * ABORT_WAIT_SECS)
 }
 
 pub fn signal_reload(pid: i32) -> Result<()> {
@@ -100,16 +114,136 @@ pub fn signal_reload(pid: i32) -> Result<()> {
     }
 }
 
-fn init_daemon_logging() -> Result<tracing_appender::non_blocking::WorkerGuard> {
-    logging::init_daemon_logging()
+fn init_daemon_logging(paths: &PathsConfig) -> Result<tracing_appender::non_blocking::WorkerGuard> {
+    logging::init_daemon_logging(paths)
+}
+
+/// Run the scheduler and, if `with_gateway`, the gateway until SIGTERM or
+/// Ctrl-C, then shut down gracefully: stop starting new work, give running
+/// jobs and gateway messages `[shutdown].grace_secs` to finish, cancel the
+/// rest and let the gateway close its persistent session.
+pub async fn serve(home: &PathsConfig, config: DemonConfig, with_gateway: bool) -> Result<()> {
+    // Register before starting work so an early SIGTERM isn't fatal
+    let terminated = terminated()?;
+
+    let config_rx = spawn_reload_handler(home.clone(), config)?;
+    let shutdown = Shutdown::new();
+
+    // Shared so chat-triggered runs follow the same overlap and concurrency rules
+    let running = crate::scheduler::RunningJobs::new(&config_rx.borrow(), shutdown.clone());
+
+    let mut scheduler_handle = tokio::spawn({
+        let config_rx = config_rx.clone();
+        let running = running.clone();
+        async move {
+            if let Err(e) = crate::scheduler::run(config_rx, running).await {
+                tracing::error!(component = "daemon", error = %e, "Scheduler error");
+            }
+        }
+    });
+
+    let mut gateway_handle = if with_gateway {
+        tracing::info!(component = "daemon", "Starting gateway");
+        Some(tokio::spawn({
+            let config_rx = config_rx.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                if let Err(e) = crate::gateway::run(config_rx, shutdown, Some(running)).await {
+                    tracing::error!(component = "daemon", error = %e, "Gateway error");
+                }
+            }
+        }))
+    } else {
+        None
+    };
+
+    // Run until asked to stop; the tasks don't exit under normal operation
+    tokio::select! {
+        _ = terminated => {
+            tracing::info!(component = "daemon", "Shutdown requested");
+        }
+        result = &mut scheduler_handle => {
+            tracing::error!(component = "daemon", result = ?result, "Scheduler unexpectedly exited");
+        }
+        result = async {
+            match gateway_handle.as_mut() {
+                Some(h) => h.await,
+                None => std::future::pending().await,
+            }
+        } => {
+            tracing::error!(component = "daemon", result = ?result, "Gateway unexpectedly exited");
+        }
+    }
+
+    let grace = Duration::from_secs(config_rx.borrow().shutdown.grace_secs);
+    wind_down(&shutdown, grace).await;
+
+    // The gateway shuts down its persistent session before returning
+    if let Some(handle) = gateway_handle.filter(|h| !h.is_finished()) {
+        if tokio::time::timeout(Duration::from_secs(ABORT_WAIT_SECS), handle).await.is_err() {
+            tracing::warn!(component = "daemon", "Gateway did not stop in time");
+        }
+    }
+    scheduler_handle.abort();
+
+    tracing::info!(component = "daemon", "Shutdown complete");
+    Ok(())
+}
+
+/// Resolves on SIGTERM or Ctrl-C. The handlers are installed when this is
+/// called, not when the future is first polled.
+pub fn terminated() -> Result<impl std::future::Future<Output = ()>> {
+    #[cfg(unix)]
+    let mut term = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .context("Failed to install SIGTERM handler")?;
+    Ok(async move {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = term.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+    })
+}
+
+/// Stop starting new work, give work in progress `grace` to finish,
+/// cancel the rest and mark shutdown finished.
+pub async fn wind_down(shutdown: &Shutdown, grace: Duration) {
+    shutdown.drain();
+    tracing::info!(
+        component = "daemon",
+        active = shutdown.active(),
+        grace_secs = grace.as_secs(),
+        "Waiting for running jobs and messages"
+    );
+    if tokio::time::timeout(grace, shutdown.idle()).await.is_err() {
+        tracing::warn!(
+            component = "daemon",
+            active = shutdown.active(),
+            "Grace period over, cancelling remaining work"
+        );
+        shutdown.abort();
+        let wait = Duration::from_secs(ABORT_WAIT_SECS);
+        if tokio::time::timeout(wait, shutdown.idle()).await.is_err() {
+            tracing::error!(
+                component = "daemon",
+                active = shutdown.active(),
+                "Work still running after cancellation, exiting anyway"
+            );
+        }
+    }
+    shutdown.finish();
 }
 
-pub fn daemonize(with_gateway: bool) -> Result<()> {
+/// Fork into the background and run the scheduler (and gateway). `home`
+/// is where config.toml is read from; `config` was loaded from it.
+pub fn daemonize(home: &PathsConfig, config: DemonConfig, with_gateway: bool) -> Result<()> {
     #[cfg(unix)]
     {
         use daemonize::Daemonize;
 
-        let paths = PathsConfig::default();
+        let paths = config.paths.clone();
         let base = paths.base_dir();
         fs::create_dir_all(&base)?;
         fs::create_dir_all(paths.logs_dir())?;
@@ -129,53 +263,18 @@ pub fn daemonize(with_gateway: bool) -> Result<()> {
         // We're now in the daemon process
         let rt = tokio::runtime::Runtime::new()?;
         rt.block_on(async {
-            let config = DemonConfig::load()?;
-
             // Initialize logging for daemon mode
-            let _guard = init_daemon_logging()?;
-            tracing::info!(component = "daemon", pid = std::process::id(), "Daemon started");
-
-            let scheduler_handle = tokio::spawn({
-                let config = config.clone();
-                async move {
-                    if let Err(e) = crate::scheduler::run(config).await {
-                        tracing::error!(component = "daemon", error = %e, "Scheduler error");
-                    }
-                }
-            });
-
-            let gateway_handle = if with_gateway {
-                tracing::info!(component = "daemon", "Starting gateway in daemon mode");
-                Some(tokio::spawn({
-                    let config = config.clone();
-                    async move {
-                        if let Err(e) = crate::gateway::run(config).await {
-                            tracing::error!(component = "daemon", error = %e, "Gateway error");
-                        }
-                    }
-                }))
-            } else {
-                None
-            };
-
-            // Wait for either task to complete (they shouldn't under normal operation)
-            tokio::select! {
-                result = scheduler_handle => {
-                    tracing::error!(component = "daemon", result = ?result, "Scheduler unexpectedly exited");
-                }
-                result = async {
-                    if let Some(h) = gateway_handle {
-                        h.await
-                    } else {
-                        // If no gateway, just wait forever
-                        std::future::pending::<Result<(), tokio::task::JoinError>>().await
-                    }
-                } => {
-                    tracing::error!(component = "daemon", result = ?result, "Gateway unexpectedly exited");
-                }
-            }
+            let _guard = init_daemon_logging(&paths)?;
+            tracing::info!(
+                component = "daemon",
+                pid = std::process::id(),
+                profile = paths.profile.as_deref().unwrap_or("default"),
+                "Daemon started"
+            );
 
-            Ok::<(), anyhow::Error>(())
+            let result = serve(home, config, with_gateway).await;
+            remove_pid(&paths)?;
+            result
         })?;
     }
 
@@ -0,0 +1,248 @@
+//! SIGHUP hot-reload of config.toml, jobs.toml, agents.toml and tasks.toml.
+//!
+//! The live config is published through a `watch` channel. On SIGHUP all
+//! files are re-read and validated; if anything fails to parse the old
+//! config is kept, otherwise the new one is swapped in and a diff of what
+//! changed is logged.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::watch;
+
+use crate::config::{self, DemonConfig, Job, PathsConfig};
+use crate::scheduler;
+use crate::task::{self, AgentProfile, TaskDefinition};
+
+/// Everything that is re-read on reload, kept for diffing.
+struct Snapshot {
+    config: DemonConfig,
+    jobs: Vec<Job>,
+    agents: Vec<AgentProfile>,
+    tasks: Vec<TaskDefinition>,
+}
+
+impl Snapshot {
+    /// Load and validate all configuration files.
+    fn load(home: &PathsConfig) -> Result<Self> {
+        let config = DemonConfig::load(home)?;
+        let jobs = config.load_jobs()?;
+        let agents = task::load_agents(&config)?;
+        let tasks = task::load_tasks(&config)?;
+
+        let mut seen = std::collections::HashSet::new();
+        for job in &jobs {
+            if !seen.insert(job.id.as_str()) {
+                anyhow::bail!("Duplicate job ID '{}'", job.id);
+            }
+            if job.enabled {
+                scheduler::validate_job(job, &config)?;
+            }
+        }
+
+        for job in &jobs {
+            for dep in job.depends_on.iter().filter(|d| !jobs.iter().any(|j| &j.id == *d)) {
+                tracing::warn!(
+                    component = "daemon",
+                    job_id = %job.id,
+                    depends_on = %dep,
+                    "Job depends on unknown job"
+                );
+            }
+        }
+
+        for t in &tasks {
+            if !agents.iter().any(|a| a.id == t.agent_id) {
+                tracing::warn!(
+                    component = "daemon",
+                    task_id = %t.id,
+                    agent_id = %t.agent_id,
+                    "Task references unknown agent"
+                );
+            }
+        }
+
+        Ok(Self {
+            config,
+            jobs,
+            agents,
+            tasks,
+        })
+    }
+
+    /// Describe the differences between two snapshots, one line per change.
+    fn diff(&self, new: &Snapshot) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        diff_values(
+            "config",
+            &to_value(&self.config),
+            &to_value(&new.config),
+            &mut changes,
+        );
+
+        diff_by_id("job", &self.jobs, &new.jobs, |j| &j.id, &mut changes);
+        diff_by_id("agent", &self.agents, &new.agents, |a| &a.id, &mut changes);
+        diff_by_id("task", &self.tasks, &new.tasks, |t| &t.id, &mut changes);
+
+        changes
+    }
+}
+
+fn to_value<T: Serialize>(v: &T) -> Value {
+    serde_json::to_value(v).unwrap_or(Value::Null)
+}
+
+fn diff_values(path: &str, old: &Value, new: &Value, out: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(o), Value::Object(n)) => {
+            for (key, old_v) in o {
+                let child = format!("{path}.{key}");
+                match n.get(key) {
+                    Some(new_v) => diff_values(&child, old_v, new_v, out),
+                    None => out.push(format!("{child} removed")),
+                }
+            }
+            for key in n.keys().filter(|k| !o.contains_key(*k)) {
+                out.push(format!("{path}.{key} added"));
+            }
+        }
+        _ if old != new && is_secret(path) => out.push(format!("{path} changed")),
+        _ if old != new => out.push(format!("{path}: {old} -> {new}")),
+        _ => {}
+    }
+}
+
+/// Whether `path` (under `config.`) is a setting whose value must never
+/// be written to the logs.
+fn is_secret(path: &str) -> bool {
+    path.strip_prefix("config.").is_some_and(|key| config::SECRET_KEYS.contains(&key))
+}
+
+fn diff_by_id<T: Serialize>(
+    kind: &str,
+    old: &[T],
+    new: &[T],
+    id: impl Fn(&T) -> &String,
+    out: &mut Vec<String>,
+) {
+    for o in old {
+        match new.iter().find(|n| id(n) == id(o)) {
+            Some(n) if to_value(o) != to_value(n) => out.push(format!("{kind} '{}' changed", id(o))),
+            Some(_) => {}
+            None => out.push(format!("{kind} '{}' removed", id(o))),
+        }
+    }
+    for n in new.iter().filter(|n| !old.iter().any(|o| id(o) == id(n))) {
+        out.push(format!("{kind} '{}' added", id(n)));
+    }
+}
+
+/// Publish `config` as the live config and, on Unix, install a SIGHUP
+/// handler that reloads it from `home`. Must be called from within a
+/// tokio runtime.
+pub fn spawn_reload_handler(
+    home: PathsConfig,
+    config: DemonConfig,
+) -> Result<watch::Receiver<DemonConfig>> {
+    let (tx, rx) = watch::channel(config);
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        // Register before returning so an early SIGHUP can't kill the daemon
+        let mut hangup =
+            signal(SignalKind::hangup()).context("Failed to install SIGHUP handler")?;
+
+        tokio::spawn(async move {
+            let mut current = match Snapshot::load(&home) {
+                Ok(s) => s,
+                Err(e) => {
+                    tracing::warn!(component = "daemon", error = %e, "Initial config snapshot invalid");
+                    Snapshot {
+                        config: tx.borrow().clone(),
+                        jobs: Vec::new(),
+                        agents: Vec::new(),
+                        tasks: Vec::new(),
+                    }
+                }
+            };
+
+            while hangup.recv().await.is_some() {
+                tracing::info!(component = "daemon", "SIGHUP received, reloading configuration");
+
+                let new = match Snapshot::load(&home) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        tracing::error!(
+                            component = "daemon",
+                            error = %format!("{e:#}"),
+                            "Config reload failed, keeping previous configuration"
+                        );
+                        continue;
+                    }
+                };
+
+                let changes = current.diff(&new);
+                if changes.is_empty() {
+                    tracing::info!(component = "daemon", "Config reloaded, no changes");
+                } else {
+                    for change in &changes {
+                        tracing::info!(component = "daemon", change = %change, "Config changed");
+                    }
+                    tracing::info!(component = "daemon", changes = changes.len(), "Config reloaded");
+                }
+
+                tx.send_replace(new.config.clone());
+                current = new;
+            }
+        });
+    }
+
+    // No SIGHUP on this platform; the initial config stays live
+    #[cfg(not(unix))]
+    drop(tx);
+
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_values_reports_nested_changes_and_hides_token() {
+        let mut old = DemonConfig::default();
+        old.gateway.bot_token = "secret-1".to_string();
+        let mut new = old.clone();
+        new.gateway.bot_token = "secret-2".to_string();
+        new.gateway.max_turns = 20;
+
+        let mut changes = Vec::new();
+        diff_values("config", &to_value(&old), &to_value(&new), &mut changes);
+
+        assert_eq!(
+            changes,
+            vec![
+                "config.gateway.bot_token changed",
+                "config.gateway.max_turns: 10 -> 20"
+            ]
+        );
+        assert!(!changes.iter().any(|c| c.contains("secret")));
+    }
+
+    #[test]
+    fn test_diff_by_id() {
+        let old = vec![("a".to_string(), 1), ("b".to_string(), 2)];
+        let new = vec![("a".to_string(), 5), ("c".to_string(), 3)];
+
+        let mut changes = Vec::new();
+        diff_by_id("job", &old, &new, |j| &j.0, &mut changes);
+
+        assert_eq!(
+            changes,
+            vec!["job 'a' changed", "job 'b' removed", "job 'c' added"]
+        );
+    }
+}
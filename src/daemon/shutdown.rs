@@ -0,0 +1,153 @@
+//! Coordinated shutdown of the scheduler and gateway.
+//!
+//! On SIGTERM or Ctrl-C the daemon first drains: no new job runs or
+//! gateway messages are started, and work already in progress gets
+//! `[shutdown].grace_secs` to finish. Whatever is still running after that
+//! is aborted: job runs are cancelled, which kills claude's process group,
+//! and gateway requests are dropped with a note to the chat. Finally the
+//! gateway stops its persistent session.
+
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Phase {
+    Running,
+    /// Finish in-progress work, start nothing new
+    Draining,
+    /// Cancel in-progress work
+    Aborting,
+    /// All work is done or cancelled; release remaining resources
+    Finished,
+}
+
+/// Shutdown signal shared by everything that does work for the daemon.
+#[derive(Clone)]
+pub struct Shutdown {
+    phase: Arc<watch::Sender<Phase>>,
+    active: Arc<watch::Sender<usize>>,
+}
+
+/// Marks a piece of work in progress; the daemon waits for it to be
+/// dropped before finishing shutdown.
+pub struct Work {
+    active: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for Work {
+    fn drop(&mut self) {
+        self.active.send_modify(|n| *n -= 1);
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            phase: Arc::new(watch::channel(Phase::Running).0),
+            active: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    fn advance(&self, to: Phase) {
+        self.phase.send_if_modified(|phase| {
+            let advanced = *phase < to;
+            if advanced {
+                *phase = to;
+            }
+            advanced
+        });
+    }
+
+    async fn reached(&self, phase: Phase) {
+        let mut rx = self.phase.subscribe();
+        // The sender lives as long as `self`, so this can't fail
+        let _ = rx.wait_for(|p| *p >= phase).await;
+    }
+
+    /// Stop accepting new work.
+    pub fn drain(&self) {
+        self.advance(Phase::Draining);
+    }
+
+    /// Cancel work still in progress.
+    pub fn abort(&self) {
+        self.advance(Phase::Aborting);
+    }
+
+    /// Shutdown is complete apart from releasing resources.
+    pub fn finish(&self) {
+        self.advance(Phase::Finished);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.phase.borrow() >= Phase::Draining
+    }
+
+    /// Resolves once shutdown has begun.
+    pub async fn draining(&self) {
+        self.reached(Phase::Draining).await
+    }
+
+    /// Resolves once in-progress work must be cancelled.
+    pub async fn aborted(&self) {
+        self.reached(Phase::Aborting).await
+    }
+
+    /// Resolves once all work is done or cancelled.
+    pub async fn finished(&self) {
+        self.reached(Phase::Finished).await
+    }
+
+    /// Register work in progress until the returned guard is dropped.
+    pub fn track(&self) -> Work {
+        self.active.send_modify(|n| *n += 1);
+        Work {
+            active: self.active.clone(),
+        }
+    }
+
+    /// Number of pieces of work in progress.
+    pub fn active(&self) -> usize {
+        *self.active.borrow()
+    }
+
+    /// Resolves when no work is in progress.
+    pub async fn idle(&self) {
+        let mut rx = self.active.subscribe();
+        let _ = rx.wait_for(|n| *n == 0).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{timeout, Duration};
+
+    #[tokio::test]
+    async fn test_idle_waits_for_tracked_work() {
+        let shutdown = Shutdown::new();
+        let work = shutdown.track();
+        shutdown.drain();
+        assert!(shutdown.is_draining());
+        assert_eq!(shutdown.active(), 1);
+        assert!(timeout(Duration::from_millis(50), shutdown.idle()).await.is_err());
+
+        drop(work);
+        timeout(Duration::from_secs(1), shutdown.idle()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_phases_only_advance() {
+        let shutdown = Shutdown::new();
+        shutdown.abort();
+        shutdown.drain();
+        timeout(Duration::from_secs(1), shutdown.aborted()).await.unwrap();
+        assert!(timeout(Duration::from_millis(50), shutdown.finished()).await.is_err());
+    }
+}
@@ -51,10 +51,10 @@ fn convert_markdown_to_v2(text: &str) -> String {
         }
 
         // Handle blockquotes: > text -> (just remove the >)
-        let line = if line.starts_with("> ") {
-            &line[2..]
-        } else if line.starts_with(">") {
-            &line[1..]
+        let line = if let Some(stripped) = line.strip_prefix("> ") {
+            stripped
+        } else if let Some(stripped) = line.strip_prefix('>') {
+            stripped
         } else {
             line
         };
@@ -152,11 +152,7 @@ fn parse_header(line: &str) -> Option<String> {
     }
 
     // Skip the space after #
-    let text = if rest.starts_with(' ') {
-        &rest[1..]
-    } else {
-        rest
-    };
+    let text = rest.strip_prefix(' ').unwrap_or(rest);
 
     Some(text.to_string())
 }
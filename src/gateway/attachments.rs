@@ -0,0 +1,291 @@
+//! Documents and photos sent to the bot.
+//!
+//! They are checked against `[gateway] max_attachment_bytes` and
+//! `attachment_types`, then downloaded into the chat's inbox,
+//! `<base_dir>/inbox/<chat id>/`. Claude gets their paths in the prompt;
+//! the persistent session also gets images as image content blocks.
+//! Files older than `inbox_retention_days` are deleted as new ones arrive.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use teloxide::net::Download;
+use teloxide::prelude::*;
+use teloxide::types::FileMeta;
+
+use crate::config::DemonConfig;
+use crate::session::Image;
+
+/// Image types Claude accepts as content blocks; other files are only
+/// referenced by path.
+const IMAGE_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+/// A file received from a chat, saved in its inbox.
+pub(super) struct Attachment {
+    path: PathBuf,
+    mime: String,
+}
+
+/// The document or photo of a message, before it is downloaded.
+struct Incoming<'a> {
+    file: &'a FileMeta,
+    name: String,
+    mime: String,
+}
+
+impl<'a> Incoming<'a> {
+    fn of(msg: &'a Message) -> Option<Self> {
+        if let Some(doc) = msg.document() {
+            return Some(Self {
+                file: &doc.file,
+                name: doc.file_name.clone().unwrap_or_else(|| "document".to_string()),
+                mime: doc
+                    .mime_type
+                    .as_ref()
+                    .map_or("application/octet-stream".to_string(), |m| m.essence_str().to_string()),
+            });
+        }
+        // Telegram sends a photo in several sizes, the largest last
+        let photo = msg.photo()?.last()?;
+        Some(Self {
+            file: &photo.file,
+            name: "photo.jpg".to_string(),
+            mime: "image/jpeg".to_string(),
+        })
+    }
+}
+
+/// Whether the message carries a document or photo.
+pub(super) fn has_attachment(msg: Message) -> bool {
+    msg.document().is_some() || msg.photo().is_some()
+}
+
+/// Check the message's document or photo against the limits and save it
+/// in the chat's inbox.
+pub(super) async fn receive(bot: &Bot, msg: &Message, config: &DemonConfig) -> Result<Attachment> {
+    let incoming = Incoming::of(msg).context("The message has no document or photo")?;
+    let gateway = &config.gateway;
+    if !allowed(&incoming.mime, &gateway.attachment_types) {
+        anyhow::bail!(
+            "Files of type {} are not accepted (see attachment_types in config.toml)",
+            incoming.mime
+        );
+    }
+    let size = u64::from(incoming.file.size);
+    if size > gateway.max_attachment_bytes {
+        anyhow::bail!(
+            "{} is too large ({} KB; the limit is {} KB)",
+            incoming.name,
+            size / 1024,
+            gateway.max_attachment_bytes / 1024
+        );
+    }
+
+    prune_inbox(config).await;
+    let dir = config.paths.inbox_dir().join(msg.chat.id.0.to_string());
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .context(format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(inbox_name(&incoming.name, Utc::now()));
+
+    let file = bot
+        .get_file(&incoming.file.id)
+        .await
+        .context("Failed to look up the file on Telegram")?;
+    let mut dst = tokio::fs::File::create(&path)
+        .await
+        .context(format!("Failed to create {}", path.display()))?;
+    if let Err(e) = bot.download_file(&file.path, &mut dst).await {
+        let _ = tokio::fs::remove_file(&path).await;
+        return Err(anyhow::Error::new(e).context("Failed to download the file from Telegram"));
+    }
+
+    tracing::info!(
+        component = "gateway",
+        chat_id = msg.chat.id.0,
+        path = %path.display(),
+        mime = %incoming.mime,
+        size = size,
+        "Saved attachment"
+    );
+    Ok(Attachment {
+        path,
+        mime: incoming.mime,
+    })
+}
+
+/// Delete inbox files past `inbox_retention_days`. Failures are logged:
+/// a file left behind must not stop the new one being received.
+async fn prune_inbox(config: &DemonConfig) {
+    let days = config.gateway.inbox_retention_days;
+    if days == 0 {
+        return;
+    }
+    let inbox = config.paths.inbox_dir();
+    match tokio::task::spawn_blocking(move || prune(&inbox, days, SystemTime::now())).await {
+        Ok(0) => {}
+        Ok(removed) => {
+            tracing::info!(component = "gateway", removed = removed, "Pruned old attachments");
+        }
+        Err(e) => tracing::warn!(component = "gateway", error = %e, "Failed to prune the inbox"),
+    }
+}
+
+/// Delete files in the inbox's chat directories last modified more than
+/// `days` days before `now`. Entries that can't be read or removed are
+/// logged and skipped. Returns how many files were deleted.
+fn prune(inbox: &Path, days: u64, now: SystemTime) -> usize {
+    let max_age = Duration::from_secs(days * 24 * 60 * 60);
+    let skip = |path: &Path, e: std::io::Error| {
+        tracing::warn!(component = "gateway", path = %path.display(), error = %e, "Failed to prune inbox entry");
+    };
+    let entries = |dir: &Path| match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.map_err(|e| skip(dir, e)).ok()).collect(),
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                skip(dir, e);
+            }
+            Vec::new()
+        }
+    };
+
+    let mut removed = 0;
+    for chat in entries(inbox).into_iter().map(|e| e.path()).filter(|p| p.is_dir()) {
+        for file in entries(&chat) {
+            let path = file.path();
+            let meta = match file.metadata() {
+                Ok(meta) => meta,
+                Err(e) => {
+                    skip(&path, e);
+                    continue;
+                }
+            };
+            let age = meta.modified().ok().and_then(|m| now.duration_since(m).ok());
+            let expired = meta.is_file() && age.is_some_and(|age| age > max_age);
+            if !expired {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(e) => skip(&path, e),
+            }
+        }
+    }
+    removed
+}
+
+/// Whether `mime` is in the allow-list, where `type/*` matches all subtypes.
+fn allowed(mime: &str, types: &[String]) -> bool {
+    types.iter().any(|t| match t.strip_suffix("/*") {
+        Some(kind) => mime
+            .split_once('/')
+            .is_some_and(|(k, _)| k.eq_ignore_ascii_case(kind)),
+        None => t.eq_ignore_ascii_case(mime),
+    })
+}
+
+/// File name in the inbox: the time received, then the sender's file name
+/// with anything unsafe in a path replaced.
+fn inbox_name(name: &str, now: DateTime<Utc>) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = name.trim_start_matches('.');
+    format!("{}-{}", now.format("%Y%m%d-%H%M%S%3f"), name)
+}
+
+/// The prompt for Claude: the attachments' paths, then the message text.
+pub(super) fn with_paths(text: &str, attachments: &[Attachment]) -> String {
+    if attachments.is_empty() {
+        return text.to_string();
+    }
+    let mut prompt = String::from("Attached files:\n");
+    for a in attachments {
+        prompt.push_str(&format!("- {} ({})\n", a.path.display(), a.mime));
+    }
+    if !text.is_empty() {
+        prompt.push('\n');
+        prompt.push_str(text);
+    }
+    prompt
+}
+
+/// The attachments Claude can take as image content blocks, read from the
+/// inbox.
+pub(super) async fn images(attachments: &[Attachment]) -> Result<Vec<Image>> {
+    let mut images = Vec::new();
+    for a in attachments.iter().filter(|a| IMAGE_TYPES.contains(&a.mime.as_str())) {
+        let data = tokio::fs::read(&a.path)
+            .await
+            .context(format!("Failed to read {}", a.path.display()))?;
+        images.push(Image {
+            media_type: a.mime.clone(),
+            data,
+        });
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_allowed_types() {
+        let types = ["image/png".to_string(), "text/*".to_string()];
+        assert!(allowed("image/png", &types));
+        assert!(allowed("text/x-rust", &types));
+        assert!(!allowed("image/jpeg", &types));
+        assert!(!allowed("application/pdf", &types));
+        assert!(!allowed("image/png", &[]));
+    }
+
+    #[test]
+    fn test_inbox_name_and_prompt() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 1, 9, 30, 0).unwrap();
+        assert_eq!(inbox_name("../notes 1.md", now), "20260301-093000000-_notes_1.md");
+
+        let attachment = Attachment {
+            path: PathBuf::from("/inbox/42/a.pdf"),
+            mime: "application/pdf".to_string(),
+        };
+        assert_eq!(
+            with_paths("summarize", &[attachment]),
+            "Attached files:\n- /inbox/42/a.pdf (application/pdf)\n\nsummarize"
+        );
+        assert_eq!(with_paths("hi", &[]), "hi");
+    }
+
+    #[test]
+    fn test_prune_old_files() {
+        let inbox = std::env::temp_dir().join(format!("demon-inbox-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(inbox.join("42")).unwrap();
+        let file = inbox.join("42").join("a.pdf");
+        fs::write(&file, "x").unwrap();
+
+        // Stray entries are skipped, not fatal
+        fs::write(inbox.join("stray"), "x").unwrap();
+        fs::create_dir_all(inbox.join("7").join("dir")).unwrap();
+        let other = inbox.join("7").join("b.pdf");
+        fs::write(&other, "x").unwrap();
+
+        let now = SystemTime::now();
+        assert_eq!(prune(&inbox, 30, now), 0);
+        assert!(file.exists());
+        assert_eq!(prune(&inbox, 30, now + Duration::from_secs(31 * 24 * 60 * 60)), 2);
+        assert!(!file.exists() && !other.exists());
+        assert_eq!(prune(&inbox.join("missing"), 30, now), 0);
+
+        let _ = fs::remove_dir_all(&inbox);
+    }
+}
@@ -0,0 +1,308 @@
+//! Bot commands for managing jobs from a chat.
+//!
+//! Commands go through the same code paths as the CLI: jobs are read and
+//! changed with `load_jobs`/`update_jobs` (notifying the daemon to reload),
+//! and `/run` uses `scheduler::run_now` like `demon job run`.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::ChatId;
+use teloxide::utils::command::BotCommands;
+
+use super::{results, schedule, within_budget, GatewayState, TelegramClient};
+use crate::config::DemonConfig;
+use crate::daemon;
+use crate::history::{self, RunTrigger};
+use crate::scheduler;
+
+/// Runs listed by `/history`.
+const HISTORY_LIMIT: usize = 10;
+
+/// Upcoming runs listed by `/status`.
+const UPCOMING_LIMIT: usize = 5;
+
+#[derive(BotCommands, Clone, Debug)]
+#[command(rename_rule = "lowercase", description = "Demon commands:")]
+pub enum Command {
+    #[command(description = "list jobs")]
+    Jobs,
+    #[command(description = "run a job now: /run <id>")]
+    Run(String),
+    #[command(description = "enable a job: /enable <id>")]
+    Enable(String),
+    #[command(description = "disable a job: /disable <id>")]
+    Disable(String),
+    #[command(description = "show daemon status")]
+    Status,
+    #[command(description = "show recent runs of a job: /history <id>")]
+    History(String),
+    #[command(description = "create a job from a description: /schedule <description>")]
+    Schedule(String),
+    #[command(description = "start a new conversation")]
+    New,
+    #[command(description = "cancel requests in progress")]
+    Cancel,
+}
+
+/// Handle a command from an allowed chat. Errors are left to the caller
+/// to report.
+pub(super) async fn handle(bot: &Bot, msg: &Message, command: Command, state: &Arc<GatewayState>) -> Result<()> {
+    let chat_id = msg.chat.id.0;
+    let config = state.config();
+
+    let text = match command {
+        Command::Jobs => jobs(&config)?,
+        Command::Run(id) => return run(bot, msg.chat.id, &id, &config, state).await,
+        Command::Enable(id) => toggle(&config, &id, true).await?,
+        Command::Disable(id) => toggle(&config, &id, false).await?,
+        Command::Status => status(&config, state).await?,
+        Command::History(id) => job_history(&config, &id)?,
+        Command::Schedule(description) => {
+            if within_budget(bot, msg.chat.id, &config).await {
+                schedule::start(bot, msg.chat.id, &description, state).await;
+            }
+            return Ok(());
+        }
+        Command::New => new_conversation(chat_id, state).await,
+        Command::Cancel => cancel(chat_id, state),
+    };
+
+    let client = TelegramClient::new(bot.clone(), config.gateway.message_format);
+    client
+        .send_formatted_message(msg.chat.id, &text)
+        .await
+        .context("Failed to send command reply")
+}
+
+fn find_job(config: &DemonConfig, id: &str) -> Result<crate::config::Job> {
+    if id.trim().is_empty() {
+        anyhow::bail!("Give a job ID, e.g. /run daily-standup (see /jobs)");
+    }
+    config
+        .load_jobs()?
+        .into_iter()
+        .find(|j| j.id == id.trim())
+        .context(format!("Job '{}' not found", id.trim()))
+}
+
+fn jobs(config: &DemonConfig) -> Result<String> {
+    let jobs = config.load_jobs()?;
+    if jobs.is_empty() {
+        return Ok("No jobs configured.".to_string());
+    }
+
+    let mut text = format!("**Jobs ({})**\n", jobs.len());
+    for job in &jobs {
+        let (last_run, last_status) = scheduler::last_run_summary(config, &job.id);
+        text.push_str(&format!("\n**{}** `{}`\n", job.name, job.id));
+        if job.enabled {
+            text.push_str(&format!(
+                "{} · next {}\n",
+                scheduler::schedule_summary(job),
+                scheduler::next_run_summary(config, job)
+            ));
+        } else {
+            text.push_str(&format!("{} · disabled\n", scheduler::schedule_summary(job)));
+        }
+        text.push_str(&format!("last {last_run} ({last_status})\n"));
+    }
+    Ok(text)
+}
+
+/// Run a job in the background, replying with its output when done.
+pub(super) async fn run(bot: &Bot, chat: ChatId, id: &str, config: &DemonConfig, state: &Arc<GatewayState>) -> Result<()> {
+    let job = find_job(config, id)?;
+
+    let reply_bot = bot.clone();
+    let reply_state = state.clone();
+    state
+        .spawn_request(bot, chat, move |typing| async move {
+            let config = reply_state.config();
+            tracing::info!(component = "gateway", chat_id = chat.0, job_id = %job.id, "Running job from chat");
+            let _ = reply_bot
+                .send_message(chat, format!("Running job: {} ({})", job.name, job.id))
+                .await;
+
+            let result = scheduler::run_now(&job, &config, RunTrigger::Chat, reply_state.running.as_ref()).await;
+            typing.stop();
+
+            let text = match result {
+                Ok(output) => history::result_text(&output),
+                Err(e) => format!("Job '{}' failed: {e:#}", job.id),
+            };
+            let client = TelegramClient::new(reply_bot.clone(), config.gateway.message_format);
+            if let Err(e) = client.send_formatted_message(chat, &text).await {
+                tracing::error!(
+                    component = "gateway",
+                    chat_id = chat.0,
+                    error = %e,
+                    "Failed to send job result"
+                );
+            }
+        })
+        .await;
+    Ok(())
+}
+
+pub(super) async fn toggle(config: &DemonConfig, id: &str, enabled: bool) -> Result<String> {
+    let id = find_job(config, id)?.id;
+    let job_id = id.clone();
+    let name = config
+        .update_jobs_async(move |jobs| {
+            let job = jobs
+                .iter_mut()
+                .find(|j| j.id == job_id)
+                .context(format!("Job '{}' not found", job_id))?;
+            job.enabled = enabled;
+            Ok(job.name.clone())
+        })
+        .await?;
+
+    if daemon::is_running(&config.paths)? {
+        let pid = daemon::read_pid(&config.paths)?;
+        daemon::signal_reload(pid)?;
+    }
+
+    let action = if enabled { "Enabled" } else { "Disabled" };
+    Ok(format!("{action} job: {name} (`{id}`)"))
+}
+
+async fn status(config: &DemonConfig, state: &GatewayState) -> Result<String> {
+    let mut text = String::new();
+
+    let running = daemon::is_running(&config.paths)?;
+    if running {
+        let pid = daemon::read_pid(&config.paths)?;
+        text.push_str(&format!("**Demon**: running (PID {pid})\n"));
+    } else {
+        text.push_str("**Demon**: stopped\n");
+    }
+    let mode = if state.session_manager().await.is_some() {
+        "persistent session"
+    } else {
+        "spawn mode"
+    };
+    text.push_str(&format!("**Gateway**: {mode}\n"));
+
+    // Active runs are only meaningful while the daemon is up
+    let active = if running {
+        scheduler::load_running(config).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    if !active.is_empty() {
+        text.push_str(&format!("\n**Running ({})**\n", active.len()));
+        let now = Utc::now();
+        for run in &active {
+            let run_state = match run.state {
+                scheduler::RunState::Running => "running",
+                scheduler::RunState::Waiting => "waiting",
+            };
+            text.push_str(&format!(
+                "`{}` {} for {}\n",
+                run.job_id,
+                run_state,
+                scheduler::format_duration((now - run.registered_at).num_seconds())
+            ));
+        }
+    }
+
+    let jobs = config.load_jobs()?;
+    let enabled: Vec<_> = jobs.iter().filter(|j| j.enabled).collect();
+    text.push_str(&format!("\n**Jobs**: {} enabled of {}\n", enabled.len(), jobs.len()));
+
+    let now = Utc::now();
+    let mut upcoming: Vec<_> = enabled
+        .iter()
+        .filter_map(|job| match scheduler::next_run(job, config, now) {
+            Ok(Some(at)) => Some((at, *job)),
+            _ => None,
+        })
+        .collect();
+    upcoming.sort_by_key(|(at, _)| *at);
+    for (at, job) in upcoming.into_iter().take(UPCOMING_LIMIT) {
+        text.push_str(&format!(
+            "`{}` {}\n",
+            job.id,
+            scheduler::format_in_job_timezone(config, job, at)
+        ));
+    }
+
+    Ok(text)
+}
+
+fn job_history(config: &DemonConfig, id: &str) -> Result<String> {
+    let id = find_job(config, id)?.id;
+    let records = history::load(config, &id)?;
+    if records.is_empty() {
+        return Ok(format!("No runs recorded for job `{id}`."));
+    }
+
+    let mut text = format!("**Runs of `{}`** ({} total)\n", id, records.len());
+    for r in records.iter().rev().take(HISTORY_LIMIT) {
+        text.push_str(&format!(
+            "\n`{}` {} · {} · {}",
+            r.started_at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M"),
+            r.status,
+            r.trigger.as_str(),
+            scheduler::format_duration((r.duration_ms / 1000) as i64)
+        ));
+        if let Some(cost) = r.cost_usd {
+            text.push_str(&format!(" · ${cost:.3}"));
+        }
+        if let Some(err) = &r.error {
+            let first: String = err.lines().next().unwrap_or_default().chars().take(80).collect();
+            text.push_str(&format!("\n  {first}"));
+        }
+    }
+    Ok(text)
+}
+
+fn cancel(chat_id: i64, state: &GatewayState) -> String {
+    let mut text = match state.cancel(chat_id) {
+        0 => "Nothing to cancel.".to_string(),
+        1 => "Cancelled 1 request.".to_string(),
+        n => format!("Cancelled {n} requests."),
+    };
+    if schedule::discard(state, chat_id) {
+        text.push_str(" Discarded the job draft.");
+    }
+    text
+}
+
+async fn new_conversation(chat_id: i64, state: &GatewayState) -> String {
+    if results::end_follow_up(state, chat_id) {
+        return "Stopped following up on the job; back to the normal conversation.".to_string();
+    }
+    if state.session_manager().await.is_some() {
+        return "All chats share the persistent session, so there is no conversation to reset."
+            .to_string();
+    }
+    state.sessions.lock().await.remove(&chat_id);
+    tracing::debug!(component = "gateway", chat_id = chat_id, "Session cleared by /new");
+    "Started a new conversation.".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert!(matches!(
+            Command::parse("/run daily-standup", "demon_bot"),
+            Ok(Command::Run(id)) if id == "daily-standup"
+        ));
+        assert!(matches!(Command::parse("/jobs@demon_bot", "demon_bot"), Ok(Command::Jobs)));
+        assert!(matches!(Command::parse("/run", "demon_bot"), Ok(Command::Run(id)) if id.is_empty()));
+
+        // Left for the task router and Claude
+        assert!(Command::parse("/task summarize inbox", "demon_bot").is_err());
+        assert!(Command::parse("what jobs do I have?", "demon_bot").is_err());
+        assert!(Command::parse("/jobs@other_bot", "demon_bot").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! The dispatcher's handler tree.
+//!
+//! Messages from allowed chats go to the bot commands, or to Claude when
+//! they carry text, a caption or a document or photo; a message replying
+//! to another quotes it for context. Edited messages are answered again,
+//! except commands.
+//! Button presses go to the feature that sent the buttons. Endpoints take
+//! `GatewayState` as an injected dependency and report their own errors:
+//! they are logged with the handler's name and the chat is told.
+
+use anyhow::Result;
+use std::sync::Arc;
+use teloxide::dispatching::UpdateHandler;
+use teloxide::prelude::*;
+use teloxide::types::{CallbackQuery, ChatId};
+
+use super::attachments::{self, Attachment};
+use super::commands::{self, Command};
+use super::{results, schedule, within_budget, GatewayState};
+
+type HandlerResult = Result<()>;
+
+/// Dispatch updates to the handlers below.
+pub(super) fn schema() -> UpdateHandler<anyhow::Error> {
+    let messages = Update::filter_message()
+        .filter_async(authorized)
+        .branch(teloxide::filter_command::<Command, _>().endpoint(on_command))
+        .branch(dptree::filter(attachments::has_attachment).endpoint(on_attachment))
+        .branch(dptree::filter_map(prompt).endpoint(on_prompt))
+        // Group service messages (joins, pins, ...) are left unanswered
+        .branch(dptree::filter(|msg: Message| msg.chat.is_private()).endpoint(on_unsupported));
+
+    let edited = Update::filter_edited_message()
+        .filter_async(authorized)
+        // Editing a command shouldn't run it again
+        .filter(|msg: Message| !msg.text().is_some_and(|t| t.starts_with('/')))
+        .branch(dptree::filter(attachments::has_attachment).endpoint(on_attachment))
+        .branch(dptree::filter_map(prompt).endpoint(on_prompt));
+
+    dptree::entry()
+        .branch(messages)
+        .branch(edited)
+        .branch(Update::filter_callback_query().endpoint(on_callback))
+}
+
+/// Log a failed handler and tell the chat; the update counts as handled.
+async fn report(bot: &Bot, chat: ChatId, handler: &'static str, result: HandlerResult) -> HandlerResult {
+    if let Err(e) = result {
+        tracing::error!(
+            component = "gateway",
+            handler = handler,
+            chat_id = chat.0,
+            error = %format!("{e:#}"),
+            "Handler failed"
+        );
+        let _ = bot.send_message(chat, format!("Error: {e:#}")).await;
+    }
+    Ok(())
+}
+
+/// Let through messages from whitelisted chats, turning others away.
+async fn authorized(bot: Bot, msg: Message, state: Arc<GatewayState>) -> bool {
+    let chat_id = msg.chat.id.0;
+    if state.config().gateway.allowed_chat_ids.contains(&chat_id) {
+        return true;
+    }
+    tracing::warn!(
+        component = "gateway",
+        chat_id = chat_id,
+        "Message from non-whitelisted chat"
+    );
+    let _ = bot
+        .send_message(msg.chat.id, "This chat is not authorized to use Demon.")
+        .await;
+    false
+}
+
+/// What to send to Claude for a message: its text or caption, after the
+/// message it replies to, quoted.
+fn prompt(msg: Message) -> Option<String> {
+    let text = msg.text().or(msg.caption())?;
+    let quoted = msg
+        .reply_to_message()
+        .and_then(|r| r.text().or(r.caption()))
+        .filter(|_| !text.starts_with("/task "));
+    Some(match quoted {
+        Some(quoted) => {
+            let quoted: Vec<String> = quoted.lines().map(|l| format!("> {l}")).collect();
+            format!("{}\n\n{}", quoted.join("\n"), text)
+        }
+        None => text.to_string(),
+    })
+}
+
+async fn on_command(bot: Bot, msg: Message, command: Command, state: Arc<GatewayState>) -> HandlerResult {
+    tracing::info!(
+        component = "gateway",
+        chat_id = msg.chat.id.0,
+        command = ?command,
+        "Received command"
+    );
+    let result = commands::handle(&bot, &msg, command, &state).await;
+    report(&bot, msg.chat.id, "command", result).await
+}
+
+async fn on_prompt(bot: Bot, msg: Message, text: String, state: Arc<GatewayState>) -> HandlerResult {
+    answer(bot, msg, text, Vec::new(), state).await;
+    Ok(())
+}
+
+/// Save a document or photo in the chat's inbox and answer its caption
+/// with it attached.
+async fn on_attachment(bot: Bot, msg: Message, state: Arc<GatewayState>) -> HandlerResult {
+    // Nothing is downloaded once the chat is over budget
+    if !within_budget(&bot, msg.chat.id, &state.config()).await {
+        return Ok(());
+    }
+    match attachments::receive(&bot, &msg, &state.config()).await {
+        Ok(attachment) => {
+            let text = prompt(msg.clone()).unwrap_or_default();
+            answer(bot, msg, text, vec![attachment], state).await;
+            Ok(())
+        }
+        Err(e) => report(&bot, msg.chat.id, "attachment", Err(e)).await,
+    }
+}
+
+/// Answer a message: a change to a `/schedule` draft, a follow-up on a job
+/// result, or a prompt for Claude.
+async fn answer(bot: Bot, msg: Message, text: String, attachments: Vec<Attachment>, state: Arc<GatewayState>) {
+    let chat_id = msg.chat.id.0;
+    tracing::info!(
+        component = "gateway",
+        chat_id = chat_id,
+        message_len = text.len(),
+        attachments = attachments.len(),
+        edited = msg.edit_date().is_some(),
+        "Received message"
+    );
+
+    if !within_budget(&bot, msg.chat.id, &state.config()).await {
+        return;
+    }
+
+    // A change to the job being drafted with /schedule
+    if let Some(job) = schedule::take_edit(&state, chat_id) {
+        let text = attachments::with_paths(&text, &attachments);
+        schedule::revise(&bot, msg.chat.id, job, &text, &state).await;
+        return;
+    }
+
+    // A follow-up on a job result
+    if let Some(follow_up) = results::follow_up(&state, chat_id) {
+        let text = attachments::with_paths(&text, &attachments);
+        results::ask(&bot, msg.chat.id, follow_up, &text, &state).await;
+        return;
+    }
+
+    let request_bot = bot.clone();
+    let request_state = state.clone();
+    state
+        .spawn_request(&bot, msg.chat.id, move |typing| async move {
+            super::respond(&request_bot, &msg, &text, &attachments, &request_state, &typing).await;
+        })
+        .await;
+}
+
+/// Private messages with nothing to answer, e.g. stickers or voice notes.
+async fn on_unsupported(bot: Bot, msg: Message) -> HandlerResult {
+    tracing::debug!(component = "gateway", chat_id = msg.chat.id.0, "Ignoring message without text");
+    let result = bot
+        .send_message(msg.chat.id, "Demon can only read text, documents and photos.")
+        .await
+        .map(|_| ())
+        .map_err(Into::into);
+    report(&bot, msg.chat.id, "unsupported", result).await
+}
+
+/// Handle a press of an inline button from an allowed chat. The outcome
+/// is shown as the button's notification.
+async fn on_callback(bot: Bot, query: CallbackQuery, state: Arc<GatewayState>) -> HandlerResult {
+    let Some(message) = query.message.as_ref() else {
+        return Ok(());
+    };
+    let chat = message.chat().id;
+    let data = query.data.as_deref().unwrap_or_default();
+
+    let answer = if !state.config().gateway.allowed_chat_ids.contains(&chat.0) {
+        tracing::warn!(
+            component = "gateway",
+            chat_id = chat.0,
+            "Button press from non-whitelisted chat"
+        );
+        Ok("This chat is not authorized to use Demon.".to_string())
+    } else {
+        tracing::info!(component = "gateway", chat_id = chat.0, data = %data, "Button pressed");
+        match data.split_once(':') {
+            Some((schedule::CALLBACK_PREFIX, rest)) => {
+                schedule::handle_callback(&bot, chat, message.id(), rest, &state).await
+            }
+            Some((results::CALLBACK_PREFIX, rest)) => {
+                results::handle_callback(&bot, chat, rest, &state).await
+            }
+            _ => Err(anyhow::anyhow!("Unknown button '{}'", data)),
+        }
+    };
+
+    let text = answer.unwrap_or_else(|e| {
+        tracing::error!(
+            component = "gateway",
+            handler = "callback",
+            chat_id = chat.0,
+            error = %format!("{e:#}"),
+            "Handler failed"
+        );
+        format!("Error: {e:#}")
+    });
+    let mut request = bot.answer_callback_query(query.id.clone());
+    if !text.is_empty() {
+        request = request.text(text);
+    }
+    request.await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(json: serde_json::Value) -> Message {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn text_message(text: &str, reply_to: Option<serde_json::Value>) -> serde_json::Value {
+        let mut msg = serde_json::json!({
+            "message_id": 2,
+            "date": 1700000000,
+            "chat": { "id": 42, "type": "private", "first_name": "A" },
+            "from": { "id": 42, "is_bot": false, "first_name": "A" },
+            "text": text,
+        });
+        if let Some(reply) = reply_to {
+            msg["reply_to_message"] = reply;
+        }
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_routes_messages_from_allowed_chats() {
+        let mut config = crate::config::DemonConfig::default();
+        config.gateway.allowed_chat_ids = vec![42, -100];
+        let state = Arc::new(super::super::tests::state_with(config));
+        // Nothing listens here, so replies fail fast
+        let api_url = serde_json::from_value(serde_json::json!("http://127.0.0.1:9/")).unwrap();
+        let bot = Bot::new("test").set_api_url(api_url);
+        let me: teloxide::types::Me = serde_json::from_value(serde_json::json!({
+            "id": 1, "is_bot": true, "first_name": "Demon", "username": "demon_bot",
+            "can_join_groups": true, "can_read_all_group_messages": false, "supports_inline_queries": false,
+        }))
+        .unwrap();
+        // Whether the handler tree took the update
+        let dispatch = |kind: &str, msg: serde_json::Value| {
+            let mut update = serde_json::json!({ "update_id": 1 });
+            update[kind] = msg;
+            let update: Update = serde_json::from_str(&update.to_string()).unwrap();
+            let deps = dptree::deps![update, bot.clone(), me.clone(), state.clone()];
+            async move { schema().dispatch(deps).await.is_break() }
+        };
+
+        // /cancel reaches the command handler and cancels the pending request
+        let turn = state.queue(42);
+        assert!(dispatch("message", text_message("/cancel", None)).await);
+        assert!(turn.cancel.has_changed().unwrap());
+        state.finish(42);
+
+        // ...but not from a chat that isn't allowed
+        let turn = state.queue(7);
+        let mut other = text_message("/cancel", None);
+        other["chat"]["id"] = 7.into();
+        assert!(!dispatch("message", other).await);
+        assert!(!turn.cancel.has_changed().unwrap());
+
+        // ...nor when a command is edited
+        let turn = state.queue(42);
+        let mut edited = text_message("/cancel", None);
+        edited["edit_date"] = 1700000001.into();
+        assert!(!dispatch("edited_message", edited).await);
+        assert!(!turn.cancel.has_changed().unwrap());
+
+        // Service messages in groups go unanswered
+        let joined = serde_json::json!({
+            "message_id": 3,
+            "date": 1700000000,
+            "chat": { "id": -100, "type": "group", "title": "G" },
+            "from": { "id": 42, "is_bot": false, "first_name": "A" },
+            "new_chat_members": [{ "id": 43, "is_bot": false, "first_name": "B" }],
+        });
+        assert!(!dispatch("message", joined).await);
+    }
+
+    #[test]
+    fn test_prompt_quotes_replied_message() {
+        let original = text_message("Build passed\nAll green", None);
+        let reply = message(text_message("why was it slow?", Some(original.clone())));
+        assert_eq!(
+            prompt(reply).unwrap(),
+            "> Build passed\n> All green\n\nwhy was it slow?"
+        );
+
+        let task = message(text_message("/task summarize", Some(original)));
+        assert_eq!(prompt(task).unwrap(), "/task summarize");
+
+        let mut photo = text_message("", None);
+        photo.as_object_mut().unwrap().remove("text");
+        photo["caption"] = "what is this?".into();
+        photo["photo"] = serde_json::json!([
+            { "file_id": "f", "file_unique_id": "u", "width": 90, "height": 90 }
+        ]);
+        assert_eq!(prompt(message(photo)).unwrap(), "what is this?");
+    }
+}
@@ -1,16 +1,31 @@
+mod attachments;
+mod commands;
+mod handlers;
+mod results;
+mod schedule;
 mod telegram_client;
 
 use anyhow::{Context, Result};
 use chrono::Utc;
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
 use teloxide::prelude::*;
-use tokio::sync::Mutex;
-
-use crate::config::DemonConfig;
+use teloxide::types::ChatId;
+use teloxide::update_listeners;
+use teloxide::utils::command::BotCommands;
+use tokio::sync::{oneshot, watch, Mutex, RwLock};
+
+use crate::config::{DemonConfig, GatewayConfig, Job};
+use crate::daemon::Shutdown;
+use crate::process::{self, ProcessError};
+use crate::scheduler::RunningJobs;
 use crate::session::{SessionConfig, SessionManager};
 use crate::task;
+use crate::usage::{self, Scope, Usage, UsageEntry};
 
+use commands::Command;
+pub use results::result_keyboard;
 pub use telegram_client::TelegramClient;
 
 /// Tracks an active Claude session for a chat
@@ -22,123 +37,401 @@ struct ChatSession {
 
 type SessionMap = Arc<Mutex<HashMap<i64, ChatSession>>>;
 
+/// Requests of one chat, answered one at a time in order.
+#[derive(Default)]
+struct ChatQueue {
+    /// Resolves when the chat's latest request is done
+    last: Option<oneshot::Receiver<()>>,
+    /// Requests queued or in progress
+    pending: usize,
+    /// Bumped by `/cancel`; requests queued before that give up
+    cancel: watch::Sender<u64>,
+}
+
+/// A request's place in its chat's queue.
+struct Turn {
+    previous: Option<oneshot::Receiver<()>>,
+    /// Dropped when the request is done, letting the next one start
+    done: oneshot::Sender<()>,
+    cancel: watch::Receiver<u64>,
+    generation: u64,
+}
+
+/// Repeats the "typing" chat action until stopped or dropped.
+struct Typing(tokio::task::JoinHandle<()>);
+
+impl Typing {
+    fn start(bot: &Bot, chat: ChatId) -> Self {
+        let bot = bot.clone();
+        Self(tokio::spawn(async move {
+            loop {
+                let _ = bot
+                    .send_chat_action(chat, teloxide::types::ChatAction::Typing)
+                    .await;
+                tokio::time::sleep(tokio::time::Duration::from_secs(4)).await;
+            }
+        }))
+    }
+
+    fn stop(&self) {
+        self.0.abort();
+    }
+}
+
+impl Drop for Typing {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
 /// Shared state for the gateway, including optional persistent session manager.
 struct GatewayState {
-    config: DemonConfig,
+    config: watch::Receiver<DemonConfig>,
     sessions: SessionMap,
-    session_manager: Option<Arc<SessionManager>>,
+    session_manager: RwLock<Option<Arc<SessionManager>>>,
+    shutdown: Shutdown,
+    chats: std::sync::Mutex<HashMap<i64, ChatQueue>>,
+    /// Jobs being drafted with `/schedule`, by chat
+    drafts: std::sync::Mutex<HashMap<i64, schedule::Draft>>,
+    /// Job runs whose session a chat is continuing, by chat
+    follow_ups: std::sync::Mutex<HashMap<i64, results::FollowUp>>,
+    /// The scheduler's registry when running in the daemon, for `/run`
+    running: Option<Arc<RunningJobs>>,
 }
 
-pub async fn run(config: DemonConfig) -> Result<()> {
-    tracing::info!(component = "gateway", "Starting Telegram gateway");
+impl GatewayState {
+    /// Snapshot of the live config.
+    fn config(&self) -> DemonConfig {
+        self.config.borrow().clone()
+    }
 
-    if config.gateway.bot_token.is_empty() {
-        anyhow::bail!("Telegram bot token is not configured");
+    /// The current persistent session manager, if enabled.
+    async fn session_manager(&self) -> Option<Arc<SessionManager>> {
+        self.session_manager.read().await.clone()
     }
 
-    // Initialize persistent session manager if enabled
-    let session_manager = if config.gateway.use_persistent_session {
-        tracing::info!(
-            component = "gateway",
-            tmux_session = %config.gateway.tmux_session_name,
-            compact_interval_secs = config.gateway.compact_interval_secs,
-            "Starting with persistent session"
-        );
+    /// Take the next place in a chat's queue.
+    fn queue(&self, chat_id: i64) -> Turn {
+        let mut chats = self.chats.lock().unwrap();
+        let chat = chats.entry(chat_id).or_default();
+        let (done, finished) = oneshot::channel();
+        chat.pending += 1;
+        let generation = *chat.cancel.borrow();
+        Turn {
+            previous: chat.last.replace(finished),
+            done,
+            cancel: chat.cancel.subscribe(),
+            generation,
+        }
+    }
 
-        let session_config = SessionConfig {
-            session_name: config.gateway.tmux_session_name.clone(),
-            prompt_marker: config.gateway.prompt_marker.clone(),
-            poll_interval_ms: 200,
-            response_timeout_secs: config.gateway.max_turns as u64 * 30 + 60,
-            startup_timeout_secs: 60,
-            compact_interval_secs: config.gateway.compact_interval_secs,
-            max_restart_attempts: 3,
-            model: config.gateway.default_model.clone(),
-            max_turns: config.gateway.max_turns * 10, // Higher limit for persistent session
-            max_budget_usd: config.gateway.max_budget_usd * 10.0, // Higher budget for persistent session
-            allowed_tools: config.gateway.allowed_tools.clone(),
-            disallowed_tools: config.gateway.disallowed_tools.clone(),
-            append_system_prompt: config.gateway.append_system_prompt.clone(),
-        };
+    /// Mark one of a chat's requests done.
+    fn finish(&self, chat_id: i64) {
+        if let Some(chat) = self.chats.lock().unwrap().get_mut(&chat_id) {
+            chat.pending -= 1;
+        }
+    }
 
-        let manager = SessionManager::new(session_config)
-            .await
-            .context("Failed to initialize persistent session manager")?;
+    /// Cancel a chat's queued and in-progress requests. Returns how many
+    /// there were.
+    fn cancel(&self, chat_id: i64) -> usize {
+        let chats = self.chats.lock().unwrap();
+        match chats.get(&chat_id) {
+            Some(chat) if chat.pending > 0 => {
+                chat.cancel.send_modify(|g| *g += 1);
+                chat.pending
+            }
+            _ => 0,
+        }
+    }
 
-        tracing::info!(component = "gateway", "Persistent session manager initialized");
-        Some(Arc::new(manager))
-    } else {
+    /// Answer a request in the background, after the chat's earlier
+    /// requests, so the chat can still `/cancel` it. `request` gets the
+    /// typing indicator and should stop it before replying. Nothing new is
+    /// started once the daemon is shutting down.
+    async fn spawn_request<F, Fut>(self: &Arc<Self>, bot: &Bot, chat: ChatId, request: F)
+    where
+        F: FnOnce(Typing) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        if self.shutdown.is_draining() {
+            let _ = bot
+                .send_message(chat, "Demon is shutting down; please send your message again once it is back.")
+                .await;
+            return;
+        }
+
+        let work = self.shutdown.track();
+        let Turn {
+            previous,
+            done,
+            mut cancel,
+            generation,
+        } = self.queue(chat.0);
+        let state = self.clone();
+        let bot = bot.clone();
+
+        tokio::spawn(async move {
+            let _work = work;
+            let run = async {
+                if let Some(previous) = previous {
+                    let _ = previous.await;
+                }
+                request(Typing::start(&bot, chat)).await;
+            };
+            let cancelled = async {
+                let _ = cancel.wait_for(|g| *g != generation).await;
+            };
+
+            tokio::select! {
+                _ = run => {}
+                _ = cancelled => {
+                    tracing::info!(component = "gateway", chat_id = chat.0, "Request cancelled");
+                }
+                _ = state.shutdown.aborted() => {
+                    tracing::warn!(
+                        component = "gateway",
+                        chat_id = chat.0,
+                        "Request cancelled by shutdown"
+                    );
+                    let _ = bot
+                        .send_message(
+                            chat,
+                            "Demon shut down before it could answer; please send your message again once it is back.",
+                        )
+                        .await;
+                }
+            }
+
+            state.finish(chat.0);
+            drop(done);
+        });
+    }
+}
+
+/// Build the persistent session config from gateway settings.
+fn session_config(config: &DemonConfig) -> SessionConfig {
+    let gateway = &config.gateway;
+    SessionConfig {
+        session_name: config.tmux_session_name(),
+        prompt_marker: gateway.prompt_marker.clone(),
+        poll_interval_ms: 200,
+        response_timeout_secs: gateway.max_turns as u64 * 30 + 60,
+        startup_timeout_secs: 60,
+        compact_interval_secs: gateway.compact_interval_secs,
+        max_restart_attempts: 3,
+        model: gateway.default_model.clone(),
+        max_turns: gateway.max_turns * 10, // Higher limit for persistent session
+        max_budget_usd: gateway.max_budget_usd * 10.0, // Higher budget for persistent session
+        allowed_tools: gateway.allowed_tools.clone(),
+        disallowed_tools: gateway.disallowed_tools.clone(),
+        append_system_prompt: gateway.append_system_prompt.clone(),
+    }
+}
+
+/// Start the persistent session manager if the gateway is configured for it.
+async fn start_session_manager(config: &DemonConfig) -> Result<Option<Arc<SessionManager>>> {
+    let gateway = &config.gateway;
+    if !gateway.use_persistent_session {
         tracing::info!(
             component = "gateway",
-            session_timeout_secs = config.gateway.session_timeout_secs,
+            session_timeout_secs = gateway.session_timeout_secs,
             "Starting with spawn mode"
         );
-        None
-    };
+        return Ok(None);
+    }
+
+    tracing::info!(
+        component = "gateway",
+        tmux_session = %config.tmux_session_name(),
+        compact_interval_secs = gateway.compact_interval_secs,
+        "Starting with persistent session"
+    );
+
+    let manager = SessionManager::new(session_config(config))
+        .await
+        .context("Failed to initialize persistent session manager")?;
+
+    tracing::info!(component = "gateway", "Persistent session manager initialized");
+    Ok(Some(Arc::new(manager)))
+}
+
+/// Run the gateway until `shutdown` finishes, then stop the persistent
+/// session. `running` is the scheduler's registry when the gateway runs in
+/// the daemon.
+pub async fn run(config_rx: watch::Receiver<DemonConfig>, shutdown: Shutdown, running: Option<Arc<RunningJobs>>) -> Result<()> {
+    tracing::info!(component = "gateway", "Starting Telegram gateway");
+
+    let config = config_rx.borrow().clone();
+    if config.gateway.bot_token.is_empty() {
+        anyhow::bail!("Telegram bot token is not configured");
+    }
+
+    // Initialize persistent session manager if enabled
+    let session_manager = start_session_manager(&config).await?;
 
     let bot = Bot::new(&config.gateway.bot_token);
     let sessions: SessionMap = Arc::new(Mutex::new(HashMap::new()));
 
+    if let Err(e) = bot.set_my_commands(Command::bot_commands()).await {
+        tracing::warn!(component = "gateway", error = %e, "Failed to register bot commands");
+    }
+
     let state = Arc::new(GatewayState {
-        config,
+        config: config_rx.clone(),
         sessions,
-        session_manager,
+        session_manager: RwLock::new(session_manager),
+        shutdown: shutdown.clone(),
+        chats: std::sync::Mutex::new(HashMap::new()),
+        drafts: std::sync::Mutex::new(HashMap::new()),
+        follow_ups: std::sync::Mutex::new(HashMap::new()),
+        running,
     });
 
+    tokio::spawn(watch_config(state.clone(), config_rx, config));
+
     tracing::info!(component = "gateway", "Telegram bot ready, waiting for messages");
 
-    teloxide::repl(bot, move |bot: Bot, msg: Message| {
-        let state = state.clone();
-        async move {
-            tracing::debug!(component = "gateway", "Received raw message from Telegram");
-            handle_message_with_state(bot, msg, &state).await;
-            Ok(())
+    let mut dispatcher = Dispatcher::builder(bot.clone(), handlers::schema())
+        .dependencies(dptree::deps![state.clone()])
+        .default_handler(|_| async {})
+        .enable_ctrlc_handler()
+        .build();
+    let listener = update_listeners::polling_default(bot).await;
+    let error_handler = LoggingErrorHandler::with_custom_text("Failed to fetch updates from Telegram");
+
+    let result = tokio::select! {
+        result = dispatcher.try_dispatch_with_listener(listener, error_handler) => match result {
+            // teloxide stops polling on Ctrl-C by itself; keep the session
+            // until in-flight messages are done
+            Ok(()) => {
+                shutdown.finished().await;
+                Ok(())
+            }
+            Err(e) => Err(anyhow::Error::new(e).context("Failed to connect to Telegram")),
+        },
+        _ = shutdown.finished() => Ok(()),
+    };
+
+    // Stop the persistent session, failing anything still queued
+    let manager = state.session_manager.write().await.take();
+    if let Some(manager) = manager {
+        if let Err(e) = manager.shutdown().await {
+            tracing::error!(component = "gateway", error = %e, "Failed to shut down session manager");
         }
-    })
-    .await;
+    }
+    tracing::info!(component = "gateway", "Gateway stopped");
 
-    Ok(())
+    result
 }
 
-/// Handle message with gateway state (supports both persistent and spawn modes).
-async fn handle_message_with_state(bot: Bot, msg: Message, state: &GatewayState) {
-    let chat_id = msg.chat.id.0;
+/// Apply reloaded gateway settings. Most settings are read per message;
+/// the persistent session manager is re-created when its settings change.
+async fn watch_config(
+    state: Arc<GatewayState>,
+    mut config_rx: watch::Receiver<DemonConfig>,
+    mut current: DemonConfig,
+) {
+    while config_rx.changed().await.is_ok() {
+        let new = config_rx.borrow_and_update().clone();
+
+        if new.gateway.bot_token != current.gateway.bot_token {
+            tracing::warn!(
+                component = "gateway",
+                "Bot token changed; restart the gateway for it to take effect"
+            );
+        }
 
-    // Check whitelist
-    if !state.config.gateway.allowed_chat_ids.contains(&chat_id) {
-        tracing::warn!(
-            component = "gateway",
-            chat_id = chat_id,
-            "Message from non-whitelisted chat"
-        );
-        let _ = bot
-            .send_message(msg.chat.id, "This chat is not authorized to use Demon.")
-            .await;
-        return;
-    }
+        let persistent = new.gateway.use_persistent_session;
+        let session_changed = persistent != current.gateway.use_persistent_session
+            || (persistent && session_config(&new) != session_config(&current));
 
-    let Some(text) = msg.text() else {
-        return;
-    };
+        if session_changed {
+            tracing::info!(
+                component = "gateway",
+                persistent,
+                "Session settings changed, re-creating session manager"
+            );
 
-    tracing::info!(
-        component = "gateway",
-        chat_id = chat_id,
-        message_len = text.len(),
-        "Received message"
-    );
+            // Shut the old session down first so a reused tmux session name is free
+            let old = state.session_manager.write().await.take();
+            if let Some(old) = old {
+                if let Err(e) = old.shutdown().await {
+                    tracing::error!(component = "gateway", error = %e, "Failed to shut down old session manager");
+                }
+            }
 
-    // Send typing indicator continuously until Claude responds
-    let typing_bot = bot.clone();
-    let typing_chat_id = msg.chat.id;
-    let typing_handle = tokio::spawn(async move {
-        loop {
-            let _ = typing_bot
-                .send_chat_action(typing_chat_id, teloxide::types::ChatAction::Typing)
-                .await;
-            tokio::time::sleep(tokio::time::Duration::from_secs(4)).await;
+            match start_session_manager(&new).await {
+                Ok(manager) => *state.session_manager.write().await = manager,
+                Err(e) => {
+                    tracing::error!(
+                        component = "gateway",
+                        error = %format!("{e:#}"),
+                        "Failed to start session manager, falling back to spawn mode"
+                    );
+                }
+            }
         }
-    });
+
+        current = new;
+    }
+}
+
+/// Check the chat's budget, telling the chat when it is used up.
+async fn within_budget(bot: &Bot, chat: ChatId, config: &DemonConfig) -> bool {
+    match usage::check(config, Scope::Chat(chat.0), Utc::now()) {
+        Ok(Some(reason)) => {
+            tracing::warn!(
+                component = "gateway",
+                chat_id = chat.0,
+                reason = %reason,
+                "Budget reached, not answering"
+            );
+            let _ = bot.send_message(chat, format!("{reason}.")).await;
+            false
+        }
+        Ok(None) => true,
+        Err(e) => {
+            tracing::error!(
+                component = "gateway",
+                chat_id = chat.0,
+                error = %e,
+                "Failed to check budget, answering anyway"
+            );
+            true
+        }
+    }
+}
+
+/// Send a prompt to Claude outside the chat's conversation: through the
+/// persistent session if enabled, otherwise a one-shot `claude -p`.
+async fn ask_claude(state: &GatewayState, config: &DemonConfig, chat_id: i64, prompt: &str) -> Result<String> {
+    match state.session_manager().await {
+        Some(manager) => {
+            let reply = manager.send_message(prompt).await?;
+            if let Some(spent) = reply.usage {
+                usage::record(config, UsageEntry::gateway(chat_id, spent));
+            }
+            Ok(reply.text)
+        }
+        None => Ok(execute_prompt(prompt, None, &ClaudeOptions::gateway(&config.gateway), config, chat_id).await?.0),
+    }
+}
+
+/// Answer an accepted message: run a `/task`, or send it to Claude through
+/// the persistent session or a spawned `claude -p`, with its attachments.
+/// Stops the typing indicator before replying.
+async fn respond(
+    bot: &Bot,
+    msg: &Message,
+    text: &str,
+    attachments: &[attachments::Attachment],
+    state: &GatewayState,
+    typing: &Typing,
+) {
+    let chat_id = msg.chat.id.0;
+    let config = &state.config();
+    let session_manager = state.session_manager().await;
 
     // Check for /task prefix - route to task system
     if let Some(task_msg) = text.strip_prefix("/task ") {
@@ -148,16 +441,18 @@ async fn handle_message_with_state(bot: Bot, msg: Message, state: &GatewayState)
             "Task command detected"
         );
 
+        let task_msg = attachments::with_paths(task_msg.trim(), attachments);
         match task::classify_and_execute(
-            task_msg.trim(),
-            &state.config,
-            state.session_manager.as_ref(),
+            &task_msg,
+            config,
+            session_manager.as_ref(),
+            chat_id,
         )
         .await
         {
             Ok(Some(response)) => {
                 // Task executed successfully
-                typing_handle.abort();
+                typing.stop();
 
                 tracing::info!(
                     component = "gateway",
@@ -168,7 +463,7 @@ async fn handle_message_with_state(bot: Bot, msg: Message, state: &GatewayState)
 
                 // Send response using TelegramClient
                 let client =
-                    TelegramClient::new(bot.clone(), state.config.gateway.message_format);
+                    TelegramClient::new(bot.clone(), config.gateway.message_format);
                 if let Err(e) = client.send_formatted_message(msg.chat.id, &response).await {
                     tracing::error!(
                         component = "gateway",
@@ -192,7 +487,7 @@ async fn handle_message_with_state(bot: Bot, msg: Message, state: &GatewayState)
             }
             Err(e) => {
                 // Task execution failed
-                typing_handle.abort();
+                typing.stop();
                 tracing::error!(
                     component = "gateway",
                     chat_id = chat_id,
@@ -207,17 +502,24 @@ async fn handle_message_with_state(bot: Bot, msg: Message, state: &GatewayState)
         }
     }
 
+    let prompt = attachments::with_paths(text, attachments);
+
     // Use persistent session if available, otherwise fall back to spawn mode
-    let result = if let Some(ref session_manager) = state.session_manager {
+    let result = if let Some(ref session_manager) = session_manager {
         tracing::debug!(
             component = "gateway",
             chat_id = chat_id,
             "Using persistent session"
         );
-        session_manager
-            .send_message(text)
-            .await
-            .map(|response| (response, None))
+        async {
+            let images = attachments::images(attachments).await?;
+            let reply = session_manager.send_message_with_images(&prompt, images).await?;
+            if let Some(spent) = reply.usage {
+                usage::record(config, UsageEntry::gateway(chat_id, spent));
+            }
+            Ok((reply.text, None))
+        }
+        .await
     } else {
         // Fall back to original spawn mode
         let existing_session = {
@@ -228,7 +530,7 @@ async fn handle_message_with_state(bot: Bot, msg: Message, state: &GatewayState)
         let resume_session_id = match existing_session {
             Some(ref session) => {
                 let elapsed = (Utc::now() - session.last_message_at).num_seconds() as u64;
-                if elapsed < state.config.gateway.session_timeout_secs {
+                if elapsed < config.gateway.session_timeout_secs {
                     tracing::debug!(
                         component = "gateway",
                         chat_id = chat_id,
@@ -242,7 +544,7 @@ async fn handle_message_with_state(bot: Bot, msg: Message, state: &GatewayState)
                         component = "gateway",
                         chat_id = chat_id,
                         idle_secs = elapsed,
-                        timeout_secs = state.config.gateway.session_timeout_secs,
+                        timeout_secs = config.gateway.session_timeout_secs,
                         "Session expired, starting new"
                     );
                     None
@@ -258,11 +560,12 @@ async fn handle_message_with_state(bot: Bot, msg: Message, state: &GatewayState)
             }
         };
 
-        execute_prompt(text, resume_session_id.as_deref(), &state.config, chat_id).await
+        let options = ClaudeOptions::gateway(&config.gateway);
+        execute_prompt(&prompt, resume_session_id.as_deref(), &options, config, chat_id).await
     };
 
     // Stop typing indicator
-    typing_handle.abort();
+    typing.stop();
 
     match result {
         Ok((response, new_session_id)) => {
@@ -285,7 +588,7 @@ async fn handle_message_with_state(bot: Bot, msg: Message, state: &GatewayState)
             }
 
             // Send formatted message using TelegramClient
-            let client = TelegramClient::new(bot.clone(), state.config.gateway.message_format);
+            let client = TelegramClient::new(bot.clone(), config.gateway.message_format);
             if let Err(e) = client.send_formatted_message(msg.chat.id, &response).await {
                 tracing::error!(
                     component = "gateway",
@@ -304,7 +607,7 @@ async fn handle_message_with_state(bot: Bot, msg: Message, state: &GatewayState)
             );
 
             // If using spawn mode and resume failed, clear the session
-            if state.session_manager.is_none() {
+            if session_manager.is_none() {
                 let mut map = state.sessions.lock().await;
                 map.remove(&chat_id);
                 tracing::debug!(
@@ -321,43 +624,101 @@ async fn handle_message_with_state(bot: Bot, msg: Message, state: &GatewayState)
     }
 }
 
-/// Execute a prompt via claude CLI, optionally resuming a session.
-/// Returns (response_text, Option<session_id>).
+/// Claude settings for `execute_prompt`: the gateway's, or a job's when
+/// following up on one of its runs.
+#[derive(Debug, Clone, Default)]
+struct ClaudeOptions {
+    model: String,
+    allowed_tools: Vec<String>,
+    disallowed_tools: Vec<String>,
+    system_prompt: String,
+    append_system_prompt: String,
+    mcp_config: String,
+    max_turns: u32,
+    max_budget_usd: f64,
+    /// Sessions are per directory, so resuming a job's session needs the
+    /// job's `working_dir`
+    working_dir: Option<String>,
+}
+
+impl ClaudeOptions {
+    fn gateway(config: &GatewayConfig) -> Self {
+        Self {
+            model: config.default_model.clone(),
+            allowed_tools: config.allowed_tools.clone(),
+            disallowed_tools: config.disallowed_tools.clone(),
+            append_system_prompt: config.append_system_prompt.clone(),
+            max_turns: config.max_turns,
+            max_budget_usd: config.max_budget_usd,
+            ..Default::default()
+        }
+    }
+
+    fn job(job: &Job) -> Self {
+        Self {
+            model: job.model.clone(),
+            allowed_tools: job.allowed_tools.clone(),
+            disallowed_tools: job.disallowed_tools.clone(),
+            system_prompt: job.system_prompt.clone(),
+            append_system_prompt: job.append_system_prompt.clone(),
+            mcp_config: job.mcp_config.clone(),
+            max_turns: job.max_turns,
+            max_budget_usd: job.max_budget_usd,
+            working_dir: Some(job.working_dir.clone()).filter(|d| !d.is_empty()),
+        }
+    }
+}
+
+/// Execute a prompt via claude CLI with `options`, optionally resuming a
+/// session. Returns (response_text, Option<session_id>).
 async fn execute_prompt(
     prompt: &str,
     resume_session_id: Option<&str>,
+    options: &ClaudeOptions,
     config: &DemonConfig,
     chat_id: i64,
 ) -> Result<(String, Option<String>)> {
     let mut cmd = tokio::process::Command::new("claude");
     cmd.arg("-p");
 
+    if let Some(dir) = &options.working_dir {
+        cmd.current_dir(dir);
+    }
+
     // Resume existing session if available
     if let Some(session_id) = resume_session_id {
         cmd.arg("--resume").arg(session_id);
     }
 
-    if !config.gateway.default_model.is_empty() {
-        cmd.arg("--model").arg(&config.gateway.default_model);
+    if !options.model.is_empty() {
+        cmd.arg("--model").arg(&options.model);
     }
 
-    for tool in &config.gateway.allowed_tools {
+    for tool in &options.allowed_tools {
         cmd.arg("--allowedTools").arg(tool);
     }
 
-    for tool in &config.gateway.disallowed_tools {
+    for tool in &options.disallowed_tools {
         cmd.arg("--disallowedTools").arg(tool);
     }
 
-    if !config.gateway.append_system_prompt.is_empty() {
+    if !options.system_prompt.is_empty() {
+        cmd.arg("--system-prompt").arg(&options.system_prompt);
+    }
+
+    if !options.append_system_prompt.is_empty() {
         cmd.arg("--append-system-prompt")
-            .arg(&config.gateway.append_system_prompt);
+            .arg(&options.append_system_prompt);
+    }
+
+    if !options.mcp_config.is_empty() {
+        cmd.arg("--mcp-config").arg(&options.mcp_config);
     }
 
     cmd.arg("--max-turns")
-        .arg(config.gateway.max_turns.to_string());
+        .arg(options.max_turns.to_string());
     cmd.arg("--max-budget-usd")
-        .arg(format!("{:.2}", config.gateway.max_budget_usd));
+        .arg(format!("{:.2}", options.max_budget_usd));
 
     // Always use JSON output to capture session_id
     cmd.arg("--output-format").arg("json");
@@ -367,56 +728,30 @@ async fn execute_prompt(
     tracing::debug!(
         component = "gateway",
         chat_id = chat_id,
-        model = %config.gateway.default_model,
+        model = %options.model,
         resume_session = resume_session_id.is_some(),
         "Spawning claude CLI"
     );
 
-    // Spawn with piped outputs so we can capture both
-    cmd.stdout(std::process::Stdio::piped());
-    cmd.stderr(std::process::Stdio::piped());
-
-    let child = cmd.spawn().context("Failed to spawn claude CLI")?;
-    let pid = child.id().unwrap_or(0);
-
-    tracing::debug!(
-        component = "gateway",
-        chat_id = chat_id,
-        pid = pid,
-        "Claude process started"
-    );
-
-    // Wait with timeout
-    let timeout_secs = config.gateway.max_turns as u64 * 30 + 60;
-    let result = tokio::time::timeout(
-        tokio::time::Duration::from_secs(timeout_secs),
-        child.wait_with_output(),
+    // Wait with timeout, killing claude's whole process group if it hangs
+    let timeout_secs = options.max_turns as u64 * 30 + 60;
+    let output = match process::output_with_timeout(
+        &mut cmd,
+        Some(tokio::time::Duration::from_secs(timeout_secs)),
     )
-    .await;
-
-    let output = match result {
-        Ok(Ok(output)) => output,
-        Ok(Err(e)) => {
-            anyhow::bail!("claude process error: {e}");
-        }
-        Err(_) => {
-            // Timeout - kill the process by PID
+    .await
+    {
+        Ok(output) => output,
+        Err(ProcessError::TimedOut { .. }) => {
             tracing::warn!(
                 component = "gateway",
                 chat_id = chat_id,
-                pid = pid,
                 timeout_secs = timeout_secs,
-                "Claude process timed out, killing"
+                "Claude process timed out, killed"
             );
-            #[cfg(unix)]
-            {
-                let _ = nix::sys::signal::kill(
-                    nix::unistd::Pid::from_raw(pid as i32),
-                    nix::sys::signal::Signal::SIGKILL,
-                );
-            }
             anyhow::bail!("claude timed out after {timeout_secs}s");
         }
+        Err(ProcessError::Io(e)) => return Err(e).context("Failed to spawn claude CLI"),
     };
 
     let stdout = String::from_utf8_lossy(&output.stdout);
@@ -444,6 +779,9 @@ async fn execute_prompt(
         // Parse JSON to extract result and session_id
         match serde_json::from_str::<serde_json::Value>(&stdout) {
             Ok(json) => {
+                if let Some(spent) = Usage::from_result(&json) {
+                    usage::record(config, UsageEntry::gateway(chat_id, spent));
+                }
                 let result_text = json
                     .get("result")
                     .and_then(|v| v.as_str())
@@ -484,3 +822,49 @@ async fn execute_prompt(
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{timeout, Duration};
+
+    fn state() -> GatewayState {
+        state_with(DemonConfig::default())
+    }
+
+    pub(super) fn state_with(config: DemonConfig) -> GatewayState {
+        GatewayState {
+            config: watch::channel(config).1,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            session_manager: RwLock::new(None),
+            shutdown: Shutdown::new(),
+            chats: std::sync::Mutex::new(HashMap::new()),
+            drafts: std::sync::Mutex::new(HashMap::new()),
+            follow_ups: std::sync::Mutex::new(HashMap::new()),
+            running: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_queue_orders_and_cancels() {
+        let state = state();
+        let first = state.queue(1);
+        let mut second = state.queue(1);
+        assert!(first.previous.is_none());
+        assert!(state.queue(2).previous.is_none());
+
+        // The second request waits for the first
+        let previous = second.previous.as_mut().unwrap();
+        assert!(timeout(Duration::from_millis(50), &mut *previous).await.is_err());
+        drop(first.done);
+        timeout(Duration::from_secs(1), previous).await.unwrap().unwrap_err();
+
+        assert_eq!(state.cancel(1), 2);
+        assert!(second.cancel.has_changed().unwrap());
+        assert_ne!(*second.cancel.borrow(), second.generation);
+
+        state.finish(1);
+        state.finish(1);
+        assert_eq!(state.cancel(1), 0);
+    }
+}
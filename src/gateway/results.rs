@@ -0,0 +1,239 @@
+//! Buttons on job results sent to Telegram: Run again, Disable job, Show
+//! prompt and Ask follow-up.
+//!
+//! The callback data names the job and the run, since the result may come
+//! from a daemon other than this gateway. Ask follow-up resumes the run's
+//! claude session (from the run history) in the job's working directory;
+//! the chat's messages continue that session until `/new`.
+
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup};
+
+use super::{commands, GatewayState, TelegramClient};
+use crate::config::{DemonConfig, Job};
+use crate::history;
+use crate::template::TemplateContext;
+
+/// Callback data of the result buttons: `result:<action>:<run>:<job id>`.
+pub(super) const CALLBACK_PREFIX: &str = "result";
+
+/// Characters of the run ID kept in callback data.
+const RUN_PREFIX_LEN: usize = 8;
+
+/// Telegram's limit on callback data, in bytes.
+const MAX_CALLBACK_DATA: usize = 64;
+
+/// A job run whose claude session the chat is continuing.
+#[derive(Clone)]
+pub(super) struct FollowUp {
+    job_name: String,
+    session_id: String,
+    /// The job's claude settings, which the session was started with
+    options: super::ClaudeOptions,
+}
+
+/// Buttons for the result of run `run_id` of job `job_id`. Ask follow-up
+/// is left out when the run has no session to resume. `None` if the job
+/// ID is too long to fit in callback data.
+pub fn result_keyboard(job_id: &str, run_id: &str, resumable: bool) -> Option<InlineKeyboardMarkup> {
+    let run = &run_id[..run_id.len().min(RUN_PREFIX_LEN)];
+    let data = |action: &str| format!("{CALLBACK_PREFIX}:{action}:{run}:{job_id}");
+    if data("followup").len() > MAX_CALLBACK_DATA {
+        return None;
+    }
+
+    let mut second = vec![InlineKeyboardButton::callback("Show prompt", data("prompt"))];
+    if resumable {
+        second.push(InlineKeyboardButton::callback("Ask follow-up", data("followup")));
+    }
+    Some(InlineKeyboardMarkup::new([
+        vec![
+            InlineKeyboardButton::callback("Run again", data("run")),
+            InlineKeyboardButton::callback("Disable job", data("disable")),
+        ],
+        second,
+    ]))
+}
+
+/// Handle a press of a result button; `data` is the callback data after
+/// the prefix.
+pub(super) async fn handle_callback(
+    bot: &Bot,
+    chat: ChatId,
+    data: &str,
+    state: &Arc<GatewayState>,
+) -> Result<String> {
+    let mut parts = data.splitn(3, ':');
+    let (Some(action), Some(run), Some(job_id)) = (parts.next(), parts.next(), parts.next()) else {
+        anyhow::bail!("Malformed button data '{}'", data);
+    };
+    let config = state.config();
+    let client = TelegramClient::new(bot.clone(), config.gateway.message_format);
+
+    match action {
+        "run" => {
+            commands::run(bot, chat, job_id, &config, state).await?;
+            Ok(String::new())
+        }
+        "disable" => {
+            let text = commands::toggle(&config, job_id, false).await?;
+            client.send_formatted_message(chat, &text).await?;
+            Ok("Job disabled".to_string())
+        }
+        "prompt" => {
+            let job = rendered(&config, job_id)?;
+            client
+                .send_formatted_message(chat, &format!("**Prompt of {}**\n\n{}", job.name, job.prompt))
+                .await?;
+            Ok(String::new())
+        }
+        "followup" => {
+            let record = history::find_run(&config, job_id, run)?
+                .context("That run is no longer in the run history")?;
+            let session_id = record
+                .session_id
+                .context("That run has no claude session to resume (the job needs output_format = \"json\")")?;
+            let job = rendered(&config, job_id)?;
+
+            let follow_up = FollowUp {
+                job_name: job.name.clone(),
+                session_id,
+                options: super::ClaudeOptions::job(&job),
+            };
+            state.follow_ups.lock().unwrap().insert(chat.0, follow_up);
+            tracing::info!(component = "gateway", chat_id = chat.0, job_id = %job_id, "Following up on job run");
+
+            client
+                .send_formatted_message(
+                    chat,
+                    &format!(
+                        "Ask your follow-up about **{}**. Your messages continue the job's session until you send /new.",
+                        job.name
+                    ),
+                )
+                .await?;
+            Ok(String::new())
+        }
+        other => anyhow::bail!("Unknown result action '{}'", other),
+    }
+}
+
+/// The job run the chat is following up on, if any.
+pub(super) fn follow_up(state: &GatewayState, chat_id: i64) -> Option<FollowUp> {
+    state.follow_ups.lock().unwrap().get(&chat_id).cloned()
+}
+
+/// Stop following up on a job run. Returns whether the chat was.
+pub(super) fn end_follow_up(state: &GatewayState, chat_id: i64) -> bool {
+    state.follow_ups.lock().unwrap().remove(&chat_id).is_some()
+}
+
+/// Send a message to the job run's claude session and reply with the answer.
+pub(super) async fn ask(bot: &Bot, chat: ChatId, follow_up: FollowUp, question: &str, state: &Arc<GatewayState>) {
+    let reply_bot = bot.clone();
+    let reply_state = state.clone();
+    let question = question.to_string();
+    state
+        .spawn_request(bot, chat, move |typing| async move {
+            let config = reply_state.config();
+            tracing::debug!(
+                component = "gateway",
+                chat_id = chat.0,
+                job_name = %follow_up.job_name,
+                session_id = %follow_up.session_id,
+                "Resuming job session"
+            );
+            let result = super::execute_prompt(
+                &question,
+                Some(&follow_up.session_id),
+                &follow_up.options,
+                &config,
+                chat.0,
+            )
+            .await;
+            typing.stop();
+
+            match result {
+                Ok((answer, session_id)) => {
+                    // Keep following the conversation if claude moved it to a new session
+                    if let Some(session_id) = session_id {
+                        if let Some(current) = reply_state.follow_ups.lock().unwrap().get_mut(&chat.0) {
+                            if current.session_id == follow_up.session_id {
+                                current.session_id = session_id;
+                            }
+                        }
+                    }
+                    let client = TelegramClient::new(reply_bot.clone(), config.gateway.message_format);
+                    if let Err(e) = client.send_formatted_message(chat, &answer).await {
+                        tracing::error!(
+                            component = "gateway",
+                            chat_id = chat.0,
+                            error = %e,
+                            "Failed to send follow-up answer"
+                        );
+                    }
+                }
+                Err(e) => {
+                    tracing::error!(component = "gateway", chat_id = chat.0, error = %e, "Follow-up failed");
+                    let _ = reply_bot.send_message(chat, format!("Error: {e:#}")).await;
+                }
+            }
+        })
+        .await;
+}
+
+/// The job with its prompt read and template variables expanded, as
+/// `demon job render` shows it.
+fn rendered(config: &DemonConfig, job_id: &str) -> Result<Job> {
+    let job = config
+        .load_jobs()?
+        .into_iter()
+        .find(|j| j.id == job_id)
+        .context(format!("Job '{}' not found", job_id))?;
+    let mut ctx = TemplateContext::for_job(&job, config);
+    if job.schedule_type == "after" {
+        // Only known when an upstream run triggers the job
+        ctx.set("upstream_result", "<result of upstream run>")
+            .set("upstream_job", job.depends_on.first().map_or("", |d| d.as_str()));
+    }
+    ctx.render_job(&job, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn callback_data(keyboard: &InlineKeyboardMarkup) -> Vec<String> {
+        keyboard
+            .inline_keyboard
+            .iter()
+            .flatten()
+            .filter_map(|b| match &b.kind {
+                teloxide::types::InlineKeyboardButtonKind::CallbackData(data) => Some(data.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_result_keyboard() {
+        let run_id = "0123456789abcdef";
+        let keyboard = result_keyboard("standup", run_id, true).unwrap();
+        assert_eq!(
+            callback_data(&keyboard),
+            [
+                "result:run:01234567:standup",
+                "result:disable:01234567:standup",
+                "result:prompt:01234567:standup",
+                "result:followup:01234567:standup",
+            ]
+        );
+
+        let keyboard = result_keyboard("standup", run_id, false).unwrap();
+        assert_eq!(callback_data(&keyboard).len(), 3);
+
+        assert!(result_keyboard(&"x".repeat(50), run_id, true).is_none());
+    }
+}
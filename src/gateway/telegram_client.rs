@@ -1,6 +1,6 @@
 use anyhow::{Context, Result};
 use teloxide::prelude::*;
-use teloxide::types::ChatId;
+use teloxide::types::{ChatId, InlineKeyboardMarkup};
 
 use crate::formatter::{create_formatter, MessageFormat, MessageSplitter};
 
@@ -24,6 +24,25 @@ impl TelegramClient {
     /// Send a message with formatting, automatically splitting if needed
     /// Falls back to plain text if formatting fails
     pub async fn send_formatted_message(&self, chat_id: ChatId, text: &str) -> Result<()> {
+        self.send(chat_id, text, None).await
+    }
+
+    /// Like `send_formatted_message`, with inline buttons under the last chunk
+    pub async fn send_formatted_message_with_keyboard(
+        &self,
+        chat_id: ChatId,
+        text: &str,
+        keyboard: InlineKeyboardMarkup,
+    ) -> Result<()> {
+        self.send(chat_id, text, Some(keyboard)).await
+    }
+
+    async fn send(
+        &self,
+        chat_id: ChatId,
+        text: &str,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<()> {
         let formatter = create_formatter(self.format);
 
         // Format the text, with fallback to plain text on error
@@ -44,15 +63,15 @@ impl TelegramClient {
         let chunks = splitter.split(&formatted);
 
         // Send each chunk
-        for chunk in chunks {
-            let result = if let Some(parse_mode) = self.format.as_parse_mode() {
-                self.bot
-                    .send_message(chat_id, chunk)
-                    .parse_mode(parse_mode)
-                    .await
-            } else {
-                self.bot.send_message(chat_id, chunk).await
-            };
+        let last = chunks.len().saturating_sub(1);
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let markup = keyboard.clone().filter(|_| i == last);
+            let mut request = self.bot.send_message(chat_id, chunk);
+            request.reply_markup = markup.clone().map(Into::into);
+            if let Some(parse_mode) = self.format.as_parse_mode() {
+                request = request.parse_mode(parse_mode);
+            }
+            let result = request.await;
 
             match result {
                 Ok(_) => {}
@@ -64,8 +83,9 @@ impl TelegramClient {
                             e
                         );
                         eprintln!("[demon] Formatted send failed, retrying as plain text");
-                        self.bot
-                            .send_message(chat_id, chunk)
+                        let mut request = self.bot.send_message(chat_id, chunk);
+                        request.reply_markup = markup.map(Into::into);
+                        request
                             .await
                             .context("Failed to send message even as plain text")?;
                     } else {
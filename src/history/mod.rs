@@ -0,0 +1,383 @@
+//! Persistent job run history.
+//!
+//! Every job execution is appended as one JSON line to
+//! ~/.demon/history/<job_id>.jsonl, recording timing, exit status,
+//! cost and turns parsed from the claude JSON result, and where the
+//! output was written.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::DemonConfig;
+use crate::scheduler::JobError;
+use crate::store;
+
+/// Final status of a job run.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Success,
+    Failed,
+    /// Killed after exceeding the job's `timeout_secs`
+    Timeout,
+    /// Not run, e.g. because a previous run was still in progress
+    Skipped,
+}
+
+impl RunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failed => "failed",
+            Self::Timeout => "timeout",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+impl std::fmt::Display for RunStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What caused a job to run.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunTrigger {
+    /// Fired by the scheduler
+    #[default]
+    Schedule,
+    /// Started from the CLI with `demon job run`
+    Manual,
+    /// Replay of a run missed while the daemon was down
+    CatchUp,
+    /// Fired by the success of a job listed in `depends_on`
+    Dependency,
+    /// Started from a Telegram chat with `/run`
+    Chat,
+}
+
+impl RunTrigger {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Schedule => "schedule",
+            Self::Manual => "manual",
+            Self::CatchUp => "catch-up",
+            Self::Dependency => "dependency",
+            Self::Chat => "chat",
+        }
+    }
+}
+
+/// A single entry in the run ledger.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub run_id: String,
+    pub job_id: String,
+    #[serde(default)]
+    pub trigger: RunTrigger,
+    /// The scheduled instant this run was fired for
+    #[serde(default)]
+    pub scheduled_at: Option<DateTime<Utc>>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub status: RunStatus,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub cost_usd: Option<f64>,
+    #[serde(default)]
+    pub num_turns: Option<u32>,
+    #[serde(default)]
+    pub output_path: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+    /// 1 for the first attempt, incremented on each retry
+    #[serde(default = "default_attempt")]
+    pub attempt: u32,
+    /// claude's session, for resuming the conversation (JSON output only)
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+fn default_attempt() -> u32 {
+    1
+}
+
+impl RunRecord {
+    /// Build a record from the outcome of `scheduler::execute_job`.
+    /// The run is considered finished now.
+    pub fn from_outcome(
+        job_id: &str,
+        trigger: RunTrigger,
+        started_at: DateTime<Utc>,
+        outcome: &Result<String>,
+        output_path: Option<&Path>,
+    ) -> Self {
+        let finished_at = Utc::now();
+        let duration_ms = (finished_at - started_at).num_milliseconds().max(0) as u64;
+
+        let session_id = match outcome {
+            Ok(output) => session_id(output),
+            Err(e) => match e.downcast_ref::<JobError>() {
+                Some(JobError::Result { output, .. }) => session_id(output),
+                _ => None,
+            },
+        };
+        let (status, exit_code, cost_usd, num_turns, error) = match outcome {
+            Ok(output) => {
+                let (cost, turns) = parse_result_metrics(output);
+                (RunStatus::Success, Some(0), cost, turns, None)
+            }
+            Err(e) => {
+                let (status, exit_code, (cost, turns)) = match e.downcast_ref::<JobError>() {
+                    Some(JobError::Exit { code, .. }) => (RunStatus::Failed, *code, (None, None)),
+                    Some(JobError::Result { output, .. }) => {
+                        (RunStatus::Failed, Some(0), parse_result_metrics(output))
+                    }
+                    Some(JobError::Timeout { .. }) => (RunStatus::Timeout, None, (None, None)),
+                    _ => (RunStatus::Failed, None, (None, None)),
+                };
+                (status, exit_code, cost, turns, Some(format!("{e:#}")))
+            }
+        };
+
+        Self {
+            run_id: uuid::Uuid::new_v4().to_string(),
+            job_id: job_id.to_string(),
+            trigger,
+            scheduled_at: None,
+            started_at,
+            finished_at,
+            status,
+            exit_code,
+            duration_ms,
+            cost_usd,
+            num_turns,
+            output_path: output_path.map(|p| p.to_string_lossy().to_string()),
+            error,
+            attempt: 1,
+            session_id,
+        }
+    }
+
+    /// Build a record for a run that was skipped without executing.
+    pub fn skipped(
+        job_id: &str,
+        trigger: RunTrigger,
+        scheduled_at: Option<DateTime<Utc>>,
+        reason: &str,
+    ) -> Self {
+        let now = Utc::now();
+        Self {
+            run_id: uuid::Uuid::new_v4().to_string(),
+            job_id: job_id.to_string(),
+            trigger,
+            scheduled_at,
+            started_at: now,
+            finished_at: now,
+            status: RunStatus::Skipped,
+            exit_code: None,
+            duration_ms: 0,
+            cost_usd: None,
+            num_turns: None,
+            output_path: None,
+            error: Some(reason.to_string()),
+            attempt: 1,
+            session_id: None,
+        }
+    }
+}
+
+/// Extract `total_cost_usd` and `num_turns` from claude's JSON result.
+/// Returns `(None, None)` for text output.
+pub fn parse_result_metrics(output: &str) -> (Option<f64>, Option<u32>) {
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(output.trim()) else {
+        return (None, None);
+    };
+    let cost = json.get("total_cost_usd").and_then(|v| v.as_f64());
+    let turns = json
+        .get("num_turns")
+        .and_then(|v| v.as_u64())
+        .map(|n| n as u32);
+    (cost, turns)
+}
+
+/// The `session_id` of claude's JSON output.
+pub fn session_id(output: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(output.trim())
+        .ok()?
+        .get("session_id")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// The result text of a claude run: the `result` field of JSON output,
+/// or the raw output for text format.
+pub fn result_text(output: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(output.trim())
+        .ok()
+        .and_then(|v| v.get("result").and_then(|r| r.as_str()).map(str::to_string))
+        .unwrap_or_else(|| output.trim().to_string())
+}
+
+fn last_result_file(config: &DemonConfig, job_id: &str) -> PathBuf {
+    config.paths.history_dir().join(format!("{job_id}.last-result"))
+}
+
+/// Store the result text of a job's latest successful run.
+pub fn save_last_result(config: &DemonConfig, job_id: &str, result: &str) -> Result<()> {
+    store::replace(&last_result_file(config, job_id), result).context("Failed to write last result")
+}
+
+/// The result text of a job's latest successful run, if any.
+pub fn last_result(config: &DemonConfig, job_id: &str) -> Option<String> {
+    fs::read_to_string(last_result_file(config, job_id)).ok()
+}
+
+fn history_file(config: &DemonConfig, job_id: &str) -> PathBuf {
+    config.paths.history_dir().join(format!("{job_id}.jsonl"))
+}
+
+/// Append a run record to the job's history file.
+pub fn append(config: &DemonConfig, record: &RunRecord) -> Result<()> {
+    let path = history_file(config, &record.job_id);
+    fs::create_dir_all(path.parent().unwrap()).context("Failed to create history directory")?;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context("Failed to open history file")?;
+
+    let line = serde_json::to_string(record)?;
+    writeln!(file, "{line}").context("Failed to write history record")?;
+    Ok(())
+}
+
+/// Load the run history for a job, oldest first.
+/// Lines that fail to parse are skipped.
+pub fn load(config: &DemonConfig, job_id: &str) -> Result<Vec<RunRecord>> {
+    let path = history_file(config, job_id);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let file = fs::File::open(&path).context("Failed to open history file")?;
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.context("Failed to read history file")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<RunRecord>(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                tracing::warn!(job_id = %job_id, error = %e, "Skipping malformed history record");
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Return the most recent run of a job, if any.
+pub fn last_run(config: &DemonConfig, job_id: &str) -> Result<Option<RunRecord>> {
+    Ok(load(config, job_id)?.pop())
+}
+
+/// Return the most recent run of a job with the given status, if any.
+pub fn last_with_status(
+    config: &DemonConfig,
+    job_id: &str,
+    status: RunStatus,
+) -> Result<Option<RunRecord>> {
+    Ok(load(config, job_id)?
+        .into_iter()
+        .rev()
+        .find(|r| r.status == status))
+}
+
+/// The run of a job whose `run_id` starts with `prefix`, latest first.
+pub fn find_run(config: &DemonConfig, job_id: &str, prefix: &str) -> Result<Option<RunRecord>> {
+    Ok(load(config, job_id)?
+        .into_iter()
+        .rev()
+        .find(|r| r.run_id.starts_with(prefix)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathsConfig;
+
+    fn temp_config() -> DemonConfig {
+        let dir = std::env::temp_dir().join(format!("demon-history-{}", uuid::Uuid::new_v4()));
+        DemonConfig {
+            paths: PathsConfig {
+                base_dir: Some(dir.to_string_lossy().to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_result_metrics() {
+        let output = r#"{"type":"result","result":"done","num_turns":3,"total_cost_usd":0.042}"#;
+        assert_eq!(parse_result_metrics(output), (Some(0.042), Some(3)));
+        assert_eq!(parse_result_metrics("plain text"), (None, None));
+    }
+
+    #[test]
+    fn test_successful_outcome_keeps_session_id() {
+        let outcome: Result<String> = Ok(r#"{"type":"result","result":"done","session_id":"abc-123"}"#.to_string());
+        let record = RunRecord::from_outcome("job", RunTrigger::Manual, Utc::now(), &outcome, None);
+        assert_eq!(record.session_id.as_deref(), Some("abc-123"));
+
+        let outcome: Result<String> = Ok("plain text".to_string());
+        let record = RunRecord::from_outcome("job", RunTrigger::Manual, Utc::now(), &outcome, None);
+        assert_eq!(record.session_id, None);
+    }
+
+    #[test]
+    fn test_failed_outcome_keeps_exit_code() {
+        let outcome: Result<String> = Err(JobError::Exit {
+            code: Some(2),
+            status: "exit status: 2".to_string(),
+            stderr: "boom".to_string(),
+        }
+        .into());
+        let record = RunRecord::from_outcome("job", RunTrigger::Manual, Utc::now(), &outcome, None);
+
+        assert_eq!(record.status, RunStatus::Failed);
+        assert_eq!(record.exit_code, Some(2));
+        assert!(record.error.unwrap().contains("boom"));
+    }
+
+    #[test]
+    fn test_append_and_load() {
+        let config = temp_config();
+        for result in ["first", "second"] {
+            let outcome: Result<String> = Ok(result.to_string());
+            let record =
+                RunRecord::from_outcome("job", RunTrigger::Schedule, Utc::now(), &outcome, None);
+            append(&config, &record).unwrap();
+        }
+
+        let records = load(&config, "job").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].status, RunStatus::Success);
+        assert!(load(&config, "other").unwrap().is_empty());
+
+        let found = find_run(&config, "job", &records[0].run_id[..8]).unwrap().unwrap();
+        assert_eq!(found.run_id, records[0].run_id);
+
+        let _ = fs::remove_dir_all(config.paths.base_dir());
+    }
+}
@@ -1,6 +1,6 @@
 //! Centralized logging configuration for cc-demon.
 //!
-//! Provides structured JSON logging to ~/.demon/logs/demon.jsonl,
+//! Provides structured JSON logging to <base_dir>/logs/demon.jsonl,
 //! compatible with the `hl` log viewer (https://github.com/pamburus/hl).
 
 use anyhow::{Context, Result};
@@ -12,19 +12,18 @@ use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, Env
 use crate::config::PathsConfig;
 
 /// Returns the path to the JSONL log file.
-pub fn log_file_path() -> PathBuf {
-    PathsConfig::default().logs_dir().join("demon.jsonl")
+pub fn log_file_path(paths: &PathsConfig) -> PathBuf {
+    paths.logs_dir().join("demon.jsonl")
 }
 
 /// Initialize logging for daemon mode.
-/// Logs structured JSON to ~/.demon/logs/demon.jsonl.
+/// Logs structured JSON to <base_dir>/logs/demon.jsonl.
 /// Returns a guard that must be held for the duration of the program.
-pub fn init_daemon_logging() -> Result<WorkerGuard> {
-    let paths = PathsConfig::default();
+pub fn init_daemon_logging(paths: &PathsConfig) -> Result<WorkerGuard> {
     let log_dir = paths.logs_dir();
     fs::create_dir_all(&log_dir).context("Failed to create logs directory")?;
 
-    let log_file = log_file_path();
+    let log_file = log_file_path(paths);
 
     // Open file for appending (create if doesn't exist)
     let file = OpenOptions::new()
@@ -56,12 +55,11 @@ pub fn init_daemon_logging() -> Result<WorkerGuard> {
 /// Initialize logging for foreground mode.
 /// Logs structured JSON to file AND human-readable format to stderr.
 /// Returns a guard that must be held for the duration of the program.
-pub fn init_foreground_logging() -> Result<WorkerGuard> {
-    let paths = PathsConfig::default();
+pub fn init_foreground_logging(paths: &PathsConfig) -> Result<WorkerGuard> {
     let log_dir = paths.logs_dir();
     fs::create_dir_all(&log_dir).context("Failed to create logs directory")?;
 
-    let log_file = log_file_path();
+    let log_file = log_file_path(paths);
 
     let file = OpenOptions::new()
         .create(true)
@@ -97,8 +95,8 @@ pub fn init_foreground_logging() -> Result<WorkerGuard> {
 /// Rotate the log file by renaming current and starting fresh.
 /// Returns the path to the rotated file.
 #[allow(dead_code)]
-pub fn rotate_log() -> Result<PathBuf> {
-    let log_file = log_file_path();
+pub fn rotate_log(paths: &PathsConfig) -> Result<PathBuf> {
+    let log_file = log_file_path(paths);
     if !log_file.exists() {
         anyhow::bail!("No log file to rotate");
     }
@@ -115,8 +113,8 @@ pub fn rotate_log() -> Result<PathBuf> {
 
 /// Clear the log file (truncate to zero bytes).
 #[allow(dead_code)]
-pub fn clear_log() -> Result<()> {
-    let log_file = log_file_path();
+pub fn clear_log(paths: &PathsConfig) -> Result<()> {
+    let log_file = log_file_path(paths);
     if log_file.exists() {
         File::create(&log_file).context("Failed to clear log file")?;
     }
@@ -124,8 +122,8 @@ pub fn clear_log() -> Result<()> {
 }
 
 /// Get the size of the current log file in bytes.
-pub fn log_size() -> Result<u64> {
-    let log_file = log_file_path();
+pub fn log_size(paths: &PathsConfig) -> Result<u64> {
+    let log_file = log_file_path(paths);
     if log_file.exists() {
         Ok(fs::metadata(&log_file)?.len())
     } else {
@@ -3,18 +3,25 @@ mod config;
 mod daemon;
 mod formatter;
 mod gateway;
+mod history;
 mod logging;
 mod output;
+mod process;
 mod scheduler;
 mod session;
+mod store;
 mod task;
+mod template;
+mod usage;
 
 use anyhow::Result;
 use clap::Parser;
 use cli::{Cli, Command};
+use config::DemonConfig;
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    let home = cli.home()?;
 
     // Handle daemonization BEFORE starting tokio runtime
     // This is critical because forking with an active async runtime causes issues
@@ -24,12 +31,13 @@ fn main() -> Result<()> {
     } = &cli.command
     {
         // Check if already running before daemonizing
-        if daemon::is_running()? {
-            println!("Demon is already running (PID: {})", daemon::read_pid()?);
+        let config = DemonConfig::load(&home)?;
+        if daemon::is_running(&config.paths)? {
+            println!("Demon is already running (PID: {})", daemon::read_pid(&config.paths)?);
             return Ok(());
         }
         println!("Starting demon daemon...");
-        daemon::daemonize(*with_gateway)?;
+        daemon::daemonize(&home, config, *with_gateway)?;
         println!("Demon started successfully");
         return Ok(());
     }
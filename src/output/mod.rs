@@ -1,30 +1,163 @@
 use anyhow::{Context, Result};
 use chrono::Local;
+use std::path::{Path, PathBuf};
+use teloxide::types::InlineKeyboardMarkup;
 
 use crate::config::{DemonConfig, Job};
-use crate::gateway::TelegramClient;
+use crate::gateway::{self, TelegramClient};
+use crate::scheduler::JobError;
 
-pub async fn route(job: &Job, result: &str, config: &DemonConfig) -> Result<()> {
-    for dest in &job.output_destinations {
+/// Longest stderr excerpt included in a failure notification.
+const STDERR_EXCERPT_CHARS: usize = 1500;
+
+/// Deliver the result of run `run_id` of a job to each of its output
+/// destinations. Telegram messages get buttons to act on the result.
+/// Returns the path of the saved file when the `file` destination is used.
+pub async fn route(job: &Job, run_id: &str, result: &str, config: &DemonConfig) -> Result<Option<PathBuf>> {
+    let title = format!("Job: {}", job.name);
+    let keyboard = keyboard(job, run_id, result);
+    deliver(job, &job.output_destinations, &title, extract_result(result), keyboard, config).await
+}
+
+/// Whether the job's results get an Ask follow-up button: they go to
+/// Telegram and its JSON output names the claude session to resume. Runs
+/// of such jobs have to keep their session.
+pub fn offers_follow_up(job: &Job) -> bool {
+    job.output_format == "json" && job.output_destinations.iter().any(|d| d.starts_with("telegram:"))
+}
+
+/// Buttons for the result of run `run_id`; Ask follow-up only when the
+/// run's session was kept.
+pub fn keyboard(job: &Job, run_id: &str, result: &str) -> Option<InlineKeyboardMarkup> {
+    let resumable = offers_follow_up(job) && crate::history::session_id(result).is_some();
+    gateway::result_keyboard(&job.id, run_id, resumable)
+}
+
+/// Deliver a failure notification for a job whose final attempt failed to
+/// its `on_failure_destinations`, falling back to `output_destinations`.
+pub async fn route_failure(
+    job: &Job,
+    attempts: u32,
+    error: &anyhow::Error,
+    config: &DemonConfig,
+) -> Result<Option<PathBuf>> {
+    let destinations = job
+        .on_failure_destinations
+        .as_ref()
+        .unwrap_or(&job.output_destinations);
+    let title = format!("Job failed: {}", job.name);
+    let body = failure_message(attempts, error);
+    deliver(job, destinations, &title, &body, None, config).await
+}
+
+/// Deliver a short success notice to the job's `on_success_destinations`.
+pub async fn route_success(
+    job: &Job,
+    attempts: u32,
+    duration_secs: i64,
+    output_path: Option<&Path>,
+    config: &DemonConfig,
+) -> Result<()> {
+    if job.on_success_destinations.is_empty() {
+        return Ok(());
+    }
+    let title = format!("Job succeeded: {}", job.name);
+    let mut body = format!(
+        "Completed in {} after {}",
+        crate::scheduler::format_duration(duration_secs),
+        plural(attempts, "attempt")
+    );
+    if let Some(path) = output_path {
+        body.push_str(&format!("\nOutput: `{}`", path.display()));
+    }
+    deliver(job, &job.on_success_destinations, &title, &body, None, config).await?;
+    Ok(())
+}
+
+/// Check that a destination is `file` or `telegram:<chat_id>`.
+pub fn check_destination(dest: &str) -> Result<()> {
+    match dest {
+        "file" => Ok(()),
+        d if d.starts_with("telegram:") => {
+            d.strip_prefix("telegram:")
+                .unwrap()
+                .parse::<i64>()
+                .context(format!("Invalid Telegram chat ID in destination '{}'", d))?;
+            Ok(())
+        }
+        other => anyhow::bail!("Unknown destination '{}' (expected \"file\" or \"telegram:<chat_id>\")", other),
+    }
+}
+
+async fn deliver(
+    job: &Job,
+    destinations: &[String],
+    title: &str,
+    body: &str,
+    keyboard: Option<InlineKeyboardMarkup>,
+    config: &DemonConfig,
+) -> Result<Option<PathBuf>> {
+    let mut saved_path = None;
+    for dest in destinations {
         match dest.as_str() {
-            "file" => save_to_file(job, result, config)?,
+            "file" => saved_path = Some(save_to_file(job, title, body, config)?),
             d if d.starts_with("telegram:") => {
                 let chat_id: i64 = d
                     .strip_prefix("telegram:")
                     .unwrap()
                     .parse()
                     .context("Invalid Telegram chat ID in output destination")?;
-                send_to_telegram(job, result, chat_id, config).await?;
+                send_to_telegram(job, title, body, chat_id, keyboard.clone(), config).await?;
             }
             other => {
                 tracing::warn!("Unknown output destination '{}' for job '{}'", other, job.id);
             }
         }
     }
-    Ok(())
+    Ok(saved_path)
 }
 
-fn save_to_file(job: &Job, result: &str, config: &DemonConfig) -> Result<()> {
+/// Markdown body of a failure notification: attempts, exit status and
+/// the tail of stderr or claude's error message.
+fn failure_message(attempts: u32, error: &anyhow::Error) -> String {
+    let (status, detail) = match error.downcast_ref::<JobError>() {
+        Some(JobError::Exit { status, stderr, .. }) => (status.clone(), stderr.as_str()),
+        Some(JobError::Timeout { secs }) => (format!("timed out after {secs}s"), ""),
+        Some(JobError::Spawn(e)) => (format!("failed to start claude: {e}"), ""),
+        Some(JobError::Result { message, .. }) => {
+            ("claude reported an error".to_string(), message.as_str())
+        }
+        _ => (format!("{error:#}"), ""),
+    };
+
+    let mut text = format!("Attempts: {attempts}\nStatus: {status}");
+    let excerpt = tail(detail.trim(), STDERR_EXCERPT_CHARS);
+    if !excerpt.is_empty() {
+        // Keep the excerpt from closing the code block early
+        text.push_str(&format!("\n\n```\n{}\n```", excerpt.replace("```", "'''")));
+    }
+    text
+}
+
+/// The last `max_chars` characters of `s`, marked when truncated.
+fn tail(s: &str, max_chars: usize) -> String {
+    let count = s.chars().count();
+    if count <= max_chars {
+        return s.to_string();
+    }
+    let rest: String = s.chars().skip(count - max_chars).collect();
+    format!("...{rest}")
+}
+
+fn plural(n: u32, word: &str) -> String {
+    if n == 1 {
+        format!("{n} {word}")
+    } else {
+        format!("{n} {word}s")
+    }
+}
+
+fn save_to_file(job: &Job, title: &str, body: &str, config: &DemonConfig) -> Result<PathBuf> {
     let output_dir = config.paths.output_dir().join(&job.id);
     std::fs::create_dir_all(&output_dir)?;
 
@@ -33,22 +166,24 @@ fn save_to_file(job: &Job, result: &str, config: &DemonConfig) -> Result<()> {
     let filepath = output_dir.join(&filename);
 
     let content = format!(
-        "# Job: {}\n\nDate: {}\nPrompt: {}\n\n---\n\n{}",
-        job.name,
+        "# {}\n\nDate: {}\nPrompt: {}\n\n---\n\n{}",
+        title,
         Local::now().format("%Y-%m-%d %H:%M:%S"),
         job.prompt,
-        extract_result(result)
+        body
     );
 
     std::fs::write(&filepath, content)?;
     tracing::info!("Output saved to: {}", filepath.display());
-    Ok(())
+    Ok(filepath)
 }
 
 async fn send_to_telegram(
     job: &Job,
-    result: &str,
+    title: &str,
+    body: &str,
     chat_id: i64,
+    keyboard: Option<InlineKeyboardMarkup>,
     config: &DemonConfig,
 ) -> Result<()> {
     if config.gateway.bot_token.is_empty() {
@@ -61,12 +196,14 @@ async fn send_to_telegram(
 
     let bot = teloxide::Bot::new(&config.gateway.bot_token);
     let client = TelegramClient::new(bot, config.gateway.message_format);
-    let text = format!("**Job: {}**\n\n{}", job.name, extract_result(result));
+    let text = format!("**{}**\n\n{}", title, body);
 
-    client
-        .send_formatted_message(teloxide::types::ChatId(chat_id), &text)
-        .await
-        .context("Failed to send Telegram message")?;
+    let chat = teloxide::types::ChatId(chat_id);
+    match keyboard {
+        Some(keyboard) => client.send_formatted_message_with_keyboard(chat, &text, keyboard).await,
+        None => client.send_formatted_message(chat, &text).await,
+    }
+    .context("Failed to send Telegram message")?;
 
     tracing::info!("Output sent to Telegram chat: {}", chat_id);
     Ok(())
@@ -84,3 +221,33 @@ fn extract_result(result: &str) -> &str {
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failure_message_includes_status_and_stderr_tail() {
+        let stderr = format!("{}\nfatal: boom", "x".repeat(STDERR_EXCERPT_CHARS));
+        let error: anyhow::Error = JobError::Exit {
+            code: Some(1),
+            status: "exit status: 1".to_string(),
+            stderr,
+        }
+        .into();
+        let text = failure_message(3, &error);
+
+        assert!(text.starts_with("Attempts: 3\nStatus: exit status: 1"));
+        assert!(text.contains("fatal: boom\n```"));
+        assert!(text.contains("```\n..."));
+    }
+
+    #[test]
+    fn test_failure_message_timeout() {
+        let error: anyhow::Error = JobError::Timeout { secs: 60 }.into();
+        assert_eq!(
+            failure_message(1, &error),
+            "Attempts: 1\nStatus: timed out after 60s"
+        );
+    }
+}
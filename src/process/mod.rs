@@ -0,0 +1,181 @@
+//! Child process execution with timeouts.
+//!
+//! Children run in their own process group, so that on timeout, or when
+//! the caller stops waiting, the whole tree claude started (tools, MCP
+//! servers) is signalled rather than just claude itself.
+
+use std::process::{Output, Stdio};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+
+/// How long a timed-out process group gets to exit after SIGTERM before
+/// it is sent SIGKILL.
+const TERM_GRACE_SECS: u64 = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProcessError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("timed out after {secs}s")]
+    TimedOut { secs: u64 },
+}
+
+/// Run `cmd` to completion in its own process group, capturing stdout and
+/// stderr. With a `timeout`, the group is sent SIGTERM when it expires and
+/// SIGKILL `TERM_GRACE_SECS` later. Anything the child leaves running that
+/// keeps its output open is killed at the timeout, or `TERM_GRACE_SECS`
+/// after the child exits when there is none. If the returned future is
+/// dropped before the child exits, the group is killed.
+pub async fn output_with_timeout(
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+) -> Result<Output, ProcessError> {
+    cmd.stdin(Stdio::null());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    #[cfg(unix)]
+    cmd.process_group(0);
+
+    let mut child = cmd.spawn()?;
+    let mut group = GroupGuard(child.id());
+    let mut pipes = read_output(child.stdout.take(), child.stderr.take());
+    let deadline = timeout.map(|limit| Instant::now() + limit);
+
+    let status = match deadline {
+        None => child.wait().await?,
+        Some(deadline) => match tokio::time::timeout_at(deadline, child.wait()).await {
+            Ok(status) => status?,
+            Err(_) => {
+                tracing::warn!(
+                    pid = ?child.id(),
+                    timeout_secs = timeout.unwrap_or_default().as_secs(),
+                    "Process timed out, terminating its process group"
+                );
+                group.signal_term();
+                let grace = Duration::from_secs(TERM_GRACE_SECS);
+                if tokio::time::timeout(grace, child.wait()).await.is_err() {
+                    tracing::warn!(pid = ?child.id(), "Process group ignored SIGTERM, killing");
+                    group.kill();
+                    child.wait().await?;
+                }
+                group.0 = None;
+                return Err(ProcessError::TimedOut {
+                    secs: timeout.unwrap_or_default().as_secs(),
+                });
+            }
+        },
+    };
+
+    // A background process the child started may still hold its pipes
+    let pipes_deadline = deadline.unwrap_or_else(|| Instant::now() + Duration::from_secs(TERM_GRACE_SECS));
+    let (stdout, stderr) = match tokio::time::timeout_at(pipes_deadline, &mut pipes).await {
+        Ok(output) => output.unwrap_or_default(),
+        Err(_) => {
+            tracing::warn!(
+                pid = ?group.0,
+                "Process exited but its process group still holds its output open, killing"
+            );
+            group.kill();
+            group.0 = None;
+            if let Some(limit) = timeout {
+                return Err(ProcessError::TimedOut {
+                    secs: limit.as_secs(),
+                });
+            }
+            pipes.await.unwrap_or_default()
+        }
+    };
+    group.0 = None;
+
+    Ok(Output { status, stdout, stderr })
+}
+
+/// Read stdout and stderr to the end, until every process holding them
+/// has closed them.
+fn read_output<O, E>(stdout: Option<O>, stderr: Option<E>) -> JoinHandle<(Vec<u8>, Vec<u8>)>
+where
+    O: AsyncRead + Unpin + Send + 'static,
+    E: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move { tokio::join!(read_to_end(stdout), read_to_end(stderr)) })
+}
+
+async fn read_to_end<R: AsyncRead + Unpin>(pipe: Option<R>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(mut pipe) = pipe {
+        let _ = pipe.read_to_end(&mut buf).await;
+    }
+    buf
+}
+
+/// Process group of a running child, killed on drop unless cleared.
+struct GroupGuard(Option<u32>);
+
+impl GroupGuard {
+    fn signal_term(&self) {
+        #[cfg(unix)]
+        self.signal(nix::sys::signal::Signal::SIGTERM);
+    }
+
+    fn kill(&self) {
+        #[cfg(unix)]
+        self.signal(nix::sys::signal::Signal::SIGKILL);
+    }
+
+    #[cfg(unix)]
+    fn signal(&self, signal: nix::sys::signal::Signal) {
+        if let Some(pgid) = self.0 {
+            let _ = nix::sys::signal::killpg(nix::unistd::Pid::from_raw(pgid as i32), signal);
+        }
+    }
+}
+
+impl Drop for GroupGuard {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_captures_output() {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("echo out; echo err >&2; exit 3");
+        let output = output_with_timeout(&mut cmd, Some(Duration::from_secs(5)))
+            .await
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    #[tokio::test]
+    async fn test_timeout_kills_process_group() {
+        // The background sleep holds stdout open; it must die with the group
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("sleep 30 & sleep 30");
+        let started = std::time::Instant::now();
+        let result = output_with_timeout(&mut cmd, Some(Duration::from_millis(200))).await;
+
+        assert!(matches!(result, Err(ProcessError::TimedOut { .. })));
+        assert!(started.elapsed() < Duration::from_secs(TERM_GRACE_SECS));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_covers_pipes_held_after_exit() {
+        // sh exits at once, but the background sleep keeps stdout open
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("sleep 30 & echo started");
+        let started = std::time::Instant::now();
+        let result = output_with_timeout(&mut cmd, Some(Duration::from_millis(200))).await;
+
+        assert!(matches!(result, Err(ProcessError::TimedOut { .. })));
+        assert!(started.elapsed() < Duration::from_secs(TERM_GRACE_SECS));
+    }
+}
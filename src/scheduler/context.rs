@@ -0,0 +1,121 @@
+//! Context commands: shell output appended to a job's prompt.
+
+use std::path::Path;
+use tokio::time::Duration;
+
+use super::JobError;
+use crate::config::{ContextCommand, ContextFailure, Job};
+use crate::process::{self, ProcessError};
+
+/// Run the job's context commands and return its prompt with their output
+/// appended. Fails on the first failing command whose `on_failure` is
+/// `fatal`; output of ignored failures is left out.
+pub async fn build_prompt(job: &Job) -> Result<String, JobError> {
+    let mut prompt = job.prompt.clone();
+    for cmd in &job.context_commands {
+        let label = cmd.label.as_deref().unwrap_or(&cmd.command);
+        match run(cmd, &job.working_dir).await {
+            Ok(output) => {
+                prompt.push_str(&format!("\n\n## {label}\n\n```\n{}\n```", output.trim_end()));
+            }
+            Err(reason) if cmd.on_failure == ContextFailure::Fatal => {
+                return Err(JobError::Context {
+                    command: cmd.command.clone(),
+                    reason,
+                });
+            }
+            Err(reason) => {
+                tracing::warn!(
+                    component = "scheduler",
+                    job_id = %job.id,
+                    command = %cmd.command,
+                    reason = %reason,
+                    "Context command failed, ignoring"
+                );
+            }
+        }
+    }
+    Ok(prompt)
+}
+
+/// Run one command, returning its stdout capped at `max_bytes`, or why it
+/// failed.
+async fn run(cmd: &ContextCommand, working_dir: &str) -> Result<String, String> {
+    let mut command = tokio::process::Command::new("sh");
+    command.arg("-c").arg(&cmd.command);
+    if !working_dir.is_empty() {
+        command.current_dir(Path::new(working_dir));
+    }
+
+    let timeout = Some(Duration::from_secs(cmd.timeout_secs));
+    let output = match process::output_with_timeout(&mut command, timeout).await {
+        Ok(output) => output,
+        Err(ProcessError::TimedOut { secs }) => return Err(format!("timed out after {secs}s")),
+        Err(ProcessError::Io(e)) => return Err(e.to_string()),
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{}: {}", output.status, stderr.trim()));
+    }
+
+    Ok(truncate(&String::from_utf8_lossy(&output.stdout), cmd.max_bytes))
+}
+
+/// Cut `s` to at most `max_bytes` on a char boundary, noting the cut.
+fn truncate(s: &str, max_bytes: usize) -> String {
+    if s.len() <= max_bytes {
+        return s.to_string();
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n[output truncated at {} bytes]", &s[..end], max_bytes)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn job(commands: &str) -> Job {
+        toml::from_str(&format!(
+            "id = \"j\"\nname = \"J\"\nschedule = \"0 * * * * *\"\nprompt = \"Report\"\n{commands}"
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_build_prompt_appends_output() {
+        let job = job(
+            r#"
+            [[context_commands]]
+            command = "printf 'abcdef'"
+            label = "Letters"
+            max_bytes = 3
+
+            [[context_commands]]
+            command = "exit 1"
+            on_failure = "ignore"
+            "#,
+        );
+        let prompt = build_prompt(&job).await.unwrap();
+        assert_eq!(
+            prompt,
+            "Report\n\n## Letters\n\n```\nabc\n[output truncated at 3 bytes]\n```"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_prompt_fatal_failure() {
+        let job = job(
+            r#"
+            [[context_commands]]
+            command = "echo nope >&2; exit 2"
+            "#,
+        );
+        match build_prompt(&job).await {
+            Err(JobError::Context { reason, .. }) => assert!(reason.contains("nope")),
+            other => panic!("expected context error, got {other:?}"),
+        }
+    }
+}
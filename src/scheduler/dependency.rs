@@ -0,0 +1,121 @@
+//! Triggering of `schedule_type = "after"` jobs from upstream successes.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use crate::config::{DemonConfig, Job};
+use crate::history::{self, RunStatus};
+
+/// The successful upstream run that triggered a downstream job.
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    pub job_id: String,
+    /// The upstream's result text (see `history::result_text`)
+    pub result: String,
+}
+
+/// Enabled "after" jobs that depend on `upstream_id` and whose every
+/// dependency has succeeded since the job last ran or, per `triggered`,
+/// was last started.
+pub fn ready_dependents<'a>(
+    upstream_id: &str,
+    jobs: &'a [Job],
+    config: &DemonConfig,
+    triggered: &HashMap<String, DateTime<Utc>>,
+) -> Result<Vec<&'a Job>> {
+    let mut ready = Vec::new();
+    for job in jobs.iter().filter(|j| {
+        j.enabled && j.schedule_type == "after" && j.depends_on.iter().any(|d| d == upstream_id)
+    }) {
+        let last_ran = history::load(config, &job.id)?
+            .into_iter()
+            .rev()
+            .find(|r| r.status != RunStatus::Skipped)
+            .map(|r| r.started_at)
+            .max(triggered.get(&job.id).copied());
+
+        let mut satisfied = true;
+        for dep in &job.depends_on {
+            let succeeded = history::last_with_status(config, dep, RunStatus::Success)?
+                .map(|r| r.finished_at);
+            let fresh = succeeded.is_some_and(|s| last_ran.is_none_or(|l| s > l));
+            if !fresh {
+                satisfied = false;
+                break;
+            }
+        }
+        if satisfied {
+            ready.push(job);
+        }
+    }
+    Ok(ready)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathsConfig;
+    use crate::history::{RunRecord, RunTrigger};
+    use chrono::{Duration, Utc};
+
+    fn temp_config() -> DemonConfig {
+        let dir = std::env::temp_dir().join(format!("demon-deps-{}", uuid::Uuid::new_v4()));
+        DemonConfig {
+            paths: PathsConfig {
+                base_dir: Some(dir.to_string_lossy().to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn record(config: &DemonConfig, job_id: &str, minutes_ago: i64, ok: bool) {
+        let started_at = Utc::now() - Duration::minutes(minutes_ago);
+        let outcome = if ok {
+            Ok(String::new())
+        } else {
+            Err(anyhow::anyhow!("boom"))
+        };
+        let mut r = RunRecord::from_outcome(job_id, RunTrigger::Schedule, started_at, &outcome, None);
+        r.finished_at = started_at;
+        history::append(config, &r).unwrap();
+    }
+
+    #[test]
+    fn test_ready_dependents_waits_for_all_dependencies() {
+        let config = temp_config();
+        let jobs: Vec<Job> = ["fetch", "news"]
+            .iter()
+            .map(|id| {
+                toml::from_str(&format!("id = \"{id}\"\nname = \"{id}\"\nschedule = \"0 0 7 * * *\"\nprompt = \"p\""))
+                    .unwrap()
+            })
+            .chain(std::iter::once(
+                toml::from_str(
+                    "id = \"digest\"\nname = \"Digest\"\ntrigger = \"after\"\ndepends_on = [\"fetch\", \"news\"]\nprompt = \"{upstream_result}\"",
+                )
+                .unwrap(),
+            ))
+            .collect();
+        let ids = |ready: Vec<&Job>| ready.iter().map(|j| j.id.clone()).collect::<Vec<_>>();
+        let none = HashMap::new();
+
+        // Only one dependency has succeeded
+        record(&config, "fetch", 10, true);
+        record(&config, "news", 9, false);
+        assert!(ids(ready_dependents("fetch", &jobs, &config, &none).unwrap()).is_empty());
+
+        // Both succeeded and digest never ran
+        record(&config, "news", 8, true);
+        assert_eq!(ids(ready_dependents("news", &jobs, &config, &none).unwrap()), vec!["digest"]);
+        // ...unless it was already started and hasn't recorded its run yet
+        let started = HashMap::from([("digest".to_string(), Utc::now())]);
+        assert!(ids(ready_dependents("news", &jobs, &config, &started).unwrap()).is_empty());
+
+        // Digest ran after both; a new fetch alone isn't enough
+        record(&config, "digest", 7, true);
+        record(&config, "fetch", 5, true);
+        assert!(ids(ready_dependents("fetch", &jobs, &config, &none).unwrap()).is_empty());
+    }
+}
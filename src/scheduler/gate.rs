@@ -0,0 +1,220 @@
+//! `run_if` gates, evaluated before a scheduled run starts claude.
+//!
+//! Path checks compare a fingerprint of the file or directory (latest
+//! mtime, or a content hash) against the one stored in
+//! ~/.demon/gates/<job_id> when the job last succeeded.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::time::Duration;
+
+use crate::config::{ChangeCheck, DemonConfig, Job, RunIf};
+use crate::process::{self, ProcessError};
+use crate::store;
+
+/// Files larger than this are hashed by size and mtime rather than read.
+const MAX_HASH_FILE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// The current fingerprint of a job's `run_if.path`, to be saved once the
+/// run succeeds so failed or cancelled runs don't use up the change.
+#[derive(Debug)]
+pub struct Fingerprint {
+    state_file: PathBuf,
+    value: String,
+}
+
+impl Fingerprint {
+    pub fn save(&self) -> Result<()> {
+        store::replace(&self.state_file, &self.value).context("Failed to write gate state")
+    }
+}
+
+/// Check a job's `run_if` gate. Returns why the run should be skipped, or
+/// the path's fingerprint to save after a successful run, if it has one.
+pub async fn check(job: &Job, config: &DemonConfig) -> Result<Option<Fingerprint>, String> {
+    let Some(run_if) = job.run_if.as_ref() else {
+        return Ok(None);
+    };
+
+    if let Some(command) = &run_if.command {
+        check_command(command, run_if, &job.working_dir).await?;
+    }
+
+    let Some(path) = &run_if.path else {
+        return Ok(None);
+    };
+    // Relative paths are relative to the job's working directory
+    let full_path = Path::new(&job.working_dir).join(path);
+    let check = run_if.check;
+    let value = tokio::task::spawn_blocking(move || fingerprint(&full_path, check))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|f| f)
+        .map_err(|e| format!("run_if path check failed: {e:#}"))?;
+    let state_file = config.paths.gates_dir().join(&job.id);
+    if fs::read_to_string(&state_file).ok().as_deref() == Some(value.as_str()) {
+        return Err(format!("run_if: {path} unchanged since last run"));
+    }
+    Ok(Some(Fingerprint { state_file, value }))
+}
+
+async fn check_command(command: &str, run_if: &RunIf, working_dir: &str) -> Result<(), String> {
+    let mut cmd = tokio::process::Command::new("sh");
+    cmd.arg("-c").arg(command);
+    if !working_dir.is_empty() {
+        cmd.current_dir(working_dir);
+    }
+    let timeout = Some(Duration::from_secs(run_if.timeout_secs));
+    match process::output_with_timeout(&mut cmd, timeout).await {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(format!("run_if command exited with {}", output.status)),
+        Err(ProcessError::TimedOut { secs }) => Err(format!("run_if command timed out after {secs}s")),
+        Err(ProcessError::Io(e)) => Err(format!("run_if command failed to start: {e}")),
+    }
+}
+
+/// Fingerprint of a file or directory tree. A missing path has a fixed
+/// fingerprint, so its creation counts as a change.
+fn fingerprint(path: &Path, check: ChangeCheck) -> Result<String> {
+    if !path.exists() {
+        return Ok("missing".to_string());
+    }
+    let mut files = Vec::new();
+    collect_files(path, &mut files)?;
+    files.sort();
+
+    match check {
+        ChangeCheck::Mtime => {
+            let mut latest = 0u128;
+            for file in &files {
+                let modified = fs::metadata(file)?.modified()?;
+                let nanos = modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+                latest = latest.max(nanos);
+            }
+            // The count catches deletions, which don't touch any mtime
+            Ok(format!("mtime:{}:{}", latest, files.len()))
+        }
+        ChangeCheck::Hash => {
+            let mut hash = Fnv64::new();
+            for file in &files {
+                hash.write(file.strip_prefix(path).unwrap_or(file).to_string_lossy().as_bytes());
+                hash.write(&[0]);
+                let metadata = fs::metadata(file)?;
+                if metadata.len() > MAX_HASH_FILE_BYTES {
+                    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+                    hash.write(format!("{}:{}", metadata.len(), modified.as_nanos()).as_bytes());
+                } else {
+                    hash.write(&fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?);
+                }
+                hash.write(&[0]);
+            }
+            Ok(format!("hash:{:016x}", hash.0))
+        }
+    }
+}
+
+/// The files under `path`. Symlinks inside the tree are skipped, so a
+/// link loop can't recurse forever.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path).with_context(|| format!("Failed to read {}", path.display()))? {
+            let entry = entry?.path();
+            if !fs::symlink_metadata(&entry)?.file_type().is_symlink() {
+                collect_files(&entry, files)?;
+            }
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+/// FNV-1a, chosen over `DefaultHasher` because stored fingerprints must
+/// stay comparable across builds.
+struct Fnv64(u64);
+
+impl Fnv64 {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathsConfig;
+
+    fn temp_config() -> DemonConfig {
+        let dir = std::env::temp_dir().join(format!("demon-gate-{}", uuid::Uuid::new_v4()));
+        DemonConfig {
+            paths: PathsConfig {
+                base_dir: Some(dir.to_string_lossy().to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn job(run_if: &str) -> Job {
+        toml::from_str(&format!(
+            "id = \"j\"\nname = \"J\"\nschedule = \"0 * * * * *\"\nprompt = \"hi\"\nrun_if = {run_if}"
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_path_hash_gate_runs_only_on_change() {
+        let config = temp_config();
+        let watched = config.paths.base_dir().join("inbox");
+        fs::create_dir_all(&watched).unwrap();
+        fs::write(watched.join("a.txt"), "one").unwrap();
+        let job = job(&format!(
+            "{{ path = \"{}\", check = \"hash\" }}",
+            watched.display()
+        ));
+
+        // Not saved until the run succeeds
+        check(&job, &config).await.unwrap().unwrap();
+        let fingerprint = check(&job, &config).await.unwrap().unwrap();
+        fingerprint.save().unwrap();
+        assert!(check(&job, &config).await.unwrap_err().contains("unchanged"));
+
+        fs::write(watched.join("a.txt"), "two").unwrap();
+        assert!(check(&job, &config).await.unwrap().is_some());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_path_relative_to_working_dir_skips_symlinks() {
+        let config = temp_config();
+        let dir = config.paths.base_dir().join("work");
+        fs::create_dir_all(dir.join("notes")).unwrap();
+        fs::write(dir.join("notes/a.txt"), "one").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("notes/loop")).unwrap();
+        let mut job = job("{ path = \"notes\", check = \"hash\" }");
+        job.working_dir = dir.to_string_lossy().to_string();
+
+        check(&job, &config).await.unwrap().unwrap().save().unwrap();
+        assert!(check(&job, &config).await.unwrap_err().contains("unchanged"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_gate() {
+        let config = temp_config();
+        assert!(check(&job("{ command = \"true\" }"), &config).await.unwrap().is_none());
+        assert!(check(&job("{ command = \"exit 1\" }"), &config)
+            .await
+            .unwrap_err()
+            .contains("exited"));
+    }
+}
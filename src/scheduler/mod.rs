@@ -1,25 +1,116 @@
-use anyhow::{Context, Result};
-use chrono::{DateTime, FixedOffset, Local, Utc};
+mod context;
+mod dependency;
+mod gate;
+mod retry;
+mod running;
+mod state;
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use cron::Schedule;
-use std::collections::HashSet;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::watch;
 use tokio::time::{sleep, Duration};
 
-use crate::config::{DemonConfig, Job};
+use crate::config::{CatchUpPolicy, DemonConfig, Job};
+use crate::history::{self, RunRecord, RunTrigger};
 use crate::output;
+use crate::template::TemplateContext;
+use crate::process::{self, ProcessError};
+use crate::usage::{self, Scope, Usage, UsageEntry};
+
+use dependency::Upstream;
+pub use running::{load as load_running, RunState, RunningJobs};
+use state::SchedulerState;
+
+/// Waking up later than planned by more than this means the daemon was
+/// suspended (e.g. laptop sleep) and missed runs must be evaluated.
+const CATCH_UP_GAP_SECS: i64 = 90;
+
+/// Runs that are due but at most this old when the scheduler wakes are
+/// fired normally; older ones are left to the catch-up policy.
+const FIRE_GRACE_SECS: i64 = 60;
 
-pub async fn run(config: DemonConfig) -> Result<()> {
-    tracing::info!(component = "scheduler", "Scheduler started, checking jobs every 30 seconds");
+/// Longest the scheduler sleeps without re-reading jobs.toml, so hand
+/// edits are picked up and system sleep is noticed promptly.
+const MAX_SLEEP_SECS: i64 = 60;
 
-    // Track which one-shot jobs have already been triggered
-    let mut triggered_once_jobs: HashSet<String> = HashSet::new();
+/// How long fired (job_id, instant) keys are remembered for de-duplication.
+const FIRED_RETENTION_SECS: i64 = 3600;
+
+/// Upper bound on cron occurrences scanned when looking for missed runs.
+const MAX_MISSED_SCAN: usize = 10_000;
+
+/// Errors from spawning or running the claude CLI for a job.
+#[derive(Debug, thiserror::Error)]
+pub enum JobError {
+    #[error("Failed to execute claude CLI: {0}")]
+    Spawn(#[from] std::io::Error),
+    #[error("claude CLI exited with {status}: {stderr}")]
+    Exit {
+        code: Option<i32>,
+        status: String,
+        stderr: String,
+    },
+    #[error("claude timed out after {secs}s")]
+    Timeout { secs: u64 },
+    #[error("claude reported an error: {message}")]
+    Result {
+        message: String,
+        /// Raw JSON result, for cost and turn accounting
+        output: String,
+    },
+    #[error("Context command '{command}' failed: {reason}")]
+    Context { command: String, reason: String },
+    #[error("Run cancelled by a newer run of the same job")]
+    Cancelled,
+    #[error("Run cancelled because the daemon shut down")]
+    Shutdown,
+}
+
+/// Run the scheduler loop.
+///
+/// Each job's next occurrence is kept in a priority queue; the scheduler
+/// fires everything that is due, then sleeps until the earliest upcoming
+/// occurrence. It wakes early when the live config changes (SIGHUP) and
+/// never sleeps longer than `MAX_SLEEP_SECS`. It returns when the
+/// registry's shutdown begins; runs already started are tracked by it.
+pub async fn run(mut config_rx: watch::Receiver<DemonConfig>, running: Arc<RunningJobs>) -> Result<()> {
+    tracing::info!(component = "scheduler", "Scheduler started");
+    let shutdown = running.shutdown().clone();
+
+    let mut state = SchedulerState::load(&config_rx.borrow()).unwrap_or_else(|e| {
+        tracing::error!(component = "scheduler", error = %e, "Failed to load scheduler state, starting fresh");
+        SchedulerState::default()
+    });
+
+    // (job_id, scheduled instant) pairs already fired, to never fire twice
+    let mut fired: HashSet<(String, DateTime<Utc>)> = HashSet::new();
+    let mut planned_wake: Option<DateTime<Utc>> = None;
 
     loop {
+        if shutdown.is_draining() {
+            tracing::info!(component = "scheduler", "Scheduler stopped");
+            return Ok(());
+        }
+
+        let config = config_rx.borrow_and_update().clone();
+        running.set_limit(config.scheduler.max_concurrent_jobs);
         let jobs = match config.load_jobs() {
             Ok(jobs) => jobs,
             Err(e) => {
                 tracing::error!(component = "scheduler", error = %e, "Failed to load jobs");
-                sleep(Duration::from_secs(60)).await;
+                tokio::select! {
+                    _ = sleep(Duration::from_secs(60)) => {}
+                    _ = config_rx.changed() => {}
+                    _ = shutdown.draining() => {}
+                }
                 continue;
             }
         };
@@ -41,245 +132,804 @@ pub async fn run(config: DemonConfig) -> Result<()> {
             "Scheduler tick"
         );
 
-        for job in &jobs {
-            if !job.enabled {
-                tracing::debug!(
+        // Evaluate missed runs on startup and after oversleeping
+        let overslept = planned_wake.map(|t| (now - t).num_seconds());
+        if overslept.is_none_or(|s| s > CATCH_UP_GAP_SECS) {
+            if let Some(s) = overslept {
+                tracing::warn!(
                     component = "scheduler",
-                    job_id = %job.id,
-                    job_name = %job.name,
-                    status = "skip",
-                    reason = "disabled",
-                    "Job skipped"
+                    late_by = %format_duration(s),
+                    "Scheduler woke up late, checking for missed runs"
                 );
-                continue;
             }
+            catch_up(&jobs, &config, &mut state, &running, now);
+        }
 
-            // Skip one-shot jobs that have already been triggered this session
-            if job.schedule_type == "once" && triggered_once_jobs.contains(&job.id) {
+        // Queue each enabled job's first occurrence after its last firing
+        // (or the grace window, whichever is later)
+        let grace_start = now - chrono::Duration::seconds(FIRE_GRACE_SECS);
+        let mut queue: BinaryHeap<Reverse<(DateTime<Utc>, usize)>> = BinaryHeap::new();
+        let mut timezones: Vec<Option<Tz>> = vec![None; jobs.len()];
+        for (idx, job) in jobs.iter().enumerate() {
+            if !job.enabled {
                 tracing::debug!(
                     component = "scheduler",
                     job_id = %job.id,
                     job_name = %job.name,
                     status = "skip",
-                    reason = "once job already triggered",
+                    reason = "disabled",
                     "Job skipped"
                 );
                 continue;
             }
-
-            let should_run = match job.schedule_type.as_str() {
-                "recurring" => should_run_recurring(job, now),
-                "once" => should_run_once(job, now),
-                _ => {
+            timezones[idx] = match job.schedule_timezone(&config.defaults) {
+                Ok(tz) => tz,
+                Err(e) => {
                     tracing::error!(
                         component = "scheduler",
                         job_id = %job.id,
                         job_name = %job.name,
-                        schedule_type = %job.schedule_type,
-                        "Unknown schedule type"
+                        error = %e,
+                        "Invalid job timezone"
                     );
-                    false
+                    continue;
                 }
             };
+            let since = state.last_fired(&job.id).map_or(grace_start, |t| t.max(grace_start));
+            if let Some(at) = next_fire(job, timezones[idx], since) {
+                queue.push(Reverse((at, idx)));
+            }
+        }
 
-            if should_run {
-                // Mark one-shot jobs as triggered to prevent re-execution
-                if job.schedule_type == "once" {
-                    triggered_once_jobs.insert(job.id.clone());
-                }
+        // Fire everything that is due, re-queueing each job's next occurrence
+        let mut state_changed = false;
+        while let Some(&Reverse((at, idx))) = queue.peek() {
+            if at > now {
+                break;
+            }
+            queue.pop();
+            let job = &jobs[idx];
 
-                let job = job.clone();
-                let config = config.clone();
-                tokio::spawn(async move {
-                    tracing::info!(
-                        component = "scheduler",
-                        job_id = %job.id,
-                        job_name = %job.name,
-                        schedule_type = %job.schedule_type,
-                        "Executing job"
-                    );
+            if fired.insert((job.id.clone(), at)) {
+                tracing::info!(
+                    component = "scheduler",
+                    job_id = %job.id,
+                    job_name = %job.name,
+                    scheduled_at = %at.format("%Y-%m-%d %H:%M:%S UTC"),
+                    status = "fire",
+                    "Job firing now"
+                );
+                state.mark_fired(&job.id, at);
+                state_changed = true;
+                spawn_run(
+                    job.clone(),
+                    config.clone(),
+                    running.clone(),
+                    RunTrigger::Schedule,
+                    Some(at),
+                    None,
+                );
+            }
 
-                    match execute_job(&job, &config).await {
-                        Ok(result) => {
-                            tracing::info!(
-                                component = "scheduler",
-                                job_id = %job.id,
-                                job_name = %job.name,
-                                result_len = result.len(),
-                                "Job completed successfully"
-                            );
-                            if let Err(e) = output::route(&job, &result, &config).await {
-                                tracing::error!(
-                                    component = "scheduler",
-                                    job_id = %job.id,
-                                    error = %e,
-                                    "Failed to route output"
-                                );
-                            }
-                        }
-                        Err(e) => {
-                            tracing::error!(
-                                component = "scheduler",
-                                job_id = %job.id,
-                                job_name = %job.name,
-                                error = %e,
-                                "Job execution failed"
-                            );
-                        }
-                    }
+            if let Some(next) = next_fire(job, timezones[idx], at) {
+                queue.push(Reverse((next, idx)));
+            }
+        }
+
+        fired.retain(|(_, at)| (now - *at).num_seconds() < FIRED_RETENTION_SECS);
+        state.retain_jobs(jobs.iter().map(|j| j.id.as_str()));
+        if state_changed {
+            if let Err(e) = state.save(&config) {
+                tracing::error!(component = "scheduler", error = %e, "Failed to save scheduler state");
+            }
+        }
+
+        // Sleep until the earliest upcoming occurrence
+        let max_wake = now + chrono::Duration::seconds(MAX_SLEEP_SECS);
+        let wake = match queue.peek() {
+            Some(&Reverse((at, idx))) => {
+                let job = &jobs[idx];
+                tracing::debug!(
+                    component = "scheduler",
+                    job_id = %job.id,
+                    job_name = %job.name,
+                    next_utc = %at.format("%Y-%m-%d %H:%M:%S UTC"),
+                    next_local = %at.with_timezone(&Local).format("%H:%M:%S %Z"),
+                    wait_duration = %format_duration((at - now).num_seconds()),
+                    status = "wait",
+                    "Next job"
+                );
+                at.min(max_wake)
+            }
+            None => max_wake,
+        };
+        planned_wake = Some(wake);
 
-                    // Disable one-shot jobs after execution
-                    if job.schedule_type == "once" {
-                        if let Ok(mut jobs) = config.load_jobs() {
-                            if let Some(j) = jobs.iter_mut().find(|j| j.id == job.id) {
-                                j.enabled = false;
-                                if let Err(e) = config.save_jobs(&jobs) {
-                                    tracing::error!(
-                                        component = "scheduler",
-                                        job_id = %job.id,
-                                        error = %e,
-                                        "Failed to disable one-shot job"
-                                    );
-                                } else {
-                                    tracing::info!(
-                                        component = "scheduler",
-                                        job_id = %job.id,
-                                        "One-shot job disabled after execution"
-                                    );
-                                }
-                            }
-                        }
+        let wait = (wake - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+        tokio::select! {
+            _ = sleep(wait) => {}
+            _ = shutdown.draining() => {}
+            result = config_rx.changed() => {
+                if result.is_ok() {
+                    tracing::debug!(component = "scheduler", "Config changed, rescheduling");
+                    // Woken early on purpose; don't mistake it for oversleeping
+                    planned_wake = Some(Utc::now());
+                } else {
+                    // Config sender gone (e.g. no SIGHUP support); keep polling
+                    tokio::select! {
+                        _ = sleep(wait) => {}
+                        _ = shutdown.draining() => {}
                     }
-                });
+                }
+            }
+        }
+    }
+}
+
+/// Apply each enabled job's catch-up policy to runs scheduled between its
+/// last recorded firing and the start of the normal firing grace window.
+fn catch_up(
+    jobs: &[Job],
+    config: &DemonConfig,
+    state: &mut SchedulerState,
+    running: &Arc<RunningJobs>,
+    now: DateTime<Utc>,
+) {
+    let until = now - chrono::Duration::seconds(FIRE_GRACE_SECS);
+
+    for job in jobs.iter().filter(|j| j.enabled) {
+        let Ok(tz) = job.schedule_timezone(&config.defaults) else {
+            continue;
+        };
+        let last_fired = state.last_fired(&job.id);
+        let (missed_count, missed) = missed_runs(job, tz, last_fired, until);
+
+        let Some(&latest) = missed.last() else {
+            // Nothing missed; start tracking jobs we haven't seen before
+            if last_fired.is_none() && job.schedule_type == "recurring" {
+                state.mark_fired(&job.id, until);
+            }
+            continue;
+        };
+
+        let runs: Vec<DateTime<Utc>> = match job.catch_up {
+            CatchUpPolicy::Skip => Vec::new(),
+            CatchUpPolicy::RunOnce => vec![latest],
+            CatchUpPolicy::RunAll => missed,
+        };
+
+        tracing::warn!(
+            component = "scheduler",
+            job_id = %job.id,
+            job_name = %job.name,
+            missed = missed_count,
+            catch_up = ?job.catch_up,
+            runs = runs.len(),
+            "Job missed scheduled runs"
+        );
+
+        state.mark_fired(&job.id, latest);
+        if runs.is_empty() {
+            // A one-shot job whose only run was dropped has nothing left to do
+            if job.schedule_type == "once" {
+                let (id, config) = (job.id.clone(), config.clone());
+                tokio::spawn(async move { disable_one_shot(&id, &config, "after its missed run was skipped").await });
             }
+            continue;
         }
 
-        // Check every 30 seconds
-        sleep(Duration::from_secs(30)).await;
+        // Replay sequentially so catch-up doesn't pile up parallel runs
+        let job = job.clone();
+        let config = config.clone();
+        let running = running.clone();
+        tokio::spawn(async move {
+            for at in runs {
+                run_scheduled_job(
+                    job.clone(),
+                    config.clone(),
+                    running.clone(),
+                    RunTrigger::CatchUp,
+                    Some(at),
+                    None,
+                )
+                .await;
+            }
+        });
+    }
+
+    if let Err(e) = state.save(config) {
+        tracing::error!(component = "scheduler", error = %e, "Failed to save scheduler state");
     }
 }
 
-fn should_run_recurring(job: &Job, now: chrono::DateTime<Utc>) -> bool {
-    let schedule = match Schedule::from_str(&job.schedule) {
-        Ok(s) => s,
+/// Find scheduled instants in `(last_fired, until)` that never fired.
+/// Returns the total number missed and the most recent `catch_up_max` of them.
+fn missed_runs(
+    job: &Job,
+    tz: Option<Tz>,
+    last_fired: Option<DateTime<Utc>>,
+    until: DateTime<Utc>,
+) -> (usize, Vec<DateTime<Utc>>) {
+    let keep = job.catch_up_max.max(1) as usize;
+
+    match job.schedule_type.as_str() {
+        "recurring" => {
+            let Some(last) = last_fired else {
+                return (0, Vec::new());
+            };
+            let Ok(schedule) = Schedule::from_str(&job.schedule) else {
+                return (0, Vec::new());
+            };
+
+            let mut count = 0;
+            let mut missed = std::collections::VecDeque::with_capacity(keep);
+            for at in occurrences_after(&schedule, tz, last)
+                .take_while(|at| *at < until)
+                .take(MAX_MISSED_SCAN)
+            {
+                count += 1;
+                if missed.len() == keep {
+                    missed.pop_front();
+                }
+                missed.push_back(at);
+            }
+            (count, missed.into())
+        }
+        "once" => {
+            let Some(target) = job.once_at.as_deref().and_then(|s| parse_datetime(s, tz)) else {
+                return (0, Vec::new());
+            };
+            let already_fired = last_fired.is_some_and(|last| last >= target);
+            if target < until && !already_fired {
+                (1, vec![target])
+            } else {
+                (0, Vec::new())
+            }
+        }
+        _ => (0, Vec::new()),
+    }
+}
+
+/// Run a job in the background. The future is boxed so that runs can
+/// spawn the runs of their dependent jobs.
+fn spawn_run(
+    job: Job,
+    config: DemonConfig,
+    running: Arc<RunningJobs>,
+    trigger: RunTrigger,
+    scheduled_at: Option<DateTime<Utc>>,
+    upstream: Option<Upstream>,
+) {
+    let run: Pin<Box<dyn Future<Output = ()> + Send>> = Box::pin(run_scheduled_job(
+        job,
+        config,
+        running,
+        trigger,
+        scheduled_at,
+        upstream,
+    ));
+    tokio::spawn(run);
+}
+
+/// Execute a fired job under its overlap policy, route its output, record
+/// it in the run history, trigger jobs that depend on it and disable it
+/// afterwards if it is a one-shot job.
+async fn run_scheduled_job(
+    job: Job,
+    config: DemonConfig,
+    running: Arc<RunningJobs>,
+    trigger: RunTrigger,
+    scheduled_at: Option<DateTime<Utc>>,
+    upstream: Option<Upstream>,
+) {
+    let shutdown = running.shutdown().clone();
+    let _work = shutdown.track();
+    let run_id = uuid::Uuid::new_v4().to_string();
+    let Some(mut guard) = running.acquire(&job, &run_id).await else {
+        tracing::warn!(
+            component = "scheduler",
+            job_id = %job.id,
+            job_name = %job.name,
+            status = "skip",
+            reason = "overlap",
+            "Previous run still in progress, skipping"
+        );
+        record_skip(&config, &job.id, run_id, trigger, scheduled_at, "Previous run still in progress");
+        return;
+    };
+
+    // Runs that waited for a slot (or were triggered by a finishing
+    // upstream run) don't start once shutdown has begun
+    if shutdown.is_draining() {
+        tracing::info!(
+            component = "scheduler",
+            job_id = %job.id,
+            job_name = %job.name,
+            status = "skip",
+            reason = "shutdown",
+            "Daemon shutting down, skipping"
+        );
+        record_skip(&config, &job.id, run_id, trigger, scheduled_at, "Daemon shutting down");
+        return;
+    }
+
+    match usage::check(&config, Scope::Job(&job), Utc::now()) {
+        Ok(Some(reason)) => {
+            tracing::warn!(
+                component = "scheduler",
+                job_id = %job.id,
+                job_name = %job.name,
+                status = "skip",
+                reason = %reason,
+                "Budget reached, skipping"
+            );
+            record_skip(&config, &job.id, run_id, trigger, scheduled_at, &reason);
+            return;
+        }
+        Ok(None) => {}
         Err(e) => {
             tracing::error!(
                 component = "scheduler",
                 job_id = %job.id,
-                job_name = %job.name,
-                cron = %job.schedule,
                 error = %e,
-                "Invalid cron expression"
+                "Failed to check budget, running anyway"
             );
-            return false;
         }
+    }
+
+    let mut ctx = TemplateContext::for_job(&job, &config);
+    if let Some(upstream) = &upstream {
+        ctx.set("upstream_result", &upstream.result)
+            .set("upstream_job", &upstream.job_id);
+    }
+    let rendered = ctx.render_job(&job, &config);
+    let run_job = rendered.as_ref().unwrap_or(&job);
+
+    let mut fingerprint = None;
+    if let Ok(run_job) = &rendered {
+        match gate::check(run_job, &config).await {
+            Ok(f) => fingerprint = f,
+            Err(reason) => {
+                tracing::info!(
+                    component = "scheduler",
+                    job_id = %job.id,
+                    job_name = %job.name,
+                    status = "skip",
+                    reason = %reason,
+                    "Run condition not met, skipping"
+                );
+                record_skip(&config, &job.id, run_id, trigger, scheduled_at, &reason);
+                return;
+            }
+        }
+    }
+
+    tracing::info!(
+        component = "scheduler",
+        job_id = %job.id,
+        job_name = %job.name,
+        schedule_type = %job.schedule_type,
+        "Executing job"
+    );
+
+    let run_started = Utc::now();
+    // Context commands run once per run; retries reuse the prompt
+    let prompt = match &rendered {
+        Ok(run_job) => tokio::select! {
+            prompt = context::build_prompt(run_job) => prompt.map_err(anyhow::Error::from),
+            _ = guard.cancelled() => Err(JobError::Cancelled.into()),
+            _ = shutdown.aborted() => Err(JobError::Shutdown.into()),
+        },
+        Err(e) => Err(anyhow::anyhow!("{e:#}")),
     };
+    let (prompt, mut setup_error) = match prompt {
+        Ok(prompt) => (prompt, None),
+        Err(e) => (String::new(), Some(e)),
+    };
+    let mut attempt = 1;
+    let mut succeeded_with = None;
+    loop {
+        let started_at = if attempt == 1 { run_started } else { Utc::now() };
+        let outcome = match setup_error.take() {
+            // Rendering and context failures are never retried
+            Some(e) => Err(e),
+            None => tokio::select! {
+                outcome = execute_job(run_job, &prompt, &config) => outcome,
+                _ = guard.cancelled() => Err(JobError::Cancelled.into()),
+                _ = shutdown.aborted() => Err(JobError::Shutdown.into()),
+            },
+        };
+        let mut output_path = None;
 
-    // Check if there's an upcoming event within the next 30 seconds
-    if let Some(next) = schedule.upcoming(Utc).next() {
-        let diff = next - now;
-        let next_local = next.with_timezone(&Local);
-        let should = diff.num_seconds() <= 30 && diff.num_seconds() >= 0;
+        match &outcome {
+            Ok(result) => {
+                tracing::info!(
+                    component = "scheduler",
+                    job_id = %job.id,
+                    job_name = %job.name,
+                    attempt = attempt,
+                    result_len = result.len(),
+                    "Job completed successfully"
+                );
+                match output::route(run_job, &run_id, result, &config).await {
+                    Ok(path) => output_path = path,
+                    Err(e) => {
+                        tracing::error!(
+                            component = "scheduler",
+                            job_id = %job.id,
+                            error = %e,
+                            "Failed to route output"
+                        );
+                    }
+                }
+                let duration = (Utc::now() - run_started).num_seconds();
+                if let Err(e) =
+                    output::route_success(&job, attempt, duration, output_path.as_deref(), &config).await
+                {
+                    tracing::error!(
+                        component = "scheduler",
+                        job_id = %job.id,
+                        error = %e,
+                        "Failed to route success notification"
+                    );
+                }
+            }
+            Err(e) => {
+                tracing::error!(
+                    component = "scheduler",
+                    job_id = %job.id,
+                    job_name = %job.name,
+                    attempt = attempt,
+                    error = %e,
+                    "Job execution failed"
+                );
+            }
+        }
 
-        if should {
-            tracing::info!(
+        let mut record = RunRecord::from_outcome(
+            &job.id,
+            trigger,
+            started_at,
+            &outcome,
+            output_path.as_deref(),
+        );
+        record.run_id = run_id.clone();
+        record.scheduled_at = scheduled_at;
+        record.attempt = attempt;
+        if let Err(e) = history::append(&config, &record) {
+            tracing::error!(
                 component = "scheduler",
                 job_id = %job.id,
-                job_name = %job.name,
-                cron = %job.schedule,
-                status = "fire",
-                "Job firing now"
+                error = %e,
+                "Failed to record run history"
             );
-        } else {
-            tracing::debug!(
+        }
+
+        let error = match outcome {
+            Ok(result) => {
+                succeeded_with = Some(result);
+                break;
+            }
+            Err(error) => error,
+        };
+
+        if retry::should_retry(&job, attempt, &error) {
+            let delay = retry::backoff_delay(&job, attempt);
+            tracing::warn!(
                 component = "scheduler",
                 job_id = %job.id,
                 job_name = %job.name,
-                cron = %job.schedule,
-                next_utc = %next.format("%Y-%m-%d %H:%M:%S UTC"),
-                next_local = %next_local.format("%H:%M:%S %Z"),
-                wait_duration = %format_duration(diff.num_seconds()),
-                status = "wait",
-                "Job waiting"
+                attempt = attempt,
+                retries = job.retries,
+                delay = %format_duration(delay.as_secs() as i64),
+                "Retrying job"
             );
+            // Let other jobs use the concurrency slot during the backoff
+            guard.release_permit();
+            tokio::select! {
+                _ = sleep(delay) => {}
+                _ = guard.cancelled() => break,
+                _ = shutdown.draining() => break,
+            }
+            tokio::select! {
+                _ = guard.reacquire_permit() => {
+                    attempt += 1;
+                    continue;
+                }
+                _ = shutdown.draining() => break,
+            }
         }
-        should
-    } else {
-        tracing::error!(
-            component = "scheduler",
-            job_id = %job.id,
-            job_name = %job.name,
-            "No upcoming schedule found"
-        );
-        false
+
+        if !retry::is_cancelled(&error) {
+            if let Err(e) = output::route_failure(&job, attempt, &error, &config).await {
+                tracing::error!(
+                    component = "scheduler",
+                    job_id = %job.id,
+                    error = %e,
+                    "Failed to route failure notification"
+                );
+            }
+        }
+        break;
+    }
+    drop(guard);
+
+    if let Some(result) = succeeded_with {
+        let text = history::result_text(&result);
+        if let Err(e) = history::save_last_result(&config, &job.id, &text) {
+            tracing::error!(
+                component = "scheduler",
+                job_id = %job.id,
+                error = %e,
+                "Failed to save last result"
+            );
+        }
+        if let Some(Err(e)) = fingerprint.map(|f| f.save()) {
+            tracing::error!(
+                component = "scheduler",
+                job_id = %job.id,
+                error = %e,
+                "Failed to save run_if fingerprint"
+            );
+        }
+        trigger_dependents(&job, text, &config, &running);
+    }
+
+    // Disable one-shot jobs after execution
+    if job.schedule_type == "once" {
+        disable_one_shot(&job.id, &config, "after execution").await;
     }
 }
 
-fn should_run_once(job: &Job, now: chrono::DateTime<Utc>) -> bool {
-    let Some(ref once_at) = job.once_at else {
+/// Disable a `once` job in jobs.toml so it isn't left pending.
+async fn disable_one_shot(job_id: &str, config: &DemonConfig, when: &str) {
+    let id = job_id.to_string();
+    let disabled = config
+        .update_jobs_async(move |jobs| {
+            Ok(jobs
+                .iter_mut()
+                .find(|j| j.id == id)
+                .map(|j| j.enabled = false)
+                .is_some())
+        })
+        .await;
+    match disabled {
+        Ok(true) => {
+            tracing::info!(
+                component = "scheduler",
+                job_id = %job_id,
+                "One-shot job disabled {when}"
+            );
+        }
+        // Removed in the meantime
+        Ok(false) => {}
+        Err(e) => {
+            tracing::error!(
+                component = "scheduler",
+                job_id = %job_id,
+                error = %e,
+                "Failed to disable one-shot job"
+            );
+        }
+    }
+}
+
+/// Record a run that was skipped without starting claude.
+fn record_skip(
+    config: &DemonConfig,
+    job_id: &str,
+    run_id: String,
+    trigger: RunTrigger,
+    scheduled_at: Option<DateTime<Utc>>,
+    reason: &str,
+) {
+    let mut record = RunRecord::skipped(job_id, trigger, scheduled_at, reason);
+    record.run_id = run_id;
+    if let Err(e) = history::append(config, &record) {
         tracing::error!(
             component = "scheduler",
-            job_id = %job.id,
-            job_name = %job.name,
-            "Once job missing once_at field"
+            job_id = %job_id,
+            error = %e,
+            "Failed to record run history"
         );
-        return false;
-    };
+    }
+}
 
-    let target = match parse_datetime(once_at) {
-        Some(t) => t,
-        None => {
+/// Start every "after" job that was waiting on this successful run.
+fn trigger_dependents(job: &Job, result: String, config: &DemonConfig, running: &Arc<RunningJobs>) {
+    let jobs = match config.load_jobs() {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            tracing::error!(component = "scheduler", error = %e, "Failed to load jobs");
+            return;
+        }
+    };
+    let ready = match running.claim_dependents(&job.id, &jobs, config) {
+        Ok(ready) => ready,
+        Err(e) => {
             tracing::error!(
                 component = "scheduler",
                 job_id = %job.id,
-                job_name = %job.name,
-                once_at = %once_at,
-                "Invalid once_at datetime format"
+                error = %e,
+                "Failed to check dependent jobs"
             );
-            return false;
+            return;
         }
     };
 
-    let diff = (target - now).num_seconds();
-    let should = diff <= 30 && diff >= -30;
-    let target_local = target.with_timezone(&Local);
-
-    if should {
+    let upstream = Upstream {
+        job_id: job.id.clone(),
+        result,
+    };
+    for dependent in ready {
         tracing::info!(
             component = "scheduler",
-            job_id = %job.id,
-            job_name = %job.name,
-            once_at = %once_at,
+            job_id = %dependent.id,
+            job_name = %dependent.name,
+            upstream = %job.id,
             status = "fire",
-            "Once job firing now"
+            "Dependencies satisfied, firing job"
         );
-    } else if diff > 0 {
-        tracing::debug!(
-            component = "scheduler",
-            job_id = %job.id,
-            job_name = %job.name,
-            target_utc = %target.format("%Y-%m-%d %H:%M:%S UTC"),
-            target_local = %target_local.format("%H:%M:%S %Z"),
-            wait_duration = %format_duration(diff),
-            status = "wait",
-            "Once job waiting"
-        );
-    } else {
-        tracing::warn!(
-            component = "scheduler",
-            job_id = %job.id,
-            job_name = %job.name,
-            once_at = %once_at,
-            missed_by = %format_duration(-diff),
-            status = "miss",
-            "Once job missed its scheduled time"
+        spawn_run(
+            dependent.clone(),
+            config.clone(),
+            running.clone(),
+            RunTrigger::Dependency,
+            None,
+            Some(upstream.clone()),
         );
     }
+}
+
+/// Scheduled instants of `schedule` strictly after `after`, evaluated in
+/// `tz` (UTC when `None`).
+///
+/// DST is handled deterministically: local times skipped when clocks go
+/// forward never fire, and local times repeated when clocks go back fire
+/// once, at the earlier instant.
+fn occurrences_after<'a>(
+    schedule: &'a Schedule,
+    tz: Option<Tz>,
+    after: DateTime<Utc>,
+) -> Box<dyn Iterator<Item = DateTime<Utc>> + 'a> {
+    match tz {
+        None => Box::new(schedule.after(&after)),
+        Some(tz) => Box::new(
+            schedule
+                .after(&after.with_timezone(&tz))
+                .filter(move |at| tz.from_local_datetime(&at.naive_local()).earliest().as_ref() == Some(at))
+                .map(|at| at.with_timezone(&Utc)),
+        ),
+    }
+}
 
-    should
+/// The next scheduled run of a job after `after`, in the job's timezone.
+pub fn next_run(job: &Job, config: &DemonConfig, after: DateTime<Utc>) -> Result<Option<DateTime<Utc>>> {
+    let tz = job.schedule_timezone(&config.defaults)?;
+    Ok(next_fire(job, tz, after))
 }
 
-fn format_duration(secs: i64) -> String {
+/// The next `count` scheduled instants of a job after `after`. Empty for
+/// jobs the clock never fires.
+pub fn upcoming_runs(
+    job: &Job,
+    config: &DemonConfig,
+    after: DateTime<Utc>,
+    count: usize,
+) -> Result<Vec<DateTime<Utc>>> {
+    let tz = job.schedule_timezone(&config.defaults)?;
+    let mut runs = Vec::with_capacity(count);
+    let mut from = after;
+    while runs.len() < count {
+        let Some(at) = next_fire(job, tz, from) else {
+            break;
+        };
+        runs.push(at);
+        from = at;
+    }
+    Ok(runs)
+}
+
+/// The first scheduled instant of a job strictly after `after`.
+fn next_fire(job: &Job, tz: Option<Tz>, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    match job.schedule_type.as_str() {
+        "recurring" => match Schedule::from_str(&job.schedule) {
+            Ok(schedule) => occurrences_after(&schedule, tz, after).next(),
+            Err(e) => {
+                tracing::error!(
+                    component = "scheduler",
+                    job_id = %job.id,
+                    job_name = %job.name,
+                    cron = %job.schedule,
+                    error = %e,
+                    "Invalid cron expression"
+                );
+                None
+            }
+        },
+        "once" => {
+            let Some(ref once_at) = job.once_at else {
+                tracing::error!(
+                    component = "scheduler",
+                    job_id = %job.id,
+                    job_name = %job.name,
+                    "Once job missing once_at field"
+                );
+                return None;
+            };
+            match parse_datetime(once_at, tz) {
+                Some(target) => (target > after).then_some(target),
+                None => {
+                    tracing::error!(
+                        component = "scheduler",
+                        job_id = %job.id,
+                        job_name = %job.name,
+                        once_at = %once_at,
+                        "Invalid once_at datetime format"
+                    );
+                    None
+                }
+            }
+        }
+        // Fired by upstream jobs, never by the clock
+        "after" => None,
+        other => {
+            tracing::error!(
+                component = "scheduler",
+                job_id = %job.id,
+                job_name = %job.name,
+                schedule_type = %other,
+                "Unknown schedule type"
+            );
+            None
+        }
+    }
+}
+
+/// Short description of when a job runs.
+pub fn schedule_summary(job: &Job) -> String {
+    match job.schedule_type.as_str() {
+        "once" => job.once_at.clone().unwrap_or_else(|| "pending".to_string()),
+        "after" => format!("after {}", job.depends_on.join(",")),
+        _ => job.schedule.clone(),
+    }
+}
+
+/// Format a job's next run time in its own timezone (system local time
+/// when it has none).
+pub fn next_run_summary(config: &DemonConfig, job: &Job) -> String {
+    match next_run(job, config, Utc::now()) {
+        Ok(Some(at)) => format_in_job_timezone(config, job, at),
+        Ok(None) => "-".to_string(),
+        Err(e) => format!("error: {e}"),
+    }
+}
+
+pub fn format_in_job_timezone(config: &DemonConfig, job: &Job, at: DateTime<Utc>) -> String {
+    match job.schedule_timezone(&config.defaults) {
+        Ok(Some(tz)) => at.with_timezone(&tz).format("%Y-%m-%d %H:%M %Z").to_string(),
+        _ => at
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M %Z")
+            .to_string(),
+    }
+}
+
+/// Format the last run time and status of a job for table display.
+pub fn last_run_summary(config: &DemonConfig, job_id: &str) -> (String, String) {
+    match history::last_run(config, job_id) {
+        Ok(Some(r)) => (
+            r.started_at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            r.status.to_string(),
+        ),
+        _ => ("never".to_string(), "-".to_string()),
+    }
+}
+
+pub fn format_duration(secs: i64) -> String {
     let secs = secs.unsigned_abs();
     if secs < 60 {
         format!("{}s", secs)
@@ -290,11 +940,67 @@ fn format_duration(secs: i64) -> String {
     }
 }
 
+/// Check that a job's schedule can be evaluated by the scheduler.
+pub fn validate_job(job: &Job, config: &DemonConfig) -> Result<()> {
+    match job_problems(job, config).into_iter().next() {
+        Some((_, problem)) => anyhow::bail!("Job '{}': {}", job.id, problem),
+        None => Ok(()),
+    }
+}
+
+/// Everything that stops the scheduler from running a job, each with the
+/// field it concerns.
+pub fn job_problems(job: &Job, config: &DemonConfig) -> Vec<(&'static str, String)> {
+    let mut problems = Vec::new();
+    let tz = match job.schedule_timezone(&config.defaults) {
+        Ok(tz) => tz,
+        Err(e) => {
+            problems.push(("timezone", e.to_string()));
+            None
+        }
+    };
+    if job.prompt.is_empty() && job.prompt_file.is_none() {
+        problems.push(("prompt", "needs a prompt or prompt_file".to_string()));
+    }
+    if job
+        .run_if
+        .as_ref()
+        .is_some_and(|r| r.command.is_none() && r.path.is_none())
+    {
+        problems.push(("run_if", "run_if needs a command or a path".to_string()));
+    }
+    match job.schedule_type.as_str() {
+        "recurring" => {
+            if let Err(e) = Schedule::from_str(&job.schedule) {
+                problems.push((
+                    "schedule",
+                    format!("invalid cron expression '{}': {}", job.schedule, e),
+                ));
+            }
+        }
+        "once" => match job.once_at {
+            None => problems.push(("schedule_type", "once job missing once_at field".to_string())),
+            Some(ref once_at) => {
+                if parse_datetime(once_at, tz).is_none() {
+                    problems.push(("once_at", format!("invalid once_at datetime '{}'", once_at)));
+                }
+            }
+        },
+        "after" => {
+            if job.depends_on.is_empty() {
+                problems.push(("schedule_type", "\"after\" job has no depends_on".to_string()));
+            }
+        }
+        other => problems.push(("schedule_type", format!("unknown schedule type '{}'", other))),
+    }
+    problems
+}
+
 /// Parse a datetime string in various formats:
 /// - RFC 3339 / ISO 8601 with timezone: "2026-02-04T15:44:00+07:00"
 /// - UTC with Z suffix: "2026-02-04T15:44:00Z"
-/// - Naive local time: "2026-02-04T15:44:00"
-fn parse_datetime(s: &str) -> Option<DateTime<Utc>> {
+/// - Naive time in `tz`, or system local time when `None`: "2026-02-04T15:44:00"
+fn parse_datetime(s: &str, tz: Option<Tz>) -> Option<DateTime<Utc>> {
     // Try RFC 3339 (handles +07:00, Z, etc.)
     if let Ok(dt) = DateTime::<FixedOffset>::parse_from_rfc3339(s) {
         return Some(dt.with_timezone(&Utc));
@@ -305,26 +1011,163 @@ fn parse_datetime(s: &str) -> Option<DateTime<Utc>> {
         return Some(dt);
     }
 
-    // Try as naive local time
-    if let Ok(naive) = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S") {
-        let local = Local::now().timezone();
-        if let Some(local_dt) = naive.and_local_timezone(local).single() {
-            return Some(local_dt.with_timezone(&Utc));
+    // Try as naive time with T or space separator
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S"))
+        .ok()?;
+
+    match tz {
+        Some(tz) => resolve_local(&tz, naive),
+        None => resolve_local(&Local, naive),
+    }
+}
+
+/// Resolve a naive local time across DST transitions: ambiguous times
+/// use the earlier instant, and times inside a gap are shifted forward
+/// by an hour.
+fn resolve_local<Z: TimeZone>(tz: &Z, naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(naive + chrono::Duration::hours(1)))
+                .earliest()
+        })
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// Run a job now, outside its schedule, as `demon job run` and the
+/// gateway's `/run` do: check its budget, render it, execute it and record
+/// the run. Inside the daemon, `running` applies the job's overlap policy
+/// and the global limit as for scheduled runs. Returns claude's output.
+pub async fn run_now(job: &Job, config: &DemonConfig, trigger: RunTrigger, running: Option<&Arc<RunningJobs>>) -> Result<String> {
+    let run_id = uuid::Uuid::new_v4().to_string();
+    let mut guard = match running {
+        Some(running) => match running.acquire(job, &run_id).await {
+            Some(guard) => Some(guard),
+            None => {
+                record_skip(config, &job.id, run_id, trigger, None, "Previous run still in progress");
+                anyhow::bail!("Job '{}' is already running", job.id);
+            }
+        },
+        None => None,
+    };
+
+    if let Some(reason) = usage::check(config, Scope::Job(job), Utc::now())? {
+        anyhow::bail!("{reason}");
+    }
+
+    let rendered = TemplateContext::for_job(job, config).render_job(job, config)?;
+    let recorder = RunRecorder {
+        config,
+        job_id: &job.id,
+        run_id,
+        trigger,
+        started_at: Utc::now(),
+        recorded: false,
+    };
+    let execute = async {
+        let prompt = context::build_prompt(&rendered).await?;
+        execute_job(&rendered, &prompt, config).await
+    };
+    let outcome = match (guard.as_mut(), running) {
+        (Some(guard), Some(running)) => tokio::select! {
+            outcome = execute => outcome,
+            _ = guard.cancelled() => Err(JobError::Cancelled.into()),
+            _ = running.shutdown().aborted() => Err(JobError::Shutdown.into()),
+        },
+        _ => execute.await,
+    };
+    recorder.record(&outcome);
+
+    let result = outcome?;
+    if let Err(e) = history::save_last_result(config, &job.id, &history::result_text(&result)) {
+        tracing::warn!(component = "scheduler", job_id = %job.id, error = %e, "Failed to save last result");
+    }
+    Ok(result)
+}
+
+/// Writes a manual run's history record, or a cancelled one if the run is
+/// dropped before finishing (e.g. by the gateway's `/cancel`).
+struct RunRecorder<'a> {
+    config: &'a DemonConfig,
+    job_id: &'a str,
+    run_id: String,
+    trigger: RunTrigger,
+    started_at: DateTime<Utc>,
+    recorded: bool,
+}
+
+impl RunRecorder<'_> {
+    fn record(mut self, outcome: &Result<String>) {
+        self.append(outcome);
+    }
+
+    fn append(&mut self, outcome: &Result<String>) {
+        self.recorded = true;
+        let mut record = RunRecord::from_outcome(self.job_id, self.trigger, self.started_at, outcome, None);
+        record.run_id = std::mem::take(&mut self.run_id);
+        if let Err(e) = history::append(self.config, &record) {
+            tracing::warn!(component = "scheduler", job_id = %self.job_id, error = %e, "Failed to record run history");
         }
     }
+}
 
-    // Try as naive local time with space separator
-    if let Ok(naive) = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S") {
-        let local = Local::now().timezone();
-        if let Some(local_dt) = naive.and_local_timezone(local).single() {
-            return Some(local_dt.with_timezone(&Utc));
+impl Drop for RunRecorder<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.append(&Err(JobError::Cancelled.into()));
         }
     }
+}
+
+/// Run claude for `job` with `prompt`, the job's prompt after context
+/// commands (see `context::build_prompt`).
+pub async fn execute_job(job: &Job, prompt: &str, config: &DemonConfig) -> Result<String> {
+    let mut cmd = claude_command(job, prompt);
 
-    None
+    tracing::debug!(
+        component = "scheduler",
+        job_id = %job.id,
+        model = %job.model,
+        working_dir = %job.working_dir,
+        "Spawning claude CLI"
+    );
+
+    // Dropping this future (e.g. on cancellation) kills claude's process group
+    let timeout = job.timeout_secs.map(Duration::from_secs);
+    let output = process::output_with_timeout(&mut cmd, timeout)
+        .await
+        .map_err(|e| match e {
+            ProcessError::Io(e) => JobError::Spawn(e),
+            ProcessError::TimedOut { secs } => JobError::Timeout { secs },
+        })?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    if let Some(spent) = Usage::parse(&stdout) {
+        usage::record(config, UsageEntry::job(&job.id, spent));
+    }
+
+    if output.status.success() {
+        match result_error(&stdout) {
+            Some(message) => Err(JobError::Result {
+                message,
+                output: stdout,
+            }
+            .into()),
+            None => Ok(stdout),
+        }
+    } else {
+        Err(JobError::Exit {
+            code: output.status.code(),
+            status: output.status.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        }
+        .into())
+    }
 }
 
-pub async fn execute_job(job: &Job, _config: &DemonConfig) -> Result<String> {
+/// The `claude -p` command line for a job run.
+fn claude_command(job: &Job, prompt: &str) -> tokio::process::Command {
     let mut cmd = tokio::process::Command::new("claude");
     cmd.arg("-p");
 
@@ -373,34 +1216,297 @@ pub async fn execute_job(job: &Job, _config: &DemonConfig) -> Result<String> {
     // Output format
     cmd.arg("--output-format").arg(&job.output_format);
 
-    // No session persistence for cron jobs
-    cmd.arg("--no-session-persistence");
+    // No session persistence for cron jobs, unless the result lets the
+    // chat follow up on the run
+    if !output::offers_follow_up(job) {
+        cmd.arg("--no-session-persistence");
+    }
 
     // Working directory
     if !job.working_dir.is_empty() {
         cmd.current_dir(&job.working_dir);
     }
 
-    // The prompt
-    cmd.arg(&job.prompt);
+    cmd.arg(prompt);
+    cmd
+}
 
-    tracing::debug!(
-        component = "scheduler",
-        job_id = %job.id,
-        model = %job.model,
-        working_dir = %job.working_dir,
-        "Spawning claude CLI"
-    );
+/// The error message of a JSON result with `is_error` set, if any.
+fn result_error(output: &str) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_str(output.trim()).ok()?;
+    if !json.get("is_error").and_then(|v| v.as_bool()).unwrap_or(false) {
+        return None;
+    }
+    let message = json
+        .get("result")
+        .and_then(|v| v.as_str())
+        .or_else(|| json.get("subtype").and_then(|v| v.as_str()))
+        .unwrap_or("unknown error");
+    Some(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let output = cmd
-        .output()
+    fn job(toml_str: &str) -> Job {
+        toml::from_str(toml_str).unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_runs_offering_follow_up_keep_their_session() {
+        let output = r#"{"type":"result","result":"ok","session_id":"abc"}"#;
+        let base = "id = \"j\"\nname = \"J\"\nschedule = \"0 * * * * *\"\nprompt = \"hi\"\n";
+        for (extra, follows_up) in [
+            ("output_destinations = [\"telegram:1\"]", true),
+            ("output_destinations = [\"telegram:1\", \"file\"]", true),
+            ("output_destinations = [\"telegram:1\"]\noutput_format = \"text\"", false),
+            ("output_destinations = [\"file\"]", false),
+        ] {
+            let job = job(&format!("{base}{extra}"));
+            let keyboard = output::keyboard(&job, "run-1", output).unwrap();
+            let offered = keyboard.inline_keyboard.iter().flatten().any(|b| b.text == "Ask follow-up");
+            let cmd = claude_command(&job, "hi");
+            let persisted = !cmd.as_std().get_args().any(|a| a == "--no-session-persistence");
+            assert_eq!(offered, follows_up, "{extra}");
+            assert_eq!(persisted, follows_up, "{extra}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_now_follows_overlap_and_records_cancel() {
+        let dir = std::env::temp_dir().join(format!("demon-run-now-{}", uuid::Uuid::new_v4()));
+        let config = DemonConfig {
+            paths: crate::config::PathsConfig {
+                base_dir: Some(dir.to_string_lossy().to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let running = RunningJobs::new(&config, crate::daemon::Shutdown::new());
+        let job = job("id = \"j\"\nname = \"J\"\nschedule = \"0 * * * * *\"\nprompt = \"hi\"\noverlap = \"skip\"");
+
+        // A chat run while a scheduled run holds the slot is skipped
+        let scheduled = running.acquire(&job, "scheduled").await.unwrap();
+        let err = run_now(&job, &config, RunTrigger::Chat, Some(&running)).await.unwrap_err();
+        assert_eq!(err.to_string(), "Job 'j' is already running");
+        drop(scheduled);
+
+        // A run dropped before finishing, as by /cancel, is still recorded
+        drop(RunRecorder {
+            config: &config,
+            job_id: &job.id,
+            run_id: "cancelled".to_string(),
+            trigger: RunTrigger::Chat,
+            started_at: Utc::now(),
+            recorded: false,
+        });
+        let records = history::load(&config, "j").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].status, history::RunStatus::Skipped);
+        assert_eq!(records[1].run_id, "cancelled");
+        assert!(records[1].error.as_deref().unwrap_or_default().contains("cancelled"), "{:?}", records[1].error);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_missed_runs_recurring_keeps_most_recent() {
+        let job = job(
+            r#"
+            id = "hourly"
+            name = "Hourly"
+            schedule = "0 0 * * * *"
+            prompt = "hi"
+            catch_up_max = 2
+            "#,
+        );
+        let (count, missed) = missed_runs(
+            &job,
+            None,
+            Some(utc("2026-01-01T00:00:00Z")),
+            utc("2026-01-01T04:30:00Z"),
+        );
+
+        assert_eq!(count, 4);
+        assert_eq!(
+            missed,
+            vec![utc("2026-01-01T03:00:00Z"), utc("2026-01-01T04:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn test_missed_runs_recurring_needs_last_fired() {
+        let job = job(
+            r#"
+            id = "hourly"
+            name = "Hourly"
+            schedule = "0 0 * * * *"
+            prompt = "hi"
+            "#,
+        );
+        assert_eq!(missed_runs(&job, None, None, Utc::now()).0, 0);
+    }
+
+    #[test]
+    fn test_next_fire_supports_seconds() {
+        let job = job(
+            r#"
+            id = "fast"
+            name = "Fast"
+            schedule = "*/15 * * * * *"
+            prompt = "hi"
+            "#,
+        );
+        let at = next_fire(&job, None, utc("2026-01-01T00:00:00Z")).unwrap();
+        assert_eq!(at, utc("2026-01-01T00:00:15Z"));
+        assert_eq!(next_fire(&job, None, at).unwrap(), utc("2026-01-01T00:00:30Z"));
+    }
+
+    #[test]
+    fn test_next_fire_once_is_exclusive() {
+        let job = job(
+            r#"
+            id = "once"
+            name = "Once"
+            schedule_type = "once"
+            once_at = "2026-01-01T09:00:00Z"
+            prompt = "hi"
+            "#,
+        );
+        let target = utc("2026-01-01T09:00:00Z");
+        assert_eq!(next_fire(&job, None, utc("2026-01-01T08:00:00Z")), Some(target));
+        assert_eq!(next_fire(&job, None, target), None);
+    }
+
+    #[test]
+    fn test_next_fire_in_timezone() {
+        let job = job(
+            r#"
+            id = "morning"
+            name = "Morning"
+            schedule = "0 0 9 * * *"
+            prompt = "hi"
+            "#,
+        );
+        let tz: Tz = "Asia/Ho_Chi_Minh".parse().unwrap();
+        assert_eq!(
+            next_fire(&job, Some(tz), utc("2026-01-01T00:00:00Z")),
+            Some(utc("2026-01-01T02:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_next_fire_dst_transitions() {
+        let tz: Tz = "America/New_York".parse().unwrap();
+
+        // Clocks go back at 02:00 on 2026-11-01: 01:30 happens twice, fire once
+        let job_fall = job(
+            r#"
+            id = "fall"
+            name = "Fall"
+            schedule = "0 30 1 * * *"
+            prompt = "hi"
+            "#,
+        );
+        let first = next_fire(&job_fall, Some(tz), utc("2026-11-01T04:00:00Z")).unwrap();
+        assert_eq!(first, utc("2026-11-01T05:30:00Z"));
+        assert_eq!(
+            next_fire(&job_fall, Some(tz), first),
+            Some(utc("2026-11-02T06:30:00Z"))
+        );
+
+        // Clocks go forward at 02:00 on 2026-03-08: 02:30 doesn't exist, skip it
+        let job_spring = job(
+            r#"
+            id = "spring"
+            name = "Spring"
+            schedule = "0 30 2 * * *"
+            prompt = "hi"
+            "#,
+        );
+        assert_eq!(
+            next_fire(&job_spring, Some(tz), utc("2026-03-08T05:00:00Z")),
+            Some(utc("2026-03-09T06:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_parse_datetime_naive_in_timezone() {
+        let tz: Tz = "America/New_York".parse().unwrap();
+        assert_eq!(
+            parse_datetime("2026-01-15 09:00:00", Some(tz)),
+            Some(utc("2026-01-15T14:00:00Z"))
+        );
+        // Inside the spring-forward gap: shifted past it
+        assert_eq!(
+            parse_datetime("2026-03-08T02:30:00", Some(tz)),
+            Some(utc("2026-03-08T07:30:00Z"))
+        );
+        // Offsets in the string win over the job timezone
+        assert_eq!(
+            parse_datetime("2026-01-15T09:00:00Z", Some(tz)),
+            Some(utc("2026-01-15T09:00:00Z"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_skip_disables_missed_once_job() {
+        use tokio::time::timeout;
+
+        let dir = std::env::temp_dir().join(format!("demon-catch-up-{}", uuid::Uuid::new_v4()));
+        let config = DemonConfig {
+            paths: crate::config::PathsConfig {
+                base_dir: Some(dir.to_string_lossy().to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            config.paths.jobs_file(),
+            "[[jobs]]\nid = \"once\"\nname = \"Once\"\nschedule_type = \"once\"\nonce_at = \"2026-01-01T09:00:00Z\"\nprompt = \"hi\"\ncatch_up = \"skip\"",
+        )
+        .unwrap();
+        let jobs = config.load_jobs().unwrap();
+        let running = RunningJobs::new(&config, crate::daemon::Shutdown::new());
+        let mut state = SchedulerState::default();
+
+        catch_up(&jobs, &config, &mut state, &running, utc("2026-01-02T00:00:00Z"));
+        assert_eq!(state.last_fired("once"), Some(utc("2026-01-01T09:00:00Z")));
+        // Disabled in the background
+        timeout(Duration::from_secs(5), async {
+            while config.load_jobs().unwrap()[0].enabled {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
         .await
-        .context("Failed to execute claude CLI")?;
+        .unwrap();
 
-    if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("claude CLI exited with {}: {}", output.status, stderr)
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_missed_runs_once() {
+        let job = job(
+            r#"
+            id = "once"
+            name = "Once"
+            schedule_type = "once"
+            once_at = "2026-01-01T09:00:00Z"
+            prompt = "hi"
+            "#,
+        );
+        let now = utc("2026-01-02T00:00:00Z");
+
+        assert_eq!(missed_runs(&job, None, None, now).1, vec![utc("2026-01-01T09:00:00Z")]);
+        assert_eq!(
+            missed_runs(&job, None, Some(utc("2026-01-01T09:00:00Z")), now).0,
+            0
+        );
     }
 }
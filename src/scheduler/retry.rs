@@ -0,0 +1,97 @@
+//! Retry policy for failed job runs.
+
+use tokio::time::Duration;
+
+use super::JobError;
+use crate::config::{Job, RetryBackoff, RetryOn};
+
+/// Longest delay between two attempts, however many retries came before.
+const MAX_RETRY_DELAY_SECS: u64 = 3600;
+
+/// The kind of failure an execution error represents, or `None` if it is
+/// never retried (e.g. the run was cancelled).
+fn failure_kind(error: &anyhow::Error) -> Option<RetryOn> {
+    match error.downcast_ref::<JobError>()? {
+        JobError::Spawn(_) => Some(RetryOn::SpawnError),
+        JobError::Exit { .. } => Some(RetryOn::NonZeroExit),
+        JobError::Timeout { .. } => Some(RetryOn::Timeout),
+        JobError::Result { .. } => Some(RetryOn::IsError),
+        JobError::Context { .. } | JobError::Cancelled | JobError::Shutdown => None,
+    }
+}
+
+/// Whether a failed attempt should be retried. `attempt` is the number of
+/// the attempt that just failed, starting at 1.
+pub fn should_retry(job: &Job, attempt: u32, error: &anyhow::Error) -> bool {
+    attempt <= job.retries && failure_kind(error).is_some_and(|kind| job.retry_on.contains(&kind))
+}
+
+/// Delay before the attempt following `attempt`.
+pub fn backoff_delay(job: &Job, attempt: u32) -> Duration {
+    let secs = match job.retry_backoff {
+        RetryBackoff::Fixed => job.retry_delay_secs,
+        RetryBackoff::Exponential => job
+            .retry_delay_secs
+            .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1))),
+    };
+    Duration::from_secs(secs.min(MAX_RETRY_DELAY_SECS))
+}
+
+/// Whether an error is the result of the run being cancelled, by a newer
+/// run or by shutdown.
+pub fn is_cancelled(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<JobError>(),
+        Some(JobError::Cancelled | JobError::Shutdown)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(extra: &str) -> Job {
+        toml::from_str(&format!(
+            "id = \"j\"\nname = \"J\"\nschedule = \"0 * * * * *\"\nprompt = \"hi\"\n{extra}"
+        ))
+        .unwrap()
+    }
+
+    fn exit_error() -> anyhow::Error {
+        JobError::Exit {
+            code: Some(1),
+            status: "exit status: 1".to_string(),
+            stderr: String::new(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_should_retry_respects_count_and_kinds() {
+        let job = job("retries = 2");
+        assert!(should_retry(&job, 1, &exit_error()));
+        assert!(should_retry(&job, 2, &exit_error()));
+        assert!(!should_retry(&job, 3, &exit_error()));
+
+        let is_error: anyhow::Error = JobError::Result {
+            message: "error_max_turns".to_string(),
+            output: String::new(),
+        }
+        .into();
+        assert!(!should_retry(&job, 1, &is_error));
+        assert!(should_retry(&job, 1, &JobError::Timeout { secs: 60 }.into()));
+        assert!(!should_retry(&job, 1, &JobError::Cancelled.into()));
+        assert!(!should_retry(&job, 1, &anyhow::anyhow!("other")));
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let fixed = job("retry_delay_secs = 10");
+        assert_eq!(backoff_delay(&fixed, 3), Duration::from_secs(10));
+
+        let exp = job("retry_delay_secs = 10\nretry_backoff = \"exponential\"");
+        assert_eq!(backoff_delay(&exp, 1), Duration::from_secs(10));
+        assert_eq!(backoff_delay(&exp, 3), Duration::from_secs(40));
+        assert_eq!(backoff_delay(&exp, 40), Duration::from_secs(MAX_RETRY_DELAY_SECS));
+    }
+}
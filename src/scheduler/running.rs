@@ -0,0 +1,462 @@
+//! Registry of running jobs.
+//!
+//! Enforces each job's overlap policy and the global `max_concurrent_jobs`
+//! limit, and mirrors the set of active runs to ~/.demon/running.json so
+//! `demon status` can show them.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{oneshot, Notify, OwnedMutexGuard};
+
+use super::dependency;
+use crate::config::{DemonConfig, Job, OverlapPolicy};
+use crate::daemon::Shutdown;
+use crate::store;
+
+/// Whether a registered run is executing or waiting for a slot.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunState {
+    Waiting,
+    Running,
+}
+
+/// A run as listed in running.json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunInfo {
+    pub run_id: String,
+    pub job_id: String,
+    pub job_name: String,
+    pub registered_at: DateTime<Utc>,
+    pub state: RunState,
+}
+
+#[derive(Default)]
+struct JobSlot {
+    /// Held for the duration of a run by every policy except `allow`
+    lock: Arc<tokio::sync::Mutex<()>>,
+    /// Cancels the run currently holding `lock` (for `kill-previous`)
+    cancel: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+struct Inner {
+    slots: HashMap<String, Arc<JobSlot>>,
+    runs: HashMap<String, RunInfo>,
+    /// Most runs holding a permit at once; 0 for unlimited
+    limit: usize,
+    /// Runs holding a permit, including those started under an older limit
+    active: usize,
+    /// When each "after" job was last started by its dependencies
+    triggered: HashMap<String, DateTime<Utc>>,
+}
+
+pub struct RunningJobs {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+    /// Wakes runs waiting for a permit when one is returned or the limit changes
+    permit_freed: Notify,
+    shutdown: Shutdown,
+}
+
+/// A run's place under the global limit, given back when dropped.
+struct Permit {
+    registry: Arc<RunningJobs>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.registry.inner.lock().unwrap().active -= 1;
+        self.registry.permit_freed.notify_waiters();
+    }
+}
+
+/// Held while a job runs; unregisters the run when dropped.
+pub struct RunGuard {
+    registry: Arc<RunningJobs>,
+    run_id: String,
+    cancel_rx: Option<oneshot::Receiver<()>>,
+    is_cancelled: bool,
+    _slot: Option<OwnedMutexGuard<()>>,
+    permit: Option<Permit>,
+}
+
+impl RunGuard {
+    /// Resolves when a newer run of the same job asks this one to stop,
+    /// and immediately on every call after that. Never resolves for runs
+    /// that cannot be cancelled.
+    pub async fn cancelled(&mut self) {
+        if let Some(rx) = self.cancel_rx.as_mut() {
+            let cancelled = rx.await.is_ok();
+            // A completed receiver must not be polled again
+            self.cancel_rx = None;
+            self.is_cancelled = cancelled;
+        }
+        if !self.is_cancelled {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Give the concurrency permit back while the run waits, e.g. between
+    /// retries, so other jobs can start. The job's overlap slot is kept.
+    pub fn release_permit(&mut self) {
+        self.permit = None;
+        self.registry.set_state(&self.run_id, RunState::Waiting);
+    }
+
+    /// Wait for a concurrency permit again after `release_permit`.
+    pub async fn reacquire_permit(&mut self) {
+        self.permit = Some(self.registry.acquire_permit().await);
+        self.registry.set_state(&self.run_id, RunState::Running);
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        self.registry.unregister(&self.run_id);
+    }
+}
+
+impl RunningJobs {
+    /// Create an empty registry, clearing any stale running.json.
+    /// A `max_concurrent_jobs` of 0 means unlimited.
+    pub fn new(config: &DemonConfig, shutdown: Shutdown) -> Arc<Self> {
+        let registry = Arc::new(Self {
+            path: config.paths.running_file(),
+            shutdown,
+            permit_freed: Notify::new(),
+            inner: Mutex::new(Inner {
+                slots: HashMap::new(),
+                runs: HashMap::new(),
+                limit: 0,
+                active: 0,
+                triggered: HashMap::new(),
+            }),
+        });
+        registry.set_limit(config.scheduler.max_concurrent_jobs);
+        registry.save(&registry.inner.lock().unwrap());
+        registry
+    }
+
+    /// The daemon's shutdown signal, which runs follow.
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// Change the global limit. Runs waiting for a permit see the new
+    /// limit at once. Running jobs keep their permits and count against
+    /// it, so a lower limit takes effect as they finish.
+    pub fn set_limit(&self, max_concurrent_jobs: usize) {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.limit == max_concurrent_jobs {
+                return;
+            }
+            inner.limit = max_concurrent_jobs;
+        }
+        self.permit_freed.notify_waiters();
+        tracing::info!(
+            component = "scheduler",
+            max_concurrent_jobs = max_concurrent_jobs,
+            "Concurrency limit set"
+        );
+    }
+
+    /// Wait until the global limit lets one more run hold a permit.
+    async fn acquire_permit(self: &Arc<Self>) -> Permit {
+        loop {
+            let freed = self.permit_freed.notified();
+            tokio::pin!(freed);
+            // Register first so a permit returned while checking isn't missed
+            freed.as_mut().enable();
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if inner.limit == 0 || inner.active < inner.limit {
+                    inner.active += 1;
+                    return Permit {
+                        registry: self.clone(),
+                    };
+                }
+            }
+            freed.await;
+        }
+    }
+
+    /// The "after" jobs that `upstream_id`'s success makes ready, marked
+    /// as triggered in the same step so another upstream finishing at the
+    /// same time can't start them again.
+    pub fn claim_dependents<'a>(&self, upstream_id: &str, jobs: &'a [Job], config: &DemonConfig) -> Result<Vec<&'a Job>> {
+        let mut inner = self.inner.lock().unwrap();
+        let ready = dependency::ready_dependents(upstream_id, jobs, config, &inner.triggered)?;
+        let now = Utc::now();
+        for job in &ready {
+            inner.triggered.insert(job.id.clone(), now);
+        }
+        Ok(ready)
+    }
+
+    /// Apply the job's overlap policy and the global limit, waiting if
+    /// needed. Returns `None` if the run should be skipped.
+    pub async fn acquire(self: &Arc<Self>, job: &Job, run_id: &str) -> Option<RunGuard> {
+        let slot = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.runs.insert(
+                run_id.to_string(),
+                RunInfo {
+                    run_id: run_id.to_string(),
+                    job_id: job.id.clone(),
+                    job_name: job.name.clone(),
+                    registered_at: Utc::now(),
+                    state: RunState::Waiting,
+                },
+            );
+            self.save(&inner);
+            inner.slots.entry(job.id.clone()).or_default().clone()
+        };
+
+        let slot_guard = match job.overlap {
+            OverlapPolicy::Allow => None,
+            OverlapPolicy::Skip => match slot.lock.clone().try_lock_owned() {
+                Ok(guard) => Some(guard),
+                Err(_) => {
+                    self.unregister(run_id);
+                    return None;
+                }
+            },
+            OverlapPolicy::Queue => Some(slot.lock.clone().lock_owned().await),
+            OverlapPolicy::KillPrevious => match slot.lock.clone().try_lock_owned() {
+                Ok(guard) => Some(guard),
+                Err(_) => {
+                    if let Some(cancel) = slot.cancel.lock().unwrap().take() {
+                        tracing::info!(
+                            component = "scheduler",
+                            job_id = %job.id,
+                            "Cancelling previous run"
+                        );
+                        let _ = cancel.send(());
+                    }
+                    Some(slot.lock.clone().lock_owned().await)
+                }
+            },
+        };
+
+        let cancel_rx = slot_guard.as_ref().map(|_| {
+            let (cancel_tx, cancel_rx) = oneshot::channel();
+            *slot.cancel.lock().unwrap() = Some(cancel_tx);
+            cancel_rx
+        });
+
+        let permit = Some(self.acquire_permit().await);
+
+        self.set_state(run_id, RunState::Running);
+
+        Some(RunGuard {
+            registry: self.clone(),
+            run_id: run_id.to_string(),
+            cancel_rx,
+            is_cancelled: false,
+            _slot: slot_guard,
+            permit,
+        })
+    }
+
+    fn set_state(&self, run_id: &str, state: RunState) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(run) = inner.runs.get_mut(run_id) {
+            run.state = state;
+        }
+        self.save(&inner);
+    }
+
+    fn unregister(&self, run_id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.runs.remove(run_id);
+        self.save(&inner);
+    }
+
+    fn save(&self, inner: &Inner) {
+        let mut runs: Vec<&RunInfo> = inner.runs.values().collect();
+        runs.sort_by_key(|r| r.registered_at);
+        let result = serde_json::to_string_pretty(&runs)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| store::replace(&self.path, &content).context("Failed to write running jobs file"));
+        if let Err(e) = result {
+            tracing::error!(component = "scheduler", error = %e, "Failed to save running jobs");
+        }
+    }
+}
+
+/// Read the running jobs snapshot written by the daemon.
+pub fn load(config: &DemonConfig) -> Result<Vec<RunInfo>> {
+    let path = config.paths.running_file();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(&path).context("Failed to read running jobs file")?;
+    serde_json::from_str(&content).context("Failed to parse running jobs file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathsConfig;
+
+    fn temp_config(max_concurrent_jobs: usize) -> DemonConfig {
+        let dir = std::env::temp_dir().join(format!("demon-running-{}", uuid::Uuid::new_v4()));
+        let mut config = DemonConfig {
+            paths: PathsConfig {
+                base_dir: Some(dir.to_string_lossy().to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        config.scheduler.max_concurrent_jobs = max_concurrent_jobs;
+        config
+    }
+
+    fn job(id: &str, overlap: &str) -> Job {
+        toml::from_str(&format!(
+            "id = \"{id}\"\nname = \"{id}\"\nschedule = \"0 * * * * *\"\nprompt = \"hi\"\noverlap = \"{overlap}\""
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_skip_while_previous_run_holds_slot() {
+        let config = temp_config(0);
+        let registry = RunningJobs::new(&config, Shutdown::new());
+        let job = job("a", "skip");
+
+        let first = registry.acquire(&job, "run-1").await.unwrap();
+        assert!(registry.acquire(&job, "run-2").await.is_none());
+        assert_eq!(load(&config).unwrap().len(), 1);
+
+        drop(first);
+        assert!(load(&config).unwrap().is_empty());
+        assert!(registry.acquire(&job, "run-3").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_kill_previous_cancels_running_run() {
+        let config = temp_config(0);
+        let registry = RunningJobs::new(&config, Shutdown::new());
+        let job = job("a", "kill-previous");
+
+        let mut first = registry.acquire(&job, "run-1").await.unwrap();
+        let second = tokio::spawn({
+            let registry = registry.clone();
+            async move { registry.acquire(&job, "run-2").await.is_some() }
+        });
+
+        first.cancelled().await;
+        drop(first);
+        assert!(second.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_global_limit_queues_runs() {
+        let config = temp_config(1);
+        let registry = RunningJobs::new(&config, Shutdown::new());
+
+        let first = registry.acquire(&job("a", "allow"), "run-1").await.unwrap();
+        let waiting = tokio::spawn({
+            let registry = registry.clone();
+            async move { registry.acquire(&job("b", "allow"), "run-2").await.is_some() }
+        });
+        tokio::task::yield_now().await;
+
+        let runs = load(&config).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert!(runs.iter().any(|r| r.job_id == "b" && r.state == RunState::Waiting));
+
+        drop(first);
+        assert!(waiting.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_released_permit_lets_other_runs_start() {
+        let config = temp_config(1);
+        let registry = RunningJobs::new(&config, Shutdown::new());
+
+        let mut first = registry.acquire(&job("a", "allow"), "run-1").await.unwrap();
+        first.release_permit();
+        let second = registry.acquire(&job("b", "allow"), "run-2").await.unwrap();
+        assert!(load(&config)
+            .unwrap()
+            .iter()
+            .any(|r| r.job_id == "a" && r.state == RunState::Waiting));
+
+        let reacquire = tokio::spawn(async move { first.reacquire_permit().await });
+        tokio::task::yield_now().await;
+        assert!(!reacquire.is_finished());
+        drop(second);
+        reacquire.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_limit_resizes_for_waiting_runs() {
+        use tokio::time::{timeout, Duration};
+
+        let config = temp_config(1);
+        let registry = RunningJobs::new(&config, Shutdown::new());
+        let acquire = |id: &'static str| {
+            let registry = registry.clone();
+            tokio::spawn(async move { registry.acquire(&job(id, "allow"), id).await.unwrap() })
+        };
+
+        let first = acquire("a").await.unwrap();
+        let second = acquire("b");
+        tokio::task::yield_now().await;
+        assert!(!second.is_finished());
+
+        // Raising the limit lets the waiting run start
+        registry.set_limit(2);
+        let second = timeout(Duration::from_secs(1), second).await.unwrap().unwrap();
+
+        // Lowering it holds new runs back until both running ones finish
+        registry.set_limit(1);
+        let third = acquire("c");
+        drop(first);
+        tokio::task::yield_now().await;
+        assert!(!third.is_finished());
+        drop(second);
+        timeout(Duration::from_secs(1), third).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_limit_counts_runs_in_flight() {
+        use tokio::time::{timeout, Duration};
+
+        let config = temp_config(2);
+        let registry = RunningJobs::new(&config, Shutdown::new());
+        let acquire = |id: &'static str| {
+            let registry = registry.clone();
+            tokio::spawn(async move { registry.acquire(&job(id, "allow"), id).await.unwrap() })
+        };
+
+        // Lowering and raising again restores the full limit
+        let first = acquire("a").await.unwrap();
+        registry.set_limit(1);
+        registry.set_limit(3);
+        let second = acquire("b").await.unwrap();
+        let third = timeout(Duration::from_secs(1), acquire("c")).await.unwrap().unwrap();
+        let fourth = acquire("d");
+        tokio::task::yield_now().await;
+        assert!(!fourth.is_finished());
+
+        // Going unlimited and back still counts the runs in flight
+        registry.set_limit(0);
+        let fourth = timeout(Duration::from_secs(1), fourth).await.unwrap().unwrap();
+        registry.set_limit(3);
+        let fifth = acquire("e");
+        drop(first);
+        tokio::task::yield_now().await;
+        assert!(!fifth.is_finished());
+        drop(second);
+        timeout(Duration::from_secs(1), fifth).await.unwrap().unwrap();
+        drop((third, fourth));
+    }
+}
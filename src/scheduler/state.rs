@@ -0,0 +1,56 @@
+//! Persisted scheduler state.
+//!
+//! Records the last scheduled instant each job fired (or was deliberately
+//! skipped) at, so missed runs can be detected across daemon restarts and
+//! system sleep.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::config::DemonConfig;
+use crate::store;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SchedulerState {
+    #[serde(default)]
+    last_fired: HashMap<String, DateTime<Utc>>,
+}
+
+impl SchedulerState {
+    /// Load state from disk, starting empty if the file is missing.
+    pub fn load(config: &DemonConfig) -> Result<Self> {
+        let path = config.paths.scheduler_state_file();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content =
+            std::fs::read_to_string(&path).context("Failed to read scheduler state file")?;
+        serde_json::from_str(&content).context("Failed to parse scheduler state file")
+    }
+
+    pub fn save(&self, config: &DemonConfig) -> Result<()> {
+        let path = config.paths.scheduler_state_file();
+        let content = serde_json::to_string_pretty(self)?;
+        store::replace(&path, &content).context("Failed to write scheduler state file")
+    }
+
+    pub fn last_fired(&self, job_id: &str) -> Option<DateTime<Utc>> {
+        self.last_fired.get(job_id).copied()
+    }
+
+    /// Record that a job fired at `at`. Never moves the timestamp backwards.
+    pub fn mark_fired(&mut self, job_id: &str, at: DateTime<Utc>) {
+        let entry = self.last_fired.entry(job_id.to_string()).or_insert(at);
+        if at > *entry {
+            *entry = at;
+        }
+    }
+
+    /// Drop entries for jobs that no longer exist.
+    pub fn retain_jobs<'a>(&mut self, job_ids: impl IntoIterator<Item = &'a str>) {
+        let ids: std::collections::HashSet<&str> = job_ids.into_iter().collect();
+        self.last_fired.retain(|id, _| ids.contains(id.as_str()));
+    }
+}
@@ -6,11 +6,11 @@
 use anyhow::{Context, Result};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
 use tokio::time::interval;
 
 use super::tmux::TmuxSession;
-use super::{ClaudeSession, MessageRequest, SessionConfig};
+use super::{ClaudeSession, Image, MessageRequest, Reply, SessionConfig};
 
 /// Manages a persistent Claude Code session.
 ///
@@ -20,8 +20,7 @@ pub struct SessionManager {
     session: Arc<Mutex<TmuxSession>>,
     request_tx: mpsc::Sender<MessageRequest>,
     config: SessionConfig,
-    #[allow(dead_code)]
-    shutdown_tx: Option<mpsc::Sender<()>>,
+    shutdown_tx: watch::Sender<bool>,
 }
 
 impl SessionManager {
@@ -47,20 +46,20 @@ impl SessionManager {
         // Create message queue (bounded to prevent memory exhaustion)
         let (request_tx, request_rx) = mpsc::channel::<MessageRequest>(100);
 
-        // Create shutdown channel
-        let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+        // Create shutdown signal shared by the background tasks
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
         let manager = Self {
             session: session.clone(),
             request_tx,
             config: config.clone(),
-            shutdown_tx: Some(shutdown_tx),
+            shutdown_tx,
         };
 
         // Spawn background tasks
-        manager.spawn_worker_loop(request_rx);
-        manager.spawn_compaction_task(shutdown_rx);
-        manager.spawn_health_monitor(config.clone());
+        manager.spawn_worker_loop(request_rx, shutdown_rx.clone());
+        manager.spawn_compaction_task(shutdown_rx.clone());
+        manager.spawn_health_monitor(config.clone(), shutdown_rx);
 
         tracing::info!("SessionManager initialized successfully");
         Ok(manager)
@@ -69,11 +68,17 @@ impl SessionManager {
     /// Send a message to Claude and wait for the response.
     ///
     /// Messages are queued and processed sequentially.
-    pub async fn send_message(&self, prompt: &str) -> Result<String> {
+    pub async fn send_message(&self, prompt: &str) -> Result<Reply> {
+        self.send_message_with_images(prompt, Vec::new()).await
+    }
+
+    /// Like `send_message`, with images attached to the message.
+    pub async fn send_message_with_images(&self, prompt: &str, images: Vec<Image>) -> Result<Reply> {
         let (response_tx, response_rx) = oneshot::channel();
 
         let request = MessageRequest {
             prompt: prompt.to_string(),
+            images,
             response_tx,
         };
 
@@ -88,29 +93,51 @@ impl SessionManager {
     }
 
     /// Spawn the worker loop that processes messages sequentially.
-    fn spawn_worker_loop(&self, mut request_rx: mpsc::Receiver<MessageRequest>) {
+    ///
+    /// On shutdown the message in progress is abandoned so the session lock
+    /// is released, and queued messages are answered with an error.
+    fn spawn_worker_loop(
+        &self,
+        mut request_rx: mpsc::Receiver<MessageRequest>,
+        mut shutdown_rx: watch::Receiver<bool>,
+    ) {
         let session = self.session.clone();
 
         tokio::spawn(async move {
             tracing::info!("Worker loop started");
 
-            while let Some(request) = request_rx.recv().await {
+            loop {
+                let request = tokio::select! {
+                    request = request_rx.recv() => match request {
+                        Some(request) => request,
+                        None => break,
+                    },
+                    _ = shutdown_rx.changed() => break,
+                };
                 tracing::debug!("Processing message: {}...", &request.prompt[..request.prompt.len().min(50)]);
 
                 let session_guard = session.lock().await;
-                let result = session_guard.send_message(&request.prompt).await;
+                let result = tokio::select! {
+                    result = session_guard.send_message(&request.prompt, &request.images) => result,
+                    _ = shutdown_rx.changed() => Err(anyhow::anyhow!("Session shut down")),
+                };
                 drop(session_guard);
 
                 // Send result back (ignore error if receiver dropped)
                 let _ = request.response_tx.send(result);
             }
 
+            request_rx.close();
+            while let Some(request) = request_rx.recv().await {
+                let _ = request.response_tx.send(Err(anyhow::anyhow!("Session shut down")));
+            }
+
             tracing::info!("Worker loop ended");
         });
     }
 
     /// Spawn the compaction task that runs periodically.
-    fn spawn_compaction_task(&self, mut shutdown_rx: mpsc::Receiver<()>) {
+    fn spawn_compaction_task(&self, mut shutdown_rx: watch::Receiver<bool>) {
         let session = self.session.clone();
         let compact_interval = Duration::from_secs(self.config.compact_interval_secs);
 
@@ -136,7 +163,7 @@ impl SessionManager {
                         }
                         drop(session_guard);
                     }
-                    _ = shutdown_rx.recv() => {
+                    _ = shutdown_rx.changed() => {
                         tracing::info!("Compaction task shutting down");
                         break;
                     }
@@ -146,7 +173,7 @@ impl SessionManager {
     }
 
     /// Spawn the health monitor that checks session liveness and auto-restarts.
-    fn spawn_health_monitor(&self, config: SessionConfig) {
+    fn spawn_health_monitor(&self, config: SessionConfig, mut shutdown_rx: watch::Receiver<bool>) {
         let session = self.session.clone();
         let max_restart_attempts = config.max_restart_attempts;
 
@@ -157,7 +184,13 @@ impl SessionManager {
             let mut consecutive_failures = 0u32;
 
             loop {
-                ticker.tick().await;
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = shutdown_rx.changed() => {
+                        tracing::info!("Health monitor shutting down");
+                        break;
+                    }
+                }
 
                 let session_guard = session.lock().await;
                 let is_alive = session_guard.is_alive().await;
@@ -201,18 +234,18 @@ impl SessionManager {
     }
 
     /// Gracefully shutdown the session manager.
-    #[allow(dead_code)]
-    pub async fn shutdown(&mut self) -> Result<()> {
+    ///
+    /// Stops the compaction and health monitor tasks, then kills the
+    /// Claude process and its tmux session.
+    pub async fn shutdown(&self) -> Result<()> {
         tracing::info!("Shutting down SessionManager");
 
         // Signal shutdown to background tasks
-        if let Some(tx) = self.shutdown_tx.take() {
-            let _ = tx.send(()).await;
-        }
+        let _ = self.shutdown_tx.send(true);
 
-        // Kill the tmux session
+        // Kill the Claude process and tmux session
         let session = self.session.lock().await;
-        let _ = session.restart().await; // This will kill the session
+        session.kill_process().await?;
 
         Ok(())
     }
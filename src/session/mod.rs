@@ -13,12 +13,15 @@ use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use tokio::sync::oneshot;
 
+use crate::usage::Usage;
+
 /// Trait for Claude session backends.
 /// Abstracts session operations for testability and future extensibility.
 #[async_trait::async_trait]
 pub trait ClaudeSession: Send + Sync {
-    /// Send a message to Claude and wait for the response.
-    async fn send_message(&self, msg: &str) -> Result<String>;
+    /// Send a message, with any images attached, to Claude and wait for
+    /// the response.
+    async fn send_message(&self, msg: &str, images: &[Image]) -> Result<Reply>;
 
     /// Check if the session is still alive.
     async fn is_alive(&self) -> bool;
@@ -30,14 +33,31 @@ pub trait ClaudeSession: Send + Sync {
     async fn compact(&self) -> Result<()>;
 }
 
+/// Claude's response to a message.
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub text: String,
+    /// Cost and tokens from the `result` message, when reported
+    pub usage: Option<Usage>,
+}
+
+/// An image attached to a message, sent to Claude as an image content block.
+#[derive(Debug, Clone)]
+pub struct Image {
+    /// e.g. "image/png"
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
 /// Request sent through the message queue.
 pub struct MessageRequest {
     pub prompt: String,
-    pub response_tx: oneshot::Sender<Result<String>>,
+    pub images: Vec<Image>,
+    pub response_tx: oneshot::Sender<Result<Reply>>,
 }
 
 /// Configuration for persistent session behavior.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SessionConfig {
     /// Tmux session name (default: "cc-demon-session")
     #[serde(default = "default_session_name")]
@@ -4,13 +4,15 @@
 //! using stream-json for reliable I/O instead of TUI parsing.
 
 use anyhow::{Context, Result};
+use base64::Engine;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{Child, Command};
 use tokio::sync::Mutex;
 
-use super::{ClaudeSession, SessionConfig};
+use super::{ClaudeSession, Image, Reply, SessionConfig};
+use crate::usage::Usage;
 
 /// A Claude session running inside a tmux pane with stream-json I/O.
 pub struct TmuxSession {
@@ -123,9 +125,7 @@ impl TmuxSession {
                 "-y",
                 "50",
                 "echo",
-                &format!(
-                    "Claude stream-json session active. Process managed by cc-demon."
-                ),
+                "Claude stream-json session active. Process managed by cc-demon.",
             ])
             .output()
             .await;
@@ -280,7 +280,7 @@ impl TmuxSession {
     }
 
     /// Read JSON messages from Claude until we get a result.
-    async fn read_until_result(&self, timeout: Duration) -> Result<String> {
+    async fn read_until_result(&self, timeout: Duration) -> Result<Reply> {
         let start = Instant::now();
 
         let mut process_guard = self.process.lock().await;
@@ -315,9 +315,13 @@ impl TmuxSession {
 
                         match msg_type {
                             Some("result") => {
-                                // Final result message
+                                // Final result message, with the cost of the turn
+                                let usage = Usage::from_result(&json);
                                 if let Some(result) = json.get("result").and_then(|v| v.as_str()) {
-                                    return Ok(result.to_string());
+                                    return Ok(Reply {
+                                        text: result.to_string(),
+                                        usage,
+                                    });
                                 }
                                 // Check for error
                                 if json.get("is_error").and_then(|v| v.as_bool()) == Some(true) {
@@ -327,7 +331,10 @@ impl TmuxSession {
                                         .unwrap_or("Unknown error");
                                     anyhow::bail!("Claude error: {}", error);
                                 }
-                                return Ok(result_text);
+                                return Ok(Reply {
+                                    text: result_text,
+                                    usage,
+                                });
                             }
                             Some("assistant") => {
                                 // Assistant message - extract text content
@@ -389,7 +396,7 @@ impl TmuxSession {
     }
 
     /// Kill the current process and clean up.
-    async fn kill_process(&self) -> Result<()> {
+    pub async fn kill_process(&self) -> Result<()> {
         let mut process_guard = self.process.lock().await;
         if let Some(ref mut process) = *process_guard {
             let _ = process.child.kill().await;
@@ -410,31 +417,52 @@ impl TmuxSession {
     }
 }
 
+/// A user message in stream-json format. Images go before the text as
+/// base64 image content blocks.
+fn user_message(msg: &str, images: &[Image]) -> serde_json::Value {
+    let content = if images.is_empty() {
+        serde_json::json!(msg)
+    } else {
+        let mut blocks: Vec<serde_json::Value> = images
+            .iter()
+            .map(|image| {
+                serde_json::json!({
+                    "type": "image",
+                    "source": {
+                        "type": "base64",
+                        "media_type": image.media_type,
+                        "data": base64::engine::general_purpose::STANDARD.encode(&image.data)
+                    }
+                })
+            })
+            .collect();
+        blocks.push(serde_json::json!({ "type": "text", "text": msg }));
+        serde_json::Value::Array(blocks)
+    };
+
+    serde_json::json!({
+        "type": "user",
+        "message": {
+            "role": "user",
+            "content": content
+        }
+    })
+}
+
 #[async_trait::async_trait]
 impl ClaudeSession for TmuxSession {
-    async fn send_message(&self, msg: &str) -> Result<String> {
+    async fn send_message(&self, msg: &str, images: &[Image]) -> Result<Reply> {
         // Ensure process is alive
         if !self.process_alive().await {
             anyhow::bail!("Claude process is not alive");
         }
 
-        // Build user message in stream-json format
-        let user_msg = serde_json::json!({
-            "type": "user",
-            "message": {
-                "role": "user",
-                "content": msg
-            }
-        });
-
         // Send the message
-        self.send_json(&user_msg).await?;
+        self.send_json(&user_message(msg, images)).await?;
 
         // Wait for response
         let timeout = Duration::from_secs(self.config.response_timeout_secs);
-        let response = self.read_until_result(timeout).await?;
-
-        Ok(response)
+        self.read_until_result(timeout).await
     }
 
     async fn is_alive(&self) -> bool {
@@ -457,7 +485,7 @@ impl ClaudeSession for TmuxSession {
     async fn compact(&self) -> Result<()> {
         tracing::info!("Running /compact on session '{}'", self.config.session_name);
         // Compact is just a special message
-        self.send_message("/compact").await?;
+        self.send_message("/compact", &[]).await?;
         Ok(())
     }
 }
@@ -466,6 +494,22 @@ impl ClaudeSession for TmuxSession {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_user_message_with_images() {
+        let image = Image {
+            media_type: "image/png".to_string(),
+            data: b"png".to_vec(),
+        };
+        let msg = user_message("what is this?", &[image]);
+        let content = &msg["message"]["content"];
+        assert_eq!(content[0]["type"], "image");
+        assert_eq!(content[0]["source"]["media_type"], "image/png");
+        assert_eq!(content[0]["source"]["data"], "cG5n");
+        assert_eq!(content[1], serde_json::json!({ "type": "text", "text": "what is this?" }));
+
+        assert_eq!(user_message("hello", &[])["message"]["content"], "hello");
+    }
+
     #[test]
     fn test_json_format() {
         let msg = serde_json::json!({
@@ -0,0 +1,144 @@
+//! Locked, atomic writes for the configuration and state files.
+//!
+//! config.toml, jobs.toml, agents.toml and tasks.toml are changed by CLI
+//! commands and by the daemon itself (e.g. disabling a one-shot job).
+//! Every change is a read-modify-write done under an exclusive advisory
+//! lock on `<file>.lock`, so concurrent writers can't lose each other's
+//! changes. The daemon's state files (scheduler state, running jobs,
+//! run_if fingerprints, last results) are written the same way. The new contents go to `<file>.tmp` and are renamed over the
+//! original, so readers never see a half-written file, and the previous
+//! version is kept as `<file>.bak`.
+
+use anyhow::{Context, Result};
+use nix::fcntl::{Flock, FlockArg};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Exclusive lock on a file, released when dropped.
+pub struct FileLock {
+    _lock: Flock<File>,
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Path of the backup kept by `write_atomic`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    sibling(path, ".bak")
+}
+
+/// Take the exclusive lock for `path`, waiting while another process
+/// holds it. The lock is not re-entrant: don't take it twice for the same
+/// file in one process.
+pub fn lock(path: &Path) -> Result<FileLock> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create directory")?;
+    }
+    let lock_path = sibling(path, ".lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .context(format!("Failed to open lock file {}", lock_path.display()))?;
+    let lock = Flock::lock(file, FlockArg::LockExclusive)
+        .map_err(|(_, errno)| errno)
+        .context(format!("Failed to lock {}", path.display()))?;
+    Ok(FileLock { _lock: lock })
+}
+
+/// Run `f` while holding the lock for `path`.
+pub fn locked<R>(path: &Path, f: impl FnOnce() -> Result<R>) -> Result<R> {
+    let _lock = lock(path)?;
+    f()
+}
+
+/// `write_atomic` under the lock for `path`, for files that are replaced
+/// rather than read-modify-written.
+pub fn replace(path: &Path, content: &str) -> Result<()> {
+    locked(path, || write_atomic(path, content))
+}
+
+/// Replace the contents of `path`: write a temporary file next to it,
+/// flush it to disk, back up the current file and rename the temporary
+/// file into place. The file's permissions are kept. Callers should hold
+/// the lock for `path`.
+pub fn write_atomic(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create directory")?;
+    }
+    let tmp_path = sibling(path, ".tmp");
+    let existing = fs::metadata(path).ok();
+
+    let mut tmp = File::create(&tmp_path)
+        .context(format!("Failed to create {}", tmp_path.display()))?;
+    tmp.write_all(content.as_bytes())
+        .and_then(|_| tmp.sync_all())
+        .context(format!("Failed to write {}", tmp_path.display()))?;
+    if let Some(meta) = &existing {
+        fs::set_permissions(&tmp_path, meta.permissions())
+            .context("Failed to copy file permissions")?;
+        fs::copy(path, backup_path(path)).context(format!("Failed to back up {}", path.display()))?;
+    }
+
+    fs::rename(&tmp_path, path).context(format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("demon-store-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_write_atomic_keeps_backup() {
+        let dir = temp_dir();
+        let path = dir.join("jobs.toml");
+
+        locked(&path, || write_atomic(&path, "first")).unwrap();
+        assert!(!backup_path(&path).exists());
+        locked(&path, || write_atomic(&path, "second")).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "first");
+        assert!(!sibling(&path, ".tmp").exists());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_lock_serializes_updates() {
+        let dir = temp_dir();
+        let path = dir.join("counter");
+        fs::write(&path, "0").unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    locked(&path, || {
+                        let n: u32 = fs::read_to_string(&path)?.parse()?;
+                        std::thread::sleep(std::time::Duration::from_millis(5));
+                        write_atomic(&path, &(n + 1).to_string())
+                    })
+                    .unwrap();
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "8");
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -13,8 +13,11 @@ use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use crate::config::DemonConfig;
+use crate::config::{DemonConfig, PathsConfig};
+use crate::process::{self, ProcessError};
 use crate::session::SessionManager;
+use crate::template::TemplateContext;
+use crate::usage::{self, Usage, UsageEntry};
 
 /// Agent execution profile (from agents.toml)
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,6 +43,9 @@ pub struct AgentProfile {
     pub append_system_prompt: String,
     #[serde(default)]
     pub mcp_config: String,
+    /// Kill the task if it takes longer than this (no limit when unset)
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
 }
 
 /// Task trigger definition (from tasks.toml)
@@ -58,15 +64,15 @@ pub struct TaskDefinition {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct AgentsFile {
+pub(crate) struct AgentsFile {
     #[serde(default)]
-    agents: Vec<AgentProfile>,
+    pub(crate) agents: Vec<AgentProfile>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct TasksFile {
+pub(crate) struct TasksFile {
     #[serde(default)]
-    tasks: Vec<TaskDefinition>,
+    pub(crate) tasks: Vec<TaskDefinition>,
 }
 
 fn default_model() -> String {
@@ -82,7 +88,7 @@ fn default_max_budget() -> f64 {
 }
 
 fn default_output_file() -> String {
-    "{home}/.demon/task-outputs/{agent}/{date}_{time}_{task}.md".to_string()
+    "{base_dir}/task-outputs/{agent}/{date}_{time}_{task}.md".to_string()
 }
 
 fn default_true() -> bool {
@@ -121,41 +127,28 @@ pub fn load_tasks(config: &DemonConfig) -> Result<Vec<TaskDefinition>> {
     Ok(file.tasks)
 }
 
-/// Expand path template variables: {home}, {agent}, {task}, {date}, {time}
+/// Expand path template variables: {home}, {base_dir}, {agent}, {task},
+/// {date}, {time}, {now_iso} and {env:NAME}, plus a leading `~/`.
 pub fn expand_path_template(
     template: &str,
     task_id: &str,
     agent_id: &str,
+    paths: &PathsConfig,
 ) -> PathBuf {
-    let home = dirs::home_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .to_string_lossy()
-        .to_string();
-    let now = Local::now();
-
-    let expanded = template
-        .replace("{home}", &home)
-        .replace("{agent}", agent_id)
-        .replace("{task}", task_id)
-        .replace("{date}", &now.format("%Y-%m-%d").to_string())
-        .replace("{time}", &now.format("%H-%M-%S").to_string());
-
-    // Also expand ~ at the start
-    let expanded = if expanded.starts_with("~/") {
-        expanded.replacen("~", &home, 1)
-    } else {
-        expanded
-    };
-
-    PathBuf::from(expanded)
+    let mut ctx = TemplateContext::new(paths);
+    ctx.set("agent", agent_id).set("task", task_id);
+    ctx.render_path(template)
 }
 
 /// Classify a message to find matching task using keyword matching first,
 /// then LLM classification if no keyword match.
+/// The cost of LLM classification is charged to `chat_id`.
 pub async fn classify_message(
     message: &str,
     tasks: &[TaskDefinition],
     session_manager: Option<&Arc<SessionManager>>,
+    config: &DemonConfig,
+    chat_id: i64,
 ) -> Result<Option<TaskDefinition>> {
     let enabled_tasks: Vec<_> = tasks.iter().filter(|t| t.enabled).collect();
 
@@ -207,8 +200,11 @@ Task ID:"#,
         );
 
         match session_mgr.send_message(&classification_prompt).await {
-            Ok(response) => {
-                let task_id = response.trim().to_lowercase();
+            Ok(reply) => {
+                if let Some(spent) = reply.usage {
+                    usage::record(config, UsageEntry::gateway(chat_id, spent));
+                }
+                let task_id = reply.text.trim().to_lowercase();
 
                 if task_id == "none" || task_id.is_empty() {
                     eprintln!("[demon] LLM classification returned 'none'");
@@ -234,11 +230,13 @@ Task ID:"#,
 
 /// Execute a task using the specified agent profile.
 /// Spawns a new claude -p process with agent configuration.
+/// Its cost is recorded against the task and, if given, the chat.
 pub async fn execute_task(
     task: &TaskDefinition,
     agent: &AgentProfile,
     message: &str,
-    _config: &DemonConfig,
+    config: &DemonConfig,
+    chat_id: Option<i64>,
 ) -> Result<String> {
     tracing::info!(
         "Executing task '{}' with agent '{}': {}",
@@ -304,7 +302,7 @@ pub async fn execute_task(
     cmd.arg("--no-session-persistence");
 
     // Working directory - expand ~ and template variables
-    let working_dir = expand_path_template(&agent.working_dir, &task.id, &agent.id);
+    let working_dir = expand_path_template(&agent.working_dir, &task.id, &agent.id, &config.paths);
     if working_dir.exists() {
         cmd.current_dir(&working_dir);
     } else {
@@ -317,12 +315,15 @@ pub async fn execute_task(
     // The user message becomes the prompt
     cmd.arg(message);
 
-    // Set up I/O
-    cmd.stdout(std::process::Stdio::piped());
-    cmd.stderr(std::process::Stdio::piped());
-
-    // Spawn and wait
-    let output = cmd.output().await.context("Failed to spawn claude CLI")?;
+    // Spawn and wait, killing claude's process group on timeout
+    let timeout = agent.timeout_secs.map(tokio::time::Duration::from_secs);
+    let output = match process::output_with_timeout(&mut cmd, timeout).await {
+        Ok(output) => output,
+        Err(ProcessError::TimedOut { secs }) => {
+            anyhow::bail!("Task '{}' timed out after {secs}s", task.id)
+        }
+        Err(ProcessError::Io(e)) => return Err(e).context("Failed to spawn claude CLI"),
+    };
 
     let stdout = String::from_utf8_lossy(&output.stdout);
     let stderr = String::from_utf8_lossy(&output.stderr);
@@ -331,6 +332,10 @@ pub async fn execute_task(
         eprintln!("[demon] claude stderr: {}", stderr);
     }
 
+    if let Some(spent) = Usage::parse(&stdout) {
+        usage::record(config, UsageEntry::task(&task.id, chat_id, spent));
+    }
+
     if output.status.success() {
         // Parse JSON to extract result
         match serde_json::from_str::<serde_json::Value>(&stdout) {
@@ -358,8 +363,9 @@ pub fn save_response(
     task: &TaskDefinition,
     agent: &AgentProfile,
     response: &str,
+    config: &DemonConfig,
 ) -> Result<PathBuf> {
-    let filepath = expand_path_template(&task.output_file, &task.id, &agent.id);
+    let filepath = expand_path_template(&task.output_file, &task.id, &agent.id, &config.paths);
 
     // Create parent directories
     if let Some(parent) = filepath.parent() {
@@ -389,6 +395,7 @@ pub async fn classify_and_execute(
     message: &str,
     config: &DemonConfig,
     session_manager: Option<&Arc<SessionManager>>,
+    chat_id: i64,
 ) -> Result<Option<String>> {
     // Load configs
     let tasks = load_tasks(config)?;
@@ -400,7 +407,7 @@ pub async fn classify_and_execute(
     }
 
     // Classify
-    let task = match classify_message(message, &tasks, session_manager).await? {
+    let task = match classify_message(message, &tasks, session_manager, config, chat_id).await? {
         Some(t) => t,
         None => {
             eprintln!("[demon] No matching task found, falling back to gateway");
@@ -418,10 +425,10 @@ pub async fn classify_and_execute(
         ))?;
 
     // Execute
-    let response = execute_task(&task, agent, message, config).await?;
+    let response = execute_task(&task, agent, message, config, Some(chat_id)).await?;
 
     // Save to file
-    if let Err(e) = save_response(&task, agent, &response) {
+    if let Err(e) = save_response(&task, agent, &response, config) {
         eprintln!("[demon] Warning: failed to save response: {}", e);
     }
 
@@ -453,10 +460,10 @@ pub async fn run_task_by_name(
         ))?;
 
     // Execute
-    let response = execute_task(task, agent, message, config).await?;
+    let response = execute_task(task, agent, message, config, None).await?;
 
     // Save to file
-    if let Err(e) = save_response(task, agent, &response) {
+    if let Err(e) = save_response(task, agent, &response, config) {
         eprintln!("[demon] Warning: failed to save response: {}", e);
     }
 
@@ -469,21 +476,26 @@ mod tests {
 
     #[test]
     fn test_expand_path_template() {
+        let paths = PathsConfig {
+            base_dir: Some("/srv/demon".to_string()),
+            ..Default::default()
+        };
         let path = expand_path_template(
-            "{home}/.demon/output/{agent}/{date}_{task}.md",
+            "{base_dir}/output/{agent}/{date}_{task}.md",
             "my-task",
             "my-agent",
+            &paths,
         );
         let path_str = path.to_string_lossy();
 
         assert!(path_str.contains("my-agent"));
         assert!(path_str.contains("my-task"));
-        assert!(path_str.contains(".demon/output"));
+        assert!(path_str.starts_with("/srv/demon/output"));
     }
 
     #[test]
     fn test_expand_tilde() {
-        let path = expand_path_template("~/projects/{agent}", "task", "agent");
+        let path = expand_path_template("~/projects/{agent}", "task", "agent", &PathsConfig::default());
         let path_str = path.to_string_lossy();
 
         // Should not start with ~/
@@ -0,0 +1,267 @@
+//! Template expansion for prompts and paths.
+//!
+//! Placeholders are written `{name}`; `{env:NAME}` expands to an
+//! environment variable (empty when unset). Expansion is a single pass, so
+//! braces inside substituted values are never expanded, and placeholders
+//! that aren't known variables (e.g. JSON in a prompt) are left as-is.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::config::{DemonConfig, Job, PathsConfig};
+use crate::history::{self, RunStatus};
+
+/// Value of `{last_run_at}` / `{last_success_at}` before the first run.
+const NEVER: &str = "never";
+
+#[derive(Debug, Clone, Default)]
+pub struct TemplateContext {
+    vars: HashMap<String, String>,
+}
+
+impl TemplateContext {
+    /// A context with `{home}`, `{base_dir}` (the demon directory of
+    /// `paths`), and `{date}`, `{time}` and `{now_iso}` set from the current
+    /// local time.
+    pub fn new(paths: &PathsConfig) -> Self {
+        Self::at(Local::now(), paths)
+    }
+
+    /// Like `new`, with the time variables taken from `now`.
+    pub fn at(now: DateTime<Local>, paths: &PathsConfig) -> Self {
+        let home = dirs::home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .to_string_lossy()
+            .to_string();
+        let mut ctx = Self::default();
+        ctx.set("home", home)
+            .set("base_dir", paths.base_dir().to_string_lossy())
+            .set("date", now.format("%Y-%m-%d").to_string())
+            .set("time", now.format("%H-%M-%S").to_string())
+            .set("now_iso", now.to_rfc3339());
+        ctx
+    }
+
+    /// A context for running `job`: the common variables plus `{job_id}`,
+    /// `{job_name}`, `{last_run_at}`, `{last_success_at}` and
+    /// `{last_result}` from the job's run history.
+    pub fn for_job(job: &Job, config: &DemonConfig) -> Self {
+        let mut ctx = Self::new(&config.paths);
+        ctx.set("job_id", &job.id).set("job_name", &job.name);
+
+        let records = history::load(config, &job.id).unwrap_or_else(|e| {
+            tracing::warn!(job_id = %job.id, error = %e, "Failed to read run history for templating");
+            Vec::new()
+        });
+        let last_run = records.iter().rev().find(|r| r.status != RunStatus::Skipped);
+        let last_success = records.iter().rev().find(|r| r.status == RunStatus::Success);
+        let format = |at: Option<DateTime<chrono::Utc>>| {
+            at.map_or_else(|| NEVER.to_string(), |at| at.with_timezone(&Local).to_rfc3339())
+        };
+        ctx.set("last_run_at", format(last_run.map(|r| r.started_at)))
+            .set("last_success_at", format(last_success.map(|r| r.started_at)))
+            .set(
+                "last_result",
+                history::last_result(config, &job.id).unwrap_or_default(),
+            );
+        ctx
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<String>) -> &mut Self {
+        self.vars.insert(name.to_string(), value.into());
+        self
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        if let Some(var) = name.strip_prefix("env:") {
+            return Some(std::env::var(var).unwrap_or_default());
+        }
+        self.vars.get(name).cloned()
+    }
+
+    /// Expand all known placeholders in `template`.
+    pub fn render(&self, template: &str) -> String {
+        self.expand(template, false)
+    }
+
+    /// Expand placeholders in a shell command, each value single-quoted so
+    /// it is passed as one word and never run, whatever it contains (e.g.
+    /// `{upstream_result}`, which Claude writes).
+    pub fn render_shell(&self, template: &str) -> String {
+        self.expand(template, true)
+    }
+
+    fn expand(&self, template: &str, quote: bool) -> String {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let value = after
+                .find('}')
+                .and_then(|end| self.lookup(&after[..end]).map(|v| (end, v)));
+            match value {
+                Some((end, value)) if quote => {
+                    out.push_str(&shell_quote(&value));
+                    rest = &after[end + 1..];
+                }
+                Some((end, value)) => {
+                    out.push_str(&value);
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    out.push('{');
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Expand placeholders in a path, plus a leading `~/`.
+    pub fn render_path(&self, template: &str) -> PathBuf {
+        let expanded = self.render(template);
+        match expanded.strip_prefix("~/") {
+            Some(rest) => match dirs::home_dir() {
+                Some(home) => home.join(rest),
+                None => PathBuf::from(expanded),
+            },
+            None => PathBuf::from(expanded),
+        }
+    }
+
+    /// A copy of `job` with its prompt (read from `prompt_file` if set),
+    /// system prompt, working directory, context commands and `run_if`
+    /// expanded. Values in commands are shell-quoted (see `render_shell`).
+    pub fn render_job(&self, job: &Job, config: &DemonConfig) -> Result<Job> {
+        let mut job = job.clone();
+        if let Some(file) = job.prompt_file.take() {
+            let path = config.paths.base_dir().join(self.render_path(&file));
+            let mut prompt = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read prompt file {}", path.display()))?;
+            if !job.prompt.is_empty() {
+                prompt = format!("{}\n\n{}", prompt.trim_end(), job.prompt);
+            }
+            job.prompt = prompt;
+        }
+        job.prompt = self.render(&job.prompt);
+        job.system_prompt = self.render(&job.system_prompt);
+        if !job.working_dir.is_empty() {
+            job.working_dir = self
+                .render_path(&job.working_dir)
+                .to_string_lossy()
+                .to_string();
+        }
+        for cmd in &mut job.context_commands {
+            cmd.command = self.render_shell(&cmd.command);
+        }
+        if let Some(run_if) = &mut job.run_if {
+            run_if.command = run_if.command.as_deref().map(|c| self.render_shell(c));
+            run_if.path = run_if
+                .path
+                .as_deref()
+                .map(|p| self.render_path(p).to_string_lossy().to_string());
+        }
+        Ok(job)
+    }
+}
+
+/// `value` as a single-quoted shell word.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_is_single_pass_and_keeps_unknown() {
+        let mut ctx = TemplateContext::default();
+        ctx.set("job_id", "digest").set("last_result", "{job_id}");
+
+        assert_eq!(
+            ctx.render("{job_id}: {last_result} {unknown} {\"a\": 1}"),
+            "digest: {job_id} {unknown} {\"a\": 1}"
+        );
+        assert_eq!(ctx.render("unclosed {job_id"), "unclosed {job_id");
+    }
+
+    #[test]
+    fn test_render_shell_quotes_values() {
+        let mut ctx = TemplateContext::default();
+        ctx.set("upstream_result", "done'; rm -rf ~; echo '$(id)")
+            .set("date", "2026-03-01");
+        let command = ctx.render_shell("echo {upstream_result} > notes/{date}.md");
+        assert_eq!(
+            command,
+            r#"echo 'done'\''; rm -rf ~; echo '\''$(id)' > notes/'2026-03-01'.md"#
+        );
+
+        #[cfg(unix)]
+        {
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(ctx.render_shell("printf %s {upstream_result}"))
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "done'; rm -rf ~; echo '$(id)");
+        }
+    }
+
+    #[test]
+    fn test_render_env() {
+        let ctx = TemplateContext::default();
+        let path = std::env::var("PATH").unwrap_or_default();
+        assert_eq!(ctx.render("{env:PATH}"), path);
+        assert_eq!(ctx.render("[{env:DEMON_TEST_UNSET_VAR}]"), "[]");
+    }
+
+    #[test]
+    fn test_render_prompt_and_result_text() {
+        let dir = std::env::temp_dir().join(format!("demon-template-{}", uuid::Uuid::new_v4()));
+        let config = DemonConfig {
+            paths: PathsConfig {
+                base_dir: Some(dir.to_string_lossy().to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let job: Job = toml::from_str(
+            "id = \"digest\"\nname = \"Digest\"\ntrigger = \"after\"\ndepends_on = [\"fetch\"]\nprompt = \"Since {last_result}, {upstream_job} found: {upstream_result}\"",
+        )
+        .unwrap();
+        history::save_last_result(&config, "digest", "2 items").unwrap();
+
+        let mut ctx = TemplateContext::for_job(&job, &config);
+        ctx.set("upstream_result", history::result_text(r#"{"type":"result","result":"3 new items"}"#))
+            .set("upstream_job", "fetch");
+        assert_eq!(
+            ctx.render_job(&job, &config).unwrap().prompt,
+            "Since 2 items, fetch found: 3 new items"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_time_variables() {
+        let now = "2026-03-01T09:05:07+07:00"
+            .parse::<DateTime<chrono::FixedOffset>>()
+            .unwrap()
+            .with_timezone(&Local);
+        let paths = PathsConfig {
+            base_dir: Some("/srv/demon".to_string()),
+            ..Default::default()
+        };
+        let ctx = TemplateContext::at(now, &paths);
+        assert_eq!(
+            ctx.render("{date}"),
+            now.format("%Y-%m-%d").to_string()
+        );
+        assert_eq!(ctx.render("{now_iso}"), now.to_rfc3339());
+        assert_eq!(ctx.render("{base_dir}/out"), "/srv/demon/out");
+    }
+}
@@ -0,0 +1,415 @@
+//! Spend ledger and budget caps.
+//!
+//! Every claude invocation that reports a cost (scheduled and manual job
+//! runs, agent tasks and gateway messages) is appended as one JSON line to
+//! ~/.demon/usage.jsonl. Before a job or task runs or a gateway message
+//! is answered, the day's and month's totals from the ledger are checked
+//! against the caps in `[budget]` and the job's own caps.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+use crate::config::{DemonConfig, Job};
+
+/// Cost and token counts claude reported for one invocation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    pub cost_usd: f64,
+    /// Prompt tokens, including cache reads and writes
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+}
+
+impl Usage {
+    /// Read `total_cost_usd` and `usage` from a claude JSON result (the
+    /// `--output-format json` output, or a stream-json `result` message).
+    pub fn from_result(json: &serde_json::Value) -> Option<Self> {
+        let cost_usd = json.get("total_cost_usd").and_then(|v| v.as_f64())?;
+        let tokens = |key: &str| {
+            json.get("usage")
+                .and_then(|u| u.get(key))
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0)
+        };
+        Some(Self {
+            cost_usd,
+            input_tokens: tokens("input_tokens")
+                + tokens("cache_creation_input_tokens")
+                + tokens("cache_read_input_tokens"),
+            output_tokens: tokens("output_tokens"),
+        })
+    }
+
+    /// Parse claude's JSON output. Returns `None` for text output.
+    pub fn parse(output: &str) -> Option<Self> {
+        let json = serde_json::from_str::<serde_json::Value>(output.trim()).ok()?;
+        Self::from_result(&json)
+    }
+}
+
+/// What spent the money.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    Job,
+    Task,
+    Gateway,
+}
+
+impl Source {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Job => "job",
+            Self::Task => "task",
+            Self::Gateway => "gateway",
+        }
+    }
+}
+
+/// A single entry in the spend ledger.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageEntry {
+    pub at: DateTime<Utc>,
+    pub source: Source,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
+    /// The gateway chat the spend is charged to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<i64>,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+impl UsageEntry {
+    pub fn job(job_id: &str, usage: Usage) -> Self {
+        Self {
+            job_id: Some(job_id.to_string()),
+            ..Self::new(Source::Job, usage)
+        }
+    }
+
+    pub fn task(task_id: &str, chat_id: Option<i64>, usage: Usage) -> Self {
+        Self {
+            task_id: Some(task_id.to_string()),
+            chat_id,
+            ..Self::new(Source::Task, usage)
+        }
+    }
+
+    pub fn gateway(chat_id: i64, usage: Usage) -> Self {
+        Self {
+            chat_id: Some(chat_id),
+            ..Self::new(Source::Gateway, usage)
+        }
+    }
+
+    fn new(source: Source, usage: Usage) -> Self {
+        Self {
+            at: Utc::now(),
+            source,
+            job_id: None,
+            task_id: None,
+            chat_id: None,
+            usage,
+        }
+    }
+}
+
+/// Append an entry to the ledger. Failures are logged, never returned:
+/// losing a ledger line must not fail the run that produced it.
+pub fn record(config: &DemonConfig, entry: UsageEntry) {
+    if let Err(e) = append(config, &entry) {
+        tracing::error!(component = "usage", error = %e, "Failed to record usage");
+    }
+}
+
+fn append(config: &DemonConfig, entry: &UsageEntry) -> Result<()> {
+    let path = config.paths.usage_file();
+    fs::create_dir_all(path.parent().unwrap()).context("Failed to create base directory")?;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context("Failed to open usage ledger")?;
+
+    let line = serde_json::to_string(entry)?;
+    writeln!(file, "{line}").context("Failed to write usage entry")?;
+    Ok(())
+}
+
+/// Load ledger entries recorded at or after `since`, oldest first.
+/// Lines that fail to parse are skipped.
+pub fn load_since(config: &DemonConfig, since: DateTime<Utc>) -> Result<Vec<UsageEntry>> {
+    let path = config.paths.usage_file();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let file = fs::File::open(&path).context("Failed to open usage ledger")?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.context("Failed to read usage ledger")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<UsageEntry>(&line) {
+            Ok(entry) if entry.at >= since => entries.push(entry),
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(component = "usage", error = %e, "Skipping malformed usage entry");
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Start of the local calendar day containing `now`.
+pub fn day_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    local_midnight(now.with_timezone(&Local).date_naive())
+}
+
+/// Start of the local calendar month containing `now`.
+pub fn month_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    let today = now.with_timezone(&Local).date_naive();
+    local_midnight(today.with_day(1).unwrap_or(today))
+}
+
+fn local_midnight(date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_time(NaiveTime::MIN);
+    match Local.from_local_datetime(&midnight).earliest() {
+        Some(at) => at.with_timezone(&Utc),
+        // Midnight skipped by a DST change; close enough for accounting
+        None => Utc.from_utc_datetime(&midnight),
+    }
+}
+
+/// Whose caps apply to a check.
+#[derive(Debug, Clone, Copy)]
+pub enum Scope<'a> {
+    /// Global caps and the job's own (or the default per-job) caps
+    Job(&'a Job),
+    /// Global caps and the default per-job caps, for a task run outside
+    /// the gateway
+    Task(&'a str),
+    /// Global caps and the per-chat caps
+    Chat(i64),
+}
+
+struct Cap {
+    limit: f64,
+    since: DateTime<Utc>,
+    period: &'static str,
+    label: String,
+    /// Counts only the scope's own spend rather than everything
+    scoped: bool,
+}
+
+/// Check the caps that apply to `scope`. Returns a description of the
+/// first cap already reached, or `None` if spending may continue.
+pub fn check(config: &DemonConfig, scope: Scope<'_>, now: DateTime<Utc>) -> Result<Option<String>> {
+    let budget = &config.budget;
+    let (day, month) = (day_start(now), month_start(now));
+
+    let mut caps = Vec::new();
+    let mut add = |limit: Option<f64>, daily: bool, label: String, scoped: bool| {
+        if let Some(limit) = limit {
+            let (since, period) = if daily { (day, "today") } else { (month, "this month") };
+            caps.push(Cap { limit, since, period, label, scoped });
+        }
+    };
+    add(budget.daily_usd, true, "Daily budget".to_string(), false);
+    add(budget.monthly_usd, false, "Monthly budget".to_string(), false);
+    match scope {
+        Scope::Job(job) => {
+            let name = format!("job '{}'", job.id);
+            add(
+                job.daily_budget_usd.or(budget.job_daily_usd),
+                true,
+                format!("Daily budget for {name}"),
+                true,
+            );
+            add(
+                job.monthly_budget_usd.or(budget.job_monthly_usd),
+                false,
+                format!("Monthly budget for {name}"),
+                true,
+            );
+        }
+        Scope::Task(task_id) => {
+            let name = format!("task '{task_id}'");
+            add(budget.job_daily_usd, true, format!("Daily budget for {name}"), true);
+            add(budget.job_monthly_usd, false, format!("Monthly budget for {name}"), true);
+        }
+        Scope::Chat(chat_id) => {
+            add(budget.chat_daily_usd, true, format!("Daily budget for chat {chat_id}"), true);
+            add(budget.chat_monthly_usd, false, format!("Monthly budget for chat {chat_id}"), true);
+        }
+    }
+    if caps.is_empty() {
+        return Ok(None);
+    }
+
+    let entries = load_since(config, month.min(day))?;
+    for cap in &caps {
+        let spent: f64 = entries
+            .iter()
+            .filter(|e| e.at >= cap.since && (!cap.scoped || in_scope(e, scope)))
+            .map(|e| e.usage.cost_usd)
+            .sum();
+        if spent >= cap.limit {
+            return Ok(Some(format!(
+                "{} of ${:.2} reached (${:.2} spent {})",
+                cap.label, cap.limit, spent, cap.period
+            )));
+        }
+    }
+    Ok(None)
+}
+
+fn in_scope(entry: &UsageEntry, scope: Scope<'_>) -> bool {
+    match scope {
+        Scope::Job(job) => entry.source == Source::Job && entry.job_id.as_deref() == Some(&job.id),
+        Scope::Task(task_id) => entry.source == Source::Task && entry.task_id.as_deref() == Some(task_id),
+        Scope::Chat(chat_id) => entry.chat_id == Some(chat_id),
+    }
+}
+
+/// Summed spend for a group of ledger entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Totals {
+    pub calls: usize,
+    pub cost_usd: f64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl Totals {
+    fn add(&mut self, usage: &Usage) {
+        self.calls += 1;
+        self.cost_usd += usage.cost_usd;
+        self.input_tokens += usage.input_tokens;
+        self.output_tokens += usage.output_tokens;
+    }
+}
+
+/// Sum entries grouped by `key`; entries for which it returns `None`
+/// are left out.
+pub fn totals_by<K: Ord>(
+    entries: &[UsageEntry],
+    key: impl Fn(&UsageEntry) -> Option<K>,
+) -> BTreeMap<K, Totals> {
+    let mut totals: BTreeMap<K, Totals> = BTreeMap::new();
+    for entry in entries {
+        if let Some(k) = key(entry) {
+            totals.entry(k).or_default().add(&entry.usage);
+        }
+    }
+    totals
+}
+
+/// Sum all entries.
+pub fn total(entries: &[UsageEntry]) -> Totals {
+    totals_by(entries, |_| Some(())).remove(&()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathsConfig;
+
+    fn temp_config() -> DemonConfig {
+        let dir = std::env::temp_dir().join(format!("demon-usage-{}", uuid::Uuid::new_v4()));
+        DemonConfig {
+            paths: PathsConfig {
+                base_dir: Some(dir.to_string_lossy().to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn job(extra: &str) -> Job {
+        toml::from_str(&format!(
+            "id = \"digest\"\nname = \"Digest\"\nschedule = \"0 0 9 * * *\"\nprompt = \"hi\"\n{extra}"
+        ))
+        .unwrap()
+    }
+
+    fn cost(cost_usd: f64) -> Usage {
+        Usage {
+            cost_usd,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_usage() {
+        let output = r#"{"type":"result","result":"ok","total_cost_usd":0.25,
+            "usage":{"input_tokens":10,"cache_read_input_tokens":90,"output_tokens":5}}"#;
+        assert_eq!(
+            Usage::parse(output),
+            Some(Usage {
+                cost_usd: 0.25,
+                input_tokens: 100,
+                output_tokens: 5
+            })
+        );
+        assert_eq!(Usage::parse("plain text"), None);
+    }
+
+    #[test]
+    fn test_job_caps() {
+        let mut config = temp_config();
+        let job = job("daily_budget_usd = 1.0");
+        let now = Utc::now();
+
+        record(&config, UsageEntry::job("digest", cost(0.6)));
+        record(&config, UsageEntry::job("other", cost(5.0)));
+        assert_eq!(check(&config, Scope::Job(&job), now).unwrap(), None);
+
+        record(&config, UsageEntry::job("digest", cost(0.4)));
+        let reason = check(&config, Scope::Job(&job), now).unwrap().unwrap();
+        assert!(reason.contains("job 'digest'"), "{reason}");
+
+        // Tasks run from the CLI get the default per-job caps
+        config.budget.job_daily_usd = Some(0.5);
+        record(&config, UsageEntry::task("review", None, cost(0.5)));
+        assert!(check(&config, Scope::Task("review"), now).unwrap().is_some());
+        assert_eq!(check(&config, Scope::Task("other"), now).unwrap(), None);
+
+        let _ = fs::remove_dir_all(config.paths.base_dir());
+    }
+
+    #[test]
+    fn test_global_and_chat_caps() {
+        let mut config = temp_config();
+        config.budget.monthly_usd = Some(3.0);
+        config.budget.chat_daily_usd = Some(1.0);
+        let now = Utc::now();
+
+        record(&config, UsageEntry::gateway(1, cost(1.5)));
+        record(&config, UsageEntry::task("review", Some(2), cost(0.5)));
+        assert!(check(&config, Scope::Chat(1), now).unwrap().is_some());
+        assert_eq!(check(&config, Scope::Chat(2), now).unwrap(), None);
+
+        record(&config, UsageEntry::job("digest", cost(1.0)));
+        let reason = check(&config, Scope::Chat(3), now).unwrap().unwrap();
+        assert!(reason.starts_with("Monthly budget of $3.00"), "{reason}");
+
+        let entries = load_since(&config, month_start(now)).unwrap();
+        let by_source = totals_by(&entries, |e| Some(e.source));
+        assert_eq!(by_source[&Source::Gateway].cost_usd, 1.5);
+        assert_eq!(total(&entries).calls, 3);
+
+        let _ = fs::remove_dir_all(config.paths.base_dir());
+    }
+}